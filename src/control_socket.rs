@@ -0,0 +1,593 @@
+use crate::{
+    devices_manager::{Device, DeviceState, DeviceStateListener, DeviceType, PortDevices},
+    gamepad::{gamepad_key_map::GamepadKeyMap, retro_gamepad::RetroGamePad},
+    hotkeys::HotkeyAction,
+    input_frame::{InputFrame, JOYPAD_BUTTON_COUNT},
+};
+use retro_ab::{erro_handle::ErroHandle, retro_sys::retro_log_level};
+use serde_json::{json, Map, Value};
+use std::{
+    io::{BufRead, BufReader, ErrorKind, Write},
+    net::Shutdown,
+    os::unix::{
+        fs::FileTypeExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{channel, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, sleep, JoinHandle},
+    time::Duration,
+};
+use uuid::Uuid;
+
+static ACCEPT_SLEEP_TIME: Duration = Duration::from_millis(50);
+
+lazy_static! {
+    static ref SUBSCRIBERS: Mutex<Vec<Sender<String>>> = Mutex::new(Vec::new());
+    static ref FORWARD_LISTENER: Mutex<Option<DeviceStateListener>> = Mutex::new(None);
+}
+
+//estado do DevicesManager que os comandos precisam, o DevicesManager inteiro não é guardado porque
+//ele guarda o próprio ControlServer
+#[derive(Clone)]
+struct ControlDevices {
+    ports: PortDevices,
+    current_frame: Arc<Mutex<(u64, InputFrame)>>,
+}
+
+//a thread de cada cliente fica junto com a conexão para saber quando ela foi encerrada
+#[derive(Debug)]
+struct ControlClient {
+    stream: UnixStream,
+    thread: JoinHandle<()>,
+}
+
+/// # Control socket
+///
+/// Servidor em um Unix domain socket para que outros programas (um app de configuração, scripts
+/// shell com `socat`, etc.) possam consultar e alterar a camada de input do frontend em execução.
+///
+/// Cada requisição e cada resposta é um objeto JSON em uma única linha:
+///
+/// | requisição                                                     | resposta                                   |
+/// |----------------------------------------------------------------|--------------------------------------------|
+/// | `{"cmd":"list_devices"}`                                       | `{"ok":true,"devices":[...]}`              |
+/// | `{"cmd":"get_state"}`                                          | `{"ok":true,"frame":n,"ports":[...],"keyboard":[...]}` |
+/// | `{"cmd":"get_mapping","device":"uuid"}`                        | `{"ok":true,"mapping":[{"button":"B","retro":0}]}` |
+/// | `{"cmd":"set_mapping","device":"uuid","button":"B","retro":8}` | `{"ok":true}`                              |
+/// | `{"cmd":"assign_port","device":"uuid","port":1}`               | `{"ok":true}`                              |
+/// | `{"cmd":"share_port","device":"uuid","port":0}`                | `{"ok":true}`                              |
+/// | `{"cmd":"swap_ports","port":0,"other":1}`                      | `{"ok":true}`                              |
+/// | `{"cmd":"unassign_port","device":"uuid"}`                      | `{"ok":true}`                              |
+/// | `{"cmd":"subscribe"}`                                          | `{"ok":true}` e depois uma linha `{"event":...,"device":{...}}` por evento |
+///
+/// Os nomes dos botões são os mesmos do evento ButtonPressed, em caso de erro a resposta é
+/// `{"ok":false,"error":"..."}`.
+///
+/// O socket não tem regras próprias para as portas: assign_port, share_port, swap_ports e
+/// unassign_port são os mesmos métodos do RetroAbController, então a porta precisa estar livre,
+/// as portas travadas são respeitadas e os eventos PortChanged são emitidos da mesma forma.
+///
+/// Para receber os eventos o servidor se coloca como listener do DevicesManager e repassa cada
+/// evento ao listener anterior, por isso inicie o servidor depois de criar o RetroAbController.
+/// Disponível somente em sistemas unix com a feature `control-socket`.
+#[derive(Debug)]
+pub struct ControlServer {
+    path: PathBuf,
+    running: Arc<Mutex<bool>>,
+    clients: Arc<Mutex<Vec<ControlClient>>>,
+    listener: Arc<Mutex<DeviceStateListener>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ControlServer {
+    #[doc = "'listener' é o listener do DevicesManager, ele passa a apontar para o servidor e o listener anterior continua recebendo os eventos. Um socket que sobrou em 'path' é removido, qualquer outro arquivo é mantido e o servidor não é iniciado"]
+    pub fn start(
+        path: &Path,
+        ports: PortDevices,
+        current_frame: Arc<Mutex<(u64, InputFrame)>>,
+        listener: &Arc<Mutex<DeviceStateListener>>,
+    ) -> Result<Self, ErroHandle> {
+        remove_socket_file(path)?;
+
+        let socket = UnixListener::bind(path).map_err(server_erro)?;
+        socket.set_nonblocking(true).map_err(server_erro)?;
+
+        install_listener(listener);
+
+        let running = Arc::new(Mutex::new(true));
+        let clients = Arc::new(Mutex::new(Vec::<ControlClient>::new()));
+        let thread_running = running.clone();
+        let thread_clients = clients.clone();
+        let devices = ControlDevices {
+            ports,
+            current_frame,
+        };
+
+        let thread = thread::spawn(move || {
+            while *thread_running.lock().unwrap() {
+                match socket.accept() {
+                    Ok((stream, _)) => {
+                        if stream.set_nonblocking(false).is_err() {
+                            continue;
+                        }
+
+                        let Ok(client_stream) = stream.try_clone() else {
+                            continue;
+                        };
+
+                        let devices = devices.clone();
+                        let client_thread = thread::spawn(move || handle_client(stream, devices));
+
+                        let mut clients = thread_clients.lock().unwrap();
+                        clients.retain(|client| !client.thread.is_finished());
+                        clients.push(ControlClient {
+                            stream: client_stream,
+                            thread: client_thread,
+                        });
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => sleep(ACCEPT_SLEEP_TIME),
+                    Err(_) => break,
+                }
+            }
+        });
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            running,
+            clients,
+            listener: listener.clone(),
+            thread: Some(thread),
+        })
+    }
+
+    #[doc = "encerra o servidor e todas as conexões abertas, o DevicesManager volta a usar o listener anterior"]
+    pub fn stop(&mut self) {
+        let Some(thread) = self.thread.take() else {
+            return;
+        };
+
+        *self.running.lock().unwrap() = false;
+        let _ = thread.join();
+
+        for client in self.clients.lock().unwrap().drain(..) {
+            let _ = client.stream.shutdown(Shutdown::Both);
+        }
+
+        SUBSCRIBERS.lock().unwrap().clear();
+        uninstall_listener(&self.listener);
+        let _ = remove_socket_file(&self.path);
+    }
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+pub fn install_listener(listener: &Arc<Mutex<DeviceStateListener>>) {
+    let mut listener = listener.lock().unwrap();
+
+    if *listener as *const () != control_listener as *const () {
+        *FORWARD_LISTENER.lock().unwrap() = Some(*listener);
+        *listener = control_listener;
+    }
+}
+
+fn uninstall_listener(listener: &Arc<Mutex<DeviceStateListener>>) {
+    let forward = FORWARD_LISTENER.lock().unwrap().take();
+    let mut listener = listener.lock().unwrap();
+
+    if *listener as *const () == control_listener as *const () {
+        *listener = forward.unwrap_or(|_, _| {});
+    }
+}
+
+fn control_listener(state: DeviceState, device: Device) {
+    let mut subscribers = SUBSCRIBERS.lock().unwrap();
+
+    if !subscribers.is_empty() {
+        let mut event = state_to_json(&state);
+        event.insert("device".to_string(), device_to_json(&device));
+        let line = Value::Object(event).to_string();
+
+        subscribers.retain(|subscriber| subscriber.send(line.clone()).is_ok());
+    }
+
+    drop(subscribers);
+
+    let forward = *FORWARD_LISTENER.lock().unwrap();
+
+    if let Some(forward) = forward {
+        forward(state, device);
+    }
+}
+
+fn handle_client(stream: UnixStream, devices: ControlDevices) {
+    let Ok(writer) = stream.try_clone() else {
+        return;
+    };
+
+    let writer = Arc::new(Mutex::new(writer));
+
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else {
+            break;
+        };
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match serde_json::from_str::<Value>(&line) {
+            Ok(request) => handle_request(&request, &devices, &writer),
+            Err(e) => Err(format!("JSON inválido: {e}")),
+        };
+
+        let response = match response {
+            Ok(Value::Object(mut response)) => {
+                response.insert("ok".to_string(), Value::Bool(true));
+                Value::Object(response)
+            }
+            Ok(_) => json!({ "ok": true }),
+            Err(error) => json!({ "ok": false, "error": error }),
+        };
+
+        if write_line(&writer, &response.to_string()).is_err() {
+            break;
+        }
+    }
+}
+
+fn handle_request(
+    request: &Value,
+    devices: &ControlDevices,
+    writer: &Arc<Mutex<UnixStream>>,
+) -> Result<Value, String> {
+    let cmd = request["cmd"]
+        .as_str()
+        .ok_or("o campo 'cmd' é obrigatório")?;
+
+    match cmd {
+        "list_devices" => {
+            let devices: Vec<Value> = devices
+                .ports
+                .get_gamepads()
+                .iter()
+                .map(Device::from_gamepad)
+                .chain(
+                    devices
+                        .ports
+                        .get_virtual_gamepads()
+                        .iter()
+                        .map(Device::from_virtual),
+                )
+                .map(|device| device_to_json(&device))
+                .collect();
+
+            Ok(json!({ "devices": devices }))
+        }
+        "get_state" => {
+            let (frame_count, frame) = devices.current_frame.lock().unwrap().clone();
+            let ports: Vec<Value> = frame
+                .ports
+                .iter()
+                .map(|input| json!({ "bitmask": input.bitmask as u16, "analog": input.analog }))
+                .collect();
+
+            Ok(json!({
+                "frame": frame_count,
+                "ports": ports,
+                "keyboard": frame.keyboard,
+            }))
+        }
+        "get_mapping" => {
+            let gamepad = find_gamepad(request, devices)?;
+            let mapping: Vec<Value> = gamepad
+                .key_map
+                .iter()
+                .map(|key_map| {
+                    json!({
+                        "button": GamepadKeyMap::get_key_name_from_native_button(&key_map.native),
+                        "retro": key_map.retro,
+                    })
+                })
+                .collect();
+
+            Ok(json!({ "mapping": mapping }))
+        }
+        "set_mapping" => {
+            let gamepad = find_gamepad(request, devices)?;
+            let button = request["button"]
+                .as_str()
+                .and_then(GamepadKeyMap::get_native_button_from_key_name)
+                .ok_or("o campo 'button' deve ser o nome de um botão")?;
+            let retro = request["retro"]
+                .as_u64()
+                .filter(|retro| *retro < JOYPAD_BUTTON_COUNT as u64)
+                .ok_or("o campo 'retro' deve ser um RETRO_DEVICE_ID_JOYPAD_*")?;
+
+            devices
+                .ports
+                .set_gamepad_key_map(gamepad.id, button, retro as u32)
+                .map_err(|e| e.message)?;
+
+            Ok(Value::Null)
+        }
+        "assign_port" => {
+            let id = device_id(request)?;
+            let port = port_field(request, "port")?;
+
+            devices.ports.assign_port(id, port).map_err(|e| e.message)?;
+
+            Ok(Value::Null)
+        }
+        "share_port" => {
+            let id = device_id(request)?;
+            let port = port_field(request, "port")?;
+
+            devices.ports.share_port(id, port).map_err(|e| e.message)?;
+
+            Ok(Value::Null)
+        }
+        "swap_ports" => {
+            let port = port_field(request, "port")?;
+            let other = port_field(request, "other")?;
+
+            devices
+                .ports
+                .swap_ports(port, other)
+                .map_err(|e| e.message)?;
+
+            Ok(Value::Null)
+        }
+        "unassign_port" => {
+            let id = device_id(request)?;
+
+            devices.ports.unassign_port(id).map_err(|e| e.message)?;
+
+            Ok(Value::Null)
+        }
+        "subscribe" => {
+            let (sender, receiver) = channel::<String>();
+            let writer = writer.clone();
+
+            thread::spawn(move || {
+                for line in receiver {
+                    if write_line(&writer, &line).is_err() {
+                        break;
+                    }
+                }
+            });
+
+            SUBSCRIBERS.lock().unwrap().push(sender);
+
+            Ok(Value::Null)
+        }
+        _ => Err(format!("comando '{cmd}' desconhecido")),
+    }
+}
+
+//aceita também os gamepads virtuais, o DevicesManager responde com erro se o id não existir
+fn device_id(request: &Value) -> Result<Uuid, String> {
+    request["device"]
+        .as_str()
+        .and_then(|id| Uuid::parse_str(id).ok())
+        .ok_or_else(|| "o campo 'device' deve ser o id de um device".to_string())
+}
+
+fn find_gamepad(request: &Value, devices: &ControlDevices) -> Result<RetroGamePad, String> {
+    let id = device_id(request)?;
+
+    devices
+        .ports
+        .get_gamepads()
+        .into_iter()
+        .find(|gamepad| gamepad.id == id)
+        .ok_or_else(|| format!("o device {id} não está conectado"))
+}
+
+fn port_field(request: &Value, field: &str) -> Result<i16, String> {
+    request[field]
+        .as_i64()
+        .and_then(|port| i16::try_from(port).ok())
+        .ok_or_else(|| format!("o campo '{field}' deve ser um número"))
+}
+
+fn write_line(writer: &Arc<Mutex<UnixStream>>, line: &str) -> std::io::Result<()> {
+    let mut writer = writer.lock().unwrap();
+    writer.write_all(line.as_bytes())?;
+    writer.write_all(b"\n")
+}
+
+fn device_to_json(device: &Device) -> Value {
+    let device_type = match device.device_type {
+        DeviceType::Gamepad => "gamepad",
+        DeviceType::RemoteGamepad => "remote_gamepad",
+        DeviceType::Keyboard => "keyboard",
+        DeviceType::Movie => "movie",
+    };
+
+    json!({
+        "id": device.id.to_string(),
+        "name": device.name,
+        "port": device.retro_port,
+        "retro_type": device.retro_type,
+        "type": device_type,
+    })
+}
+
+fn state_to_json(state: &DeviceState) -> Map<String, Value> {
+    let (event, detail) = match state {
+        DeviceState::Connected => ("connected", Value::Null),
+        DeviceState::Disconnected => ("disconnected", Value::Null),
+        DeviceState::ButtonPressed(button) => ("button_pressed", json!({ "button": button })),
+        DeviceState::HotkeyPressed(action) => {
+            ("hotkey_pressed", json!({ "action": action_name(action) }))
+        }
+        DeviceState::HotkeyReleased(action) => {
+            ("hotkey_released", json!({ "action": action_name(action) }))
+        }
+        DeviceState::ButtonHeld(button, duration) => (
+            "button_held",
+            json!({ "button": button, "ms": duration.as_millis() as u64 }),
+        ),
+        DeviceState::ButtonDoubleTapped(button) => {
+            ("button_double_tapped", json!({ "button": button }))
+        }
+        DeviceState::ButtonLongReleased(button, duration) => (
+            "button_long_released",
+            json!({ "button": button, "ms": duration.as_millis() as u64 }),
+        ),
+        DeviceState::MovieEnded(frames) => ("movie_ended", json!({ "frames": frames })),
+        DeviceState::PortChanged(from, to) => ("port_changed", json!({ "from": from, "to": to })),
+        DeviceState::Reconnected => ("reconnected", Value::Null),
+        DeviceState::Promoted(port) => ("promoted", json!({ "port": port })),
+        DeviceState::PortReserved(port) => ("port_reserved", json!({ "port": port })),
+        DeviceState::AutoPause(port) => ("auto_pause", json!({ "port": port })),
+        DeviceState::ReconnectExpired(port) => ("reconnect_expired", json!({ "port": port })),
+    };
+
+    let mut event_json = match detail {
+        Value::Object(detail) => detail,
+        _ => Map::new(),
+    };
+    event_json.insert("event".to_string(), Value::from(event));
+
+    event_json
+}
+
+fn action_name(action: &HotkeyAction) -> String {
+    match action {
+        HotkeyAction::Custom(name) => name.clone(),
+        action => format!("{action:?}"),
+    }
+}
+
+fn remove_socket_file(path: &Path) -> Result<(), ErroHandle> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            std::fs::remove_file(path).map_err(server_erro)
+        }
+        Ok(_) => Err(ErroHandle {
+            level: retro_log_level::RETRO_LOG_ERROR,
+            message: format!(
+                "não foi possível iniciar o control socket: {} já existe e não é um socket",
+                path.display()
+            ),
+        }),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(server_erro(e)),
+    }
+}
+
+fn server_erro(erro: std::io::Error) -> ErroHandle {
+    ErroHandle {
+        level: retro_log_level::RETRO_LOG_ERROR,
+        message: format!("não foi possível iniciar o control socket: {erro}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices_manager::DevicesManager;
+
+    fn socket_path() -> PathBuf {
+        std::env::temp_dir().join(format!("retro_ab_gamepad_{}.sock", Uuid::new_v4()))
+    }
+
+    struct Client {
+        reader: BufReader<UnixStream>,
+        writer: UnixStream,
+    }
+
+    impl Client {
+        fn connect(path: &Path) -> Self {
+            let writer = UnixStream::connect(path).unwrap();
+            writer
+                .set_read_timeout(Some(Duration::from_secs(2)))
+                .unwrap();
+
+            Self {
+                reader: BufReader::new(writer.try_clone().unwrap()),
+                writer,
+            }
+        }
+
+        fn read(&mut self) -> Value {
+            let mut line = String::new();
+            self.reader.read_line(&mut line).unwrap();
+            serde_json::from_str(&line).unwrap()
+        }
+
+        fn request(&mut self, request: Value) -> Value {
+            writeln!(self.writer, "{request}").unwrap();
+            self.read()
+        }
+    }
+
+    #[test]
+    fn requests_are_answered_on_the_same_line_protocol() {
+        let path = socket_path();
+        let mut manager = DevicesManager::new(None);
+        let gamepad = RetroGamePad::test_gamepad(0, 0);
+        let id = gamepad.id.to_string();
+        manager.connected_gamepads.lock().unwrap().push(gamepad);
+        manager.start_control_server(&path).unwrap();
+
+        let mut client = Client::connect(&path);
+        let mut subscriber = Client::connect(&path);
+
+        let devices = client.request(json!({ "cmd": "list_devices" }));
+        assert_eq!(devices["ok"], true);
+        assert_eq!(devices["devices"][0]["id"], id.as_str());
+        assert_eq!(devices["devices"][0]["port"], 0);
+
+        let invalid = client.request(json!({
+            "cmd": "set_mapping",
+            "device": id,
+            "button": "B",
+            "retro": JOYPAD_BUTTON_COUNT,
+        }));
+        assert_eq!(invalid["ok"], false);
+        assert!(invalid["error"].is_string());
+
+        assert_eq!(
+            subscriber.request(json!({ "cmd": "subscribe" })),
+            json!({ "ok": true })
+        );
+        assert_eq!(
+            client.request(json!({ "cmd": "unassign_port", "device": id })),
+            json!({ "ok": true })
+        );
+
+        let event = subscriber.read();
+        assert_eq!(event["event"], "port_changed");
+        assert_eq!(event["from"], 0);
+        assert_eq!(event["to"], -1);
+        assert_eq!(event["device"]["id"], id.as_str());
+
+        manager.stop_control_server();
+        assert!(FORWARD_LISTENER.lock().unwrap().is_none());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn file_that_is_not_a_socket_is_kept() {
+        let path = socket_path();
+        std::fs::write(&path, "config").unwrap();
+
+        let mut manager = DevicesManager::new(None);
+        let result = manager.start_control_server(&path);
+        let kept = std::fs::read_to_string(&path);
+        let _ = std::fs::remove_file(&path);
+
+        assert!(result.is_err());
+        assert_eq!(kept.unwrap(), "config");
+    }
+}
@@ -1,7 +1,42 @@
-use crate::{constants::DEFAULT_MAX_PORT, gamepad::retro_gamepad::RetroGamePad};
-use gilrs::Gilrs;
-use retro_ab::retro_sys::{retro_rumble_effect, RETRO_DEVICE_ID_JOYPAD_MASK};
-use std::sync::{Arc, Mutex};
+#[cfg(all(unix, feature = "control-socket"))]
+use crate::control_socket::{install_listener, ControlServer};
+#[cfg(feature = "scripting")]
+use crate::scripting::FrameScript;
+use crate::{
+    constants::DEFAULT_MAX_PORT,
+    gamepad::{
+        button_gestures::{ButtonGestures, GestureConfig},
+        gamepad_key_map::GamepadKeyMap,
+        retro_gamepad::RetroGamePad,
+        virtual_gamepad::VirtualGamePad,
+    },
+    hotkeys::{Hotkey, HotkeyAction, Hotkeys},
+    input_delay::InputDelay,
+    input_frame::{AnalogMerge, InputFrame, PortInput, JOYPAD_BUTTON_COUNT},
+    input_macro::{InputMacro, MacroBinding, Macros},
+    input_provider::{AttachedProvider, InputProvider, ProviderMode},
+    middleware::{swapped_port, InputMiddleware, MiddlewareContext, MiddlewarePipeline},
+    movie::{player::MoviePlayer, recorder::MovieRecorder, MovieHeader, MOVIE_DEVICE_ID},
+    netplay::{decode_frame, encode_frame, remote::RemoteInput},
+    overlay_server::{OverlayServer, OverlayServerConfig},
+    port_assignment::{JoinMode, PortAssignment},
+    port_policy::PortPolicy,
+    remote_server::{RemoteServer, RemoteServerConfig},
+    turbo::{Turbo, TurboButton, TurboMode},
+};
+use gilrs::{Button, Gilrs};
+use retro_ab::{
+    erro_handle::ErroHandle,
+    retro_sys::{retro_log_level, retro_rumble_effect, RETRO_DEVICE_NONE},
+};
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+    net::SocketAddr,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 use uuid::Uuid;
 
 #[derive(Debug)]
@@ -9,6 +44,27 @@ pub enum DeviceState {
     Connected,
     Disconnected,
     ButtonPressed(String),
+    HotkeyPressed(HotkeyAction),
+    HotkeyReleased(HotkeyAction),
+    #[doc = "o botão continua pressionado depois do tempo configurado em GestureConfig::long_press"]
+    ButtonHeld(String, Duration),
+    ButtonDoubleTapped(String),
+    #[doc = "o botão foi solto depois de um ButtonHeld, junto com o tempo total em que ficou pressionado"]
+    ButtonLongReleased(String, Duration),
+    #[doc = "o movie terminou ou chegou ao frame de take over, junto com a quantidade de frames entregues ao Core, o input volta a ser lido dos devices"]
+    MovieEnded(u64),
+    #[doc = "porta anterior e a nova porta do device, -1 significa sem porta"]
+    PortChanged(i16, i16),
+    #[doc = "o controle voltou dentro do tempo de reconexão e recebeu de novo o id, a porta e o key_map que tinha"]
+    Reconnected,
+    #[doc = "o device estava na fila de espera e recebeu a porta informada"]
+    Promoted(i16),
+    #[doc = "o device desconectou com as portas travadas e a porta informada ficou reservada para ele"]
+    PortReserved(i16),
+    #[doc = "o device da porta informada desconectou com o auto pause ativado, o Core deve ser pausado até a porta receber um device"]
+    AutoPause(i16),
+    #[doc = "o controle não voltou dentro do tempo de reconexão e a porta informada deixou de estar reservada, -1 se ele já não tinha porta"]
+    ReconnectExpired(i16),
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -21,7 +77,10 @@ pub struct DeviceRubble {
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum DeviceType {
     Gamepad,
+    #[doc = "gamepad virtual controlado pela rede, veja RemoteServer"]
+    RemoteGamepad,
     Keyboard,
+    Movie,
 }
 
 #[derive(Debug, Clone, Eq)]
@@ -50,45 +109,973 @@ impl Device {
             retro_type: gamepad.retro_type,
         }
     }
+
+    pub fn from_virtual(gamepad: &VirtualGamePad) -> Self {
+        Self {
+            id: gamepad.id,
+            device_type: DeviceType::RemoteGamepad,
+            name: gamepad.name.clone(),
+            retro_port: gamepad.retro_port,
+            retro_type: gamepad.retro_type,
+        }
+    }
+
+    pub fn from_movie(header: &MovieHeader) -> Self {
+        Self {
+            id: MOVIE_DEVICE_ID,
+            device_type: DeviceType::Movie,
+            name: header.core_name.clone(),
+            retro_port: -1,
+            retro_type: RETRO_DEVICE_NONE,
+        }
+    }
 }
 
 pub type DeviceStateListener = fn(DeviceState, Device);
 
 #[derive(Debug, Clone)]
 pub struct DevicesManager {
-    gilrs_instance: Arc<Mutex<Gilrs>>,
+    gilrs_instance: Arc<Mutex<Option<Gilrs>>>,
     pub connected_gamepads: Arc<Mutex<Vec<RetroGamePad>>>,
+    virtual_gamepads: Arc<Mutex<Vec<VirtualGamePad>>>,
     max_ports: Arc<Mutex<usize>>,
+    analog_merge: Arc<Mutex<AnalogMerge>>,
+    ports: Arc<Mutex<PortAssignment>>,
     listener: Option<Arc<Mutex<DeviceStateListener>>>,
+    turbo: Arc<Mutex<Turbo>>,
+    hotkeys: Arc<Mutex<Hotkeys>>,
+    gestures: Arc<Mutex<ButtonGestures>>,
+    pressed_keys: Arc<Mutex<Vec<u32>>>,
+    //o número do frame fica junto com o frame para que os servidores leiam os dois de uma vez
+    current_frame: Arc<Mutex<(u64, InputFrame)>>,
+    recorder: Arc<Mutex<Option<MovieRecorder<BufWriter<File>>>>>,
+    recorder_erro: Arc<Mutex<Option<ErroHandle>>>,
+    player: Arc<Mutex<Option<MoviePlayer>>>,
+    providers: Arc<Mutex<Vec<AttachedProvider>>>,
+    frame_count: Arc<Mutex<u64>>,
+    replay_until: Arc<Mutex<u64>>,
+    macros: Arc<Mutex<Macros>>,
+    #[cfg(feature = "scripting")]
+    script: Arc<Mutex<Option<FrameScript>>>,
+    input_delay: Arc<Mutex<InputDelay>>,
+    pipeline: Arc<Mutex<MiddlewarePipeline>>,
+    remote_input: Arc<Mutex<RemoteInput>>,
+    remote_server: Arc<Mutex<Option<RemoteServer>>>,
+    overlay_server: Arc<Mutex<Option<OverlayServer>>>,
+    #[cfg(all(unix, feature = "control-socket"))]
+    control_server: Arc<Mutex<Option<ControlServer>>>,
 }
 
 impl DevicesManager {
     pub fn new(listener: Option<Arc<Mutex<DeviceStateListener>>>) -> Self {
+        let turbo = Arc::new(Mutex::new(Turbo::new()));
+        let hotkeys = Arc::new(Mutex::new(Hotkeys::new()));
+        let macros = Arc::new(Mutex::new(Macros::new()));
+        let input_delay = Arc::new(Mutex::new(InputDelay::new()));
+        #[cfg(feature = "scripting")]
+        let script = Arc::new(Mutex::new(None));
+
+        let mut pipeline = MiddlewarePipeline::new();
+        pipeline.push(hotkeys.clone()).unwrap();
+        pipeline.push(turbo.clone()).unwrap();
+        pipeline.push(macros.clone()).unwrap();
+        #[cfg(feature = "scripting")]
+        pipeline.push(script.clone()).unwrap();
+        pipeline.push(input_delay.clone()).unwrap();
+
         Self {
-            gilrs_instance: Arc::new(Mutex::new(Gilrs::new().unwrap())),
+            //sem o gilrs (ex: CI sem udev) o manager continua funcionando com devices virtuais e InputProvider
+            gilrs_instance: Arc::new(Mutex::new(Gilrs::new().ok())),
             connected_gamepads: Arc::new(Mutex::new(Vec::new())),
+            virtual_gamepads: Arc::new(Mutex::new(Vec::new())),
             max_ports: Arc::new(Mutex::new(DEFAULT_MAX_PORT)),
+            analog_merge: Arc::new(Mutex::new(AnalogMerge::default())),
+            ports: Arc::new(Mutex::new(PortAssignment::new())),
             listener,
+            turbo,
+            hotkeys,
+            gestures: Arc::new(Mutex::new(ButtonGestures::new())),
+            pressed_keys: Arc::new(Mutex::new(Vec::new())),
+            current_frame: Arc::new(Mutex::new((0, InputFrame::default()))),
+            recorder: Arc::new(Mutex::new(None)),
+            recorder_erro: Arc::new(Mutex::new(None)),
+            player: Arc::new(Mutex::new(None)),
+            providers: Arc::new(Mutex::new(Vec::new())),
+            frame_count: Arc::new(Mutex::new(0)),
+            replay_until: Arc::new(Mutex::new(0)),
+            macros,
+            #[cfg(feature = "scripting")]
+            script,
+            input_delay,
+            pipeline: Arc::new(Mutex::new(pipeline)),
+            remote_input: Arc::new(Mutex::new(RemoteInput::new())),
+            remote_server: Arc::new(Mutex::new(None)),
+            overlay_server: Arc::new(Mutex::new(None)),
+            #[cfg(all(unix, feature = "control-socket"))]
+            control_server: Arc::new(Mutex::new(None)),
         }
     }
 
     pub fn set_listener(&mut self, listener: Arc<Mutex<DeviceStateListener>>) {
-        self.listener = Some(listener);
+        let Some(current) = &self.listener else {
+            self.listener = Some(listener);
+            return;
+        };
+
+        //os servidores guardam uma cópia do Arc, então o listener é trocado dentro dele
+        *current.lock().unwrap() = *listener.lock().unwrap();
+
+        #[cfg(all(unix, feature = "control-socket"))]
+        if self.control_server.lock().unwrap().is_some() {
+            install_listener(current);
+        }
+    }
+
+    //o mesmo Arc é compartilhado com os servidores para que eles vejam as trocas feitas pelo set_listener
+    fn shared_listener(&mut self) -> Arc<Mutex<DeviceStateListener>> {
+        self.listener
+            .get_or_insert_with(|| Arc::new(Mutex::new(|_, _| {})))
+            .clone()
     }
 
     pub fn update_state(&mut self) {
         RetroGamePad::update(
-            &mut self.gilrs_instance,
+            &self.gilrs_instance,
             &self.connected_gamepads,
+            &self.virtual_gamepads,
             &self.max_ports,
             &self.listener,
+            &self.gestures,
+            &self.ports,
         );
+
+        let gamepads = self.connected_gamepads.lock().unwrap();
+        self.hotkeys
+            .lock()
+            .unwrap()
+            .update(&gamepads, &self.listener);
+    }
+
+    #[doc = "deve ser chamado uma vez por frame (input_poll_callback), alem de atualizar os devices monta o frame que será respondido ao Core"]
+    pub fn update_frame_state(&mut self) {
+        self.update_state();
+
+        let frame_count = {
+            let mut frame_count = self.frame_count.lock().unwrap();
+            *frame_count += 1;
+            *frame_count
+        };
+
+        //depois de um rollback os frames refeitos vêm do histórico, os providers e os middlewares já
+        //processaram esses frames e não podem avançar o seu estado de novo
+        let replaying = frame_count <= *self.replay_until.lock().unwrap();
+
+        let mut live_frame = {
+            let gilrs = self.gilrs_instance.lock().unwrap();
+            let gamepads = self.connected_gamepads.lock().unwrap();
+
+            let mut frame = self.create_frame(&gamepads);
+
+            if !replaying {
+                let mut shared_bitmasks = self.virtual_bitmasks(frame.ports.len());
+                self.poll_providers(&mut frame, &mut shared_bitmasks, frame_count);
+
+                let context = MiddlewareContext {
+                    frame_count,
+                    gamepads: &gamepads,
+                    gilrs: gilrs.as_ref(),
+                    disabled_ports: &[],
+                    shared_bitmasks: &shared_bitmasks,
+                };
+                self.pipeline.lock().unwrap().process(&context, &mut frame);
+            }
+
+            frame
+        };
+
+        self.remote_input
+            .lock()
+            .unwrap()
+            .apply(frame_count, &mut live_frame);
+
+        let frame = self.next_movie_frame().unwrap_or(live_frame);
+
+        if let Some(recorder) = &mut *self.recorder.lock().unwrap() {
+            if let Err(e) = recorder.record(&frame) {
+                *self.recorder_erro.lock().unwrap() = Some(e);
+            }
+        }
+
+        *self.current_frame.lock().unwrap() = (frame_count, frame);
+    }
+
+    fn next_movie_frame(&self) -> Option<InputFrame> {
+        let mut player = self.player.lock().unwrap();
+        let movie = player.as_mut()?;
+
+        if let Some(frame) = movie.next_frame() {
+            return Some(frame.clone());
+        }
+
+        let device = Device::from_movie(&movie.header);
+        let frame_count = movie.position();
+        *player = None;
+
+        if let Some(listener) = &self.listener {
+            let listener = listener.lock().unwrap();
+            listener(DeviceState::MovieEnded(frame_count), device);
+        }
+
+        None
+    }
+
+    //'shared_bitmasks' recebe os botões de cada provider, ver MiddlewareContext::bitmask_without
+    fn poll_providers(
+        &self,
+        frame: &mut InputFrame,
+        shared_bitmasks: &mut Vec<i16>,
+        frame_count: u64,
+    ) {
+        let analog_merge = *self.analog_merge.lock().unwrap();
+
+        for attached in &mut *self.providers.lock().unwrap() {
+            if attached.port < 0 {
+                continue;
+            }
+
+            let port = attached.port as usize;
+            let input = attached.provider.poll(frame_count);
+
+            if frame.ports.len() <= port {
+                frame.ports.resize(port + 1, PortInput::default());
+            }
+
+            if shared_bitmasks.len() <= port {
+                shared_bitmasks.resize(port + 1, 0);
+            }
+
+            shared_bitmasks[port] |= input.bitmask;
+
+            frame.ports[port] = match attached.mode {
+                ProviderMode::Replace => input,
+                ProviderMode::Combine => frame.ports[port].merge(&input, analog_merge),
+            };
+        }
+    }
+
+    fn create_frame(&self, gamepads: &[RetroGamePad]) -> InputFrame {
+        let mut frame = InputFrame {
+            ports: vec![PortInput::default(); self.get_port_count(gamepads)],
+            keyboard: self.pressed_keys.lock().unwrap().clone(),
+        };
+        let analog_merge = *self.analog_merge.lock().unwrap();
+
+        for gamepad in gamepads {
+            if gamepad.retro_port < 0 {
+                continue;
+            }
+
+            let input = PortInput {
+                bitmask: gamepad.get_key_bitmask(),
+                analog: gamepad.analog,
+            };
+
+            //vários devices podem estar na mesma porta (ver share_port)
+            let port = &mut frame.ports[gamepad.retro_port as usize];
+            *port = port.merge(&input, analog_merge);
+        }
+
+        for gamepad in &*self.virtual_gamepads.lock().unwrap() {
+            if gamepad.retro_port < 0 {
+                continue;
+            }
+
+            let input = PortInput {
+                bitmask: gamepad.bitmask,
+                analog: gamepad.analog,
+            };
+
+            let port = &mut frame.ports[gamepad.retro_port as usize];
+            *port = port.merge(&input, analog_merge);
+        }
+
+        frame
+    }
+
+    fn virtual_bitmasks(&self, port_count: usize) -> Vec<i16> {
+        let mut bitmasks = vec![0; port_count];
+
+        for gamepad in &*self.virtual_gamepads.lock().unwrap() {
+            if gamepad.retro_port < 0 {
+                continue;
+            }
+
+            if let Some(bitmask) = bitmasks.get_mut(gamepad.retro_port as usize) {
+                *bitmask |= gamepad.bitmask;
+            }
+        }
+
+        bitmasks
+    }
+
+    fn get_port_count(&self, gamepads: &[RetroGamePad]) -> usize {
+        let max_ports = *self.max_ports.lock().unwrap();
+        let last_port = gamepads
+            .iter()
+            .map(|gamepad| gamepad.retro_port + 1)
+            .chain(
+                self.virtual_gamepads
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .map(|gamepad| gamepad.retro_port + 1),
+            )
+            .max()
+            .unwrap_or(0);
+
+        max_ports.max(last_port as usize)
     }
 
     pub fn set_max_port(&self, max_port: usize) {
         *self.max_ports.lock().unwrap() = max_port;
     }
 
+    #[doc = "a política é usada somente nos devices conectados depois dessa chamada"]
+    pub fn set_port_policy(&self, port_policy: Box<dyn PortPolicy>) {
+        self.ports.lock().unwrap().set_policy(port_policy);
+    }
+
+    #[doc = "tempo em que a porta de um controle desconectado continua reservada esperando que ele volte, zero desativa"]
+    pub fn set_reconnect_grace_period(&self, grace: Duration) {
+        self.ports.lock().unwrap().set_reconnect_grace(grace);
+    }
+
+    pub fn get_reconnect_grace_period(&self) -> Duration {
+        self.ports.lock().unwrap().get_reconnect_grace()
+    }
+
+    #[doc = "devices sem porta esperando uma porta livre, em ordem de chegada"]
+    pub fn get_waiting_gamepads(&self) -> Vec<Uuid> {
+        self.ports.lock().unwrap().get_waiting()
+    }
+
+    #[doc = "com Some os devices só recebem porta ao pressionar JoinMode::join_button, None volta a usar a política de portas e os devices sem porta entram na fila de espera na ordem em que se conectaram"]
+    pub fn set_join_mode(&self, join_mode: Option<JoinMode>) {
+        let gamepads = self.connected_gamepads.lock().unwrap();
+        let mut ports = self.ports.lock().unwrap();
+
+        ports.set_join_mode(join_mode);
+
+        //connected_gamepads já está na ordem de conexão
+        for gamepad in gamepads.iter() {
+            ports.wait_for_port(gamepad);
+        }
+    }
+
+    pub fn get_join_mode(&self) -> Option<JoinMode> {
+        self.ports.lock().unwrap().get_join_mode()
+    }
+
+    pub fn set_lineup_locked(&self, locked: bool) {
+        self.ports.lock().unwrap().set_lineup_locked(locked);
+    }
+
+    pub fn is_lineup_locked(&self) -> bool {
+        self.ports.lock().unwrap().is_lineup_locked()
+    }
+
+    #[doc = "congela as portas atuais, deve ser ativado quando o conteúdo é iniciado"]
+    pub fn set_ports_locked(&self, locked: bool) {
+        self.ports.lock().unwrap().set_ports_locked(locked);
+    }
+
+    pub fn is_ports_locked(&self) -> bool {
+        self.ports.lock().unwrap().is_ports_locked()
+    }
+
+    pub fn set_auto_pause(&self, enabled: bool) {
+        self.ports.lock().unwrap().set_auto_pause(enabled);
+    }
+
+    pub fn is_auto_pause_enabled(&self) -> bool {
+        self.ports.lock().unwrap().is_auto_pause_enabled()
+    }
+
+    #[doc = "true enquanto alguma porta estiver sem o controle que desconectou, pode ser consultado a cada frame"]
+    pub fn is_awaiting_controller(&self) -> bool {
+        !self
+            .ports
+            .lock()
+            .unwrap()
+            .get_awaiting_controller()
+            .is_empty()
+    }
+
+    pub fn get_ports_awaiting_controller(&self) -> Vec<i16> {
+        self.ports.lock().unwrap().get_awaiting_controller()
+    }
+
+    pub fn get_gamepads(&self) -> Vec<RetroGamePad> {
+        self.port_devices().get_gamepads()
+    }
+
+    #[doc = "gamepads sem um device do gilrs, como os controles remotos do RemoteServer"]
+    pub fn get_virtual_gamepads(&self) -> Vec<VirtualGamePad> {
+        self.port_devices().get_virtual_gamepads()
+    }
+
+    #[doc = "faz o botão nativo do gamepad responder como o botão RETRO_DEVICE_ID_JOYPAD_* informado, o botão nativo que acionava esse mesmo botão retro deixa de acioná-lo"]
+    pub fn set_gamepad_key_map(
+        &self,
+        gamepad_id: Uuid,
+        native: Button,
+        retro: u32,
+    ) -> Result<(), ErroHandle> {
+        self.port_devices()
+            .set_gamepad_key_map(gamepad_id, native, retro)
+    }
+
+    #[doc = "coloca o gamepad na porta informada, a porta precisa estar livre (use swap_ports para trocar dois jogadores), com as portas travadas somente um gamepad sem porta pode ser colocado numa porta, inclusive numa porta reservada. As configurações por porta (turbo, macros, atraso de input) ficam na porta e não acompanham o gamepad"]
+    pub fn assign_port(&self, gamepad_id: Uuid, port: i16) -> Result<(), ErroHandle> {
+        self.port_devices().assign_port(gamepad_id, port)
+    }
+
+    #[doc = "coloca o gamepad na porta informada mesmo que outros devices já estejam nela, os botões dos devices da porta são combinados com OU e os eixos analógicos de acordo com set_analog_merge. O teclado não tem porta, use um InputProvider com ProviderMode::Combine para combinar as teclas com a porta"]
+    pub fn share_port(&self, gamepad_id: Uuid, port: i16) -> Result<(), ErroHandle> {
+        self.port_devices().share_port(gamepad_id, port)
+    }
+
+    pub fn set_analog_merge(&self, analog_merge: AnalogMerge) {
+        *self.analog_merge.lock().unwrap() = analog_merge;
+    }
+
+    pub fn get_analog_merge(&self) -> AnalogMerge {
+        *self.analog_merge.lock().unwrap()
+    }
+
+    #[doc = "todos os devices da porta 'port' vão para a porta 'other' e vice-versa, o turbo, as macros e o atraso de input das portas também são trocados. As portas remotas do netplay e os estágios desabilitados por porta continuam nas mesmas portas"]
+    pub fn swap_ports(&self, port: i16, other: i16) -> Result<(), ErroHandle> {
+        self.port_devices().swap_ports(port, other)
+    }
+
+    #[doc = "o device continua conectado, mas sem porta (retro_port = -1) o seu input não chega ao Core"]
+    pub fn unassign_port(&self, gamepad_id: Uuid) -> Result<(), ErroHandle> {
+        self.port_devices().unassign_port(gamepad_id)
+    }
+
+    fn port_devices(&self) -> PortDevices {
+        PortDevices {
+            connected_gamepads: self.connected_gamepads.clone(),
+            virtual_gamepads: self.virtual_gamepads.clone(),
+            max_ports: self.max_ports.clone(),
+            ports: self.ports.clone(),
+            listener: self.listener.clone(),
+            turbo: self.turbo.clone(),
+            macros: self.macros.clone(),
+            input_delay: self.input_delay.clone(),
+        }
+    }
+
+    pub fn get_input_state(&self, port: i16, key_id: i16) -> i16 {
+        match self.current_frame.lock().unwrap().1.port(port) {
+            Some(input) => input.get_key_pressed(key_id),
+            None => 0,
+        }
+    }
+
+    pub fn get_analog_state(&self, port: i16, index: i16, id: i16) -> i16 {
+        match self.current_frame.lock().unwrap().1.port(port) {
+            Some(input) => input.get_analog(index, id),
+            None => 0,
+        }
+    }
+
+    pub fn get_keyboard_state(&self, key_id: i16) -> i16 {
+        let (_, frame) = &*self.current_frame.lock().unwrap();
+
+        if frame.keyboard.contains(&(key_id as u32)) {
+            1
+        } else {
+            0
+        }
+    }
+
+    #[doc = "o teclado é lido pelo frontend, informe aqui as teclas RETROK_* pressionadas para que elas cheguem ao Core"]
+    pub fn set_keyboard_key(&self, key: u32, pressed: bool) {
+        let mut pressed_keys = self.pressed_keys.lock().unwrap();
+        pressed_keys.retain(|k| *k != key);
+
+        if pressed {
+            pressed_keys.push(key);
+        }
+    }
+
+    pub fn start_recording(
+        &self,
+        path: &Path,
+        core_name: &str,
+        content_hash: &[u8],
+    ) -> Result<(), ErroHandle> {
+        let gamepads = self.connected_gamepads.lock().unwrap();
+        let mut port_devices = vec![RETRO_DEVICE_NONE; self.get_port_count(&gamepads)];
+
+        let virtual_gamepads = self.virtual_gamepads.lock().unwrap();
+        let devices = gamepads
+            .iter()
+            .map(|gamepad| (gamepad.retro_port, gamepad.retro_type))
+            .chain(
+                virtual_gamepads
+                    .iter()
+                    .map(|gamepad| (gamepad.retro_port, gamepad.retro_type)),
+            );
+
+        for (retro_port, retro_type) in devices {
+            if retro_port >= 0 {
+                port_devices[retro_port as usize] = retro_type;
+            }
+        }
+
+        let header = MovieHeader {
+            core_name: core_name.to_string(),
+            content_hash: content_hash.to_vec(),
+            port_devices,
+            ..Default::default()
+        };
+
+        let file = File::create(path).map_err(|e| ErroHandle {
+            level: retro_log_level::RETRO_LOG_ERROR,
+            message: format!("Não foi possível criar o movie: {e}"),
+        })?;
+
+        *self.recorder_erro.lock().unwrap() = None;
+        *self.recorder.lock().unwrap() = Some(MovieRecorder::new(BufWriter::new(file), &header)?);
+
+        Ok(())
+    }
+
+    #[doc = "finaliza a gravação e retorna a quantidade de frames gravados"]
+    pub fn stop_recording(&self) -> Result<u64, ErroHandle> {
+        let recorder = self.recorder.lock().unwrap().take();
+
+        if let Some(erro) = self.recorder_erro.lock().unwrap().take() {
+            return Err(erro);
+        }
+
+        match recorder {
+            Some(recorder) => recorder.finish(),
+            None => Err(ErroHandle {
+                level: retro_log_level::RETRO_LOG_WARN,
+                message: "Nenhuma gravação em andamento".to_string(),
+            }),
+        }
+    }
+
+    #[doc = "a partir do próximo input_poll_callback o Core recebe o input do movie ao invés dos devices"]
+    pub fn play_movie(&self, path: &Path) -> Result<MovieHeader, ErroHandle> {
+        let file = File::open(path).map_err(|e| ErroHandle {
+            level: retro_log_level::RETRO_LOG_ERROR,
+            message: format!("Não foi possível abrir o movie: {e}"),
+        })?;
+
+        let player = MoviePlayer::from_reader(&mut BufReader::new(file))?;
+        let header = player.header.clone();
+        self.load_movie(player);
+
+        Ok(header)
+    }
+
+    #[doc = "executa um movie já carregado, como os importados de .fm2 ou .bk2"]
+    pub fn load_movie(&self, player: MoviePlayer) {
+        *self.player.lock().unwrap() = Some(player);
+    }
+
+    #[doc = "devolve o controle aos devices, retorna o frame do movie em que isso aconteceu"]
+    pub fn take_over_movie(&self) -> Option<u64> {
+        self.player
+            .lock()
+            .unwrap()
+            .take()
+            .map(|player| player.position())
+    }
+
+    #[doc = "devolve o controle aos devices quando o movie chegar ao frame informado, o MovieEnded é enviado nesse momento"]
+    pub fn take_over_movie_at(&self, frame: u64) -> Result<(), ErroHandle> {
+        let mut player = self.player.lock().unwrap();
+        let movie = player.as_mut().ok_or_else(|| ErroHandle {
+            level: retro_log_level::RETRO_LOG_WARN,
+            message: "nenhum movie está em execução".to_string(),
+        })?;
+
+        if frame < movie.position() {
+            return Err(ErroHandle {
+                level: retro_log_level::RETRO_LOG_WARN,
+                message: format!(
+                    "o movie já passou do frame {frame}, use take_over_movie para parar agora"
+                ),
+            });
+        }
+
+        movie.set_take_over_frame(Some(frame));
+
+        Ok(())
+    }
+
+    pub fn is_playing_movie(&self) -> bool {
+        self.player.lock().unwrap().is_some()
+    }
+
+    #[doc = "quantidade de input_poll_callback recebidos"]
+    pub fn get_frame_count(&self) -> u64 {
+        *self.frame_count.lock().unwrap()
+    }
+
+    #[doc = "retorna o frame que está sendo respondido ao Core"]
+    pub fn get_current_frame(&self) -> InputFrame {
+        self.current_frame.lock().unwrap().1.clone()
+    }
+
+    #[doc = "serializa o frame atual junto com o seu número, veja netplay::encode_frame"]
+    pub fn encode_current_frame(&self) -> Result<Vec<u8>, ErroHandle> {
+        let (frame_count, frame) = &*self.current_frame.lock().unwrap();
+        encode_frame(*frame_count, frame)
+    }
+
+    #[doc = "guarda o input dos últimos 'capacity' frames de todas as portas para que eles possam ser refeitos depois de um rollback"]
+    pub fn enable_rollback(&self, capacity: usize) {
+        self.remote_input.lock().unwrap().enable_rollback(capacity);
+    }
+
+    pub fn disable_rollback(&self) {
+        self.remote_input.lock().unwrap().disable_rollback();
+    }
+
+    #[doc = "depois de restaurar o estado do Core no frame informado, o próximo input_poll_callback volta a ser esse frame. Os frames refeitos não chamam os InputProvider nem os middlewares, e o rollback é recusado enquanto um movie está sendo gravado ou reproduzido"]
+    pub fn rollback_to(&self, frame_number: u64) -> Result<(), ErroHandle> {
+        if self.recorder.lock().unwrap().is_some() || self.player.lock().unwrap().is_some() {
+            return Err(ErroHandle {
+                level: retro_log_level::RETRO_LOG_WARN,
+                message:
+                    "o rollback não pode ser feito durante a gravação ou reprodução de um movie"
+                        .to_string(),
+            });
+        }
+
+        let mut frame_count = self.frame_count.lock().unwrap();
+
+        if frame_number == 0
+            || frame_number > *frame_count
+            || !self
+                .remote_input
+                .lock()
+                .unwrap()
+                .can_rollback_to(frame_number)
+        {
+            return Err(ErroHandle {
+                level: retro_log_level::RETRO_LOG_WARN,
+                message: format!("o frame {frame_number} não está no histórico de input"),
+            });
+        }
+
+        let mut replay_until = self.replay_until.lock().unwrap();
+        *replay_until = (*replay_until).max(*frame_count);
+        *frame_count = frame_number - 1;
+
+        Ok(())
+    }
+
+    #[doc = "retorna em ordem os frames em que a previsão do input remoto estava errada, faça o rollback para o primeiro deles"]
+    pub fn take_mispredicted_frames(&self) -> Vec<u64> {
+        self.remote_input.lock().unwrap().take_mispredicted()
+    }
+
+    #[doc = "o input local da porta é ignorado e o Core recebe o input enviado com submit_remote_input"]
+    pub fn set_remote_port(&self, port: i16, remote: bool) {
+        self.remote_input
+            .lock()
+            .unwrap()
+            .set_remote_port(port, remote);
+    }
+
+    pub fn get_remote_ports(&self) -> Vec<i16> {
+        self.remote_input.lock().unwrap().get_remote_ports()
+    }
+
+    #[doc = "o input deve chegar antes do input_poll_callback do frame informado, caso contrário a porta fica solta nesse frame"]
+    pub fn submit_remote_input(&self, frame_number: u64, port: i16, input: PortInput) {
+        self.remote_input
+            .lock()
+            .unwrap()
+            .submit(frame_number, port, input);
+    }
+
+    #[doc = "recebe um frame gerado por encode_frame e guarda o input das portas remotas, retorna o número do frame"]
+    pub fn submit_remote_frame(&self, bytes: &[u8]) -> Result<u64, ErroHandle> {
+        let (frame_number, frame) = decode_frame(bytes)?;
+        self.remote_input
+            .lock()
+            .unwrap()
+            .submit_frame(frame_number, &frame);
+
+        Ok(frame_number)
+    }
+
+    #[doc = "cada porta aceita somente um provider, um provider anterior na mesma porta é substituído"]
+    pub fn attach_input_provider(
+        &self,
+        port: i16,
+        provider: Box<dyn InputProvider>,
+        mode: ProviderMode,
+    ) {
+        let mut providers = self.providers.lock().unwrap();
+        providers.retain(|attached| attached.port != port);
+        providers.push(AttachedProvider {
+            port,
+            mode,
+            provider,
+        });
+    }
+
+    pub fn detach_input_provider(&self, port: i16) -> Option<Box<dyn InputProvider>> {
+        let mut providers = self.providers.lock().unwrap();
+        let index = providers
+            .iter()
+            .position(|attached| attached.port == port)?;
+
+        Some(providers.remove(index).provider)
+    }
+
+    #[doc = "a partir do próximo frame o input da porta é gravado até stop_macro_recording"]
+    pub fn start_macro_recording(&self, port: i16) {
+        self.macros.lock().unwrap().start_recording(port);
+    }
+
+    pub fn stop_macro_recording(&self, name: &str) -> Option<InputMacro> {
+        self.macros.lock().unwrap().stop_recording(name)
+    }
+
+    pub fn add_macro(&self, input_macro: InputMacro) {
+        self.macros.lock().unwrap().add(input_macro);
+    }
+
+    pub fn remove_macro(&self, name: &str) {
+        self.macros.lock().unwrap().remove(name);
+    }
+
+    pub fn get_macros(&self) -> Vec<InputMacro> {
+        self.macros.lock().unwrap().get_macros()
+    }
+
+    pub fn bind_macro(&self, binding: MacroBinding) {
+        self.macros.lock().unwrap().bind(binding);
+    }
+
+    pub fn unbind_macro(&self, port: i16, trigger: Button) {
+        self.macros.lock().unwrap().unbind(port, trigger);
+    }
+
+    #[cfg(feature = "scripting")]
+    #[doc = "o script é executado a cada frame com o tempo limite informado, veja FrameScript"]
+    pub fn load_script(&self, source: &str, budget: Duration) -> Result<(), ErroHandle> {
+        *self.script.lock().unwrap() = Some(FrameScript::new(source, budget)?);
+        Ok(())
+    }
+
+    #[cfg(feature = "scripting")]
+    pub fn unload_script(&self) {
+        *self.script.lock().unwrap() = None;
+    }
+
+    #[cfg(feature = "scripting")]
+    #[doc = "retorna o último erro de execução do script, se houver"]
+    pub fn take_script_erro(&self) -> Option<ErroHandle> {
+        self.script.lock().unwrap().as_mut()?.take_erro()
+    }
+
+    #[doc = "adiciona o estágio no fim do pipeline, depois dos estágios padrão"]
+    pub fn add_middleware(
+        &self,
+        middleware: Arc<Mutex<dyn InputMiddleware>>,
+    ) -> Result<(), ErroHandle> {
+        self.pipeline.lock().unwrap().push(middleware)
+    }
+
+    pub fn insert_middleware_before(
+        &self,
+        stage_name: &str,
+        middleware: Arc<Mutex<dyn InputMiddleware>>,
+    ) -> Result<(), ErroHandle> {
+        self.pipeline
+            .lock()
+            .unwrap()
+            .insert_before(stage_name, middleware)
+    }
+
+    pub fn insert_middleware_after(
+        &self,
+        stage_name: &str,
+        middleware: Arc<Mutex<dyn InputMiddleware>>,
+    ) -> Result<(), ErroHandle> {
+        self.pipeline
+            .lock()
+            .unwrap()
+            .insert_after(stage_name, middleware)
+    }
+
+    #[doc = "os estágios padrão também podem ser removidos, use os nomes em middleware::*_STAGE"]
+    pub fn remove_middleware(&self, stage_name: &str) -> Option<Arc<Mutex<dyn InputMiddleware>>> {
+        self.pipeline.lock().unwrap().remove(stage_name)
+    }
+
+    pub fn set_middleware_port_enabled(
+        &self,
+        stage_name: &str,
+        port: i16,
+        enabled: bool,
+    ) -> Result<(), ErroHandle> {
+        self.pipeline
+            .lock()
+            .unwrap()
+            .set_port_enabled(stage_name, port, enabled)
+    }
+
+    pub fn get_middleware_names(&self) -> Vec<String> {
+        self.pipeline.lock().unwrap().get_stage_names()
+    }
+
+    #[doc = "o Core recebe o input da porta capturado 'frames' frames antes, use 0 para remover o atraso"]
+    pub fn set_input_delay(&self, port: i16, frames: usize) {
+        self.input_delay.lock().unwrap().set_delay(port, frames);
+    }
+
+    pub fn get_input_delay(&self, port: i16) -> usize {
+        self.input_delay.lock().unwrap().get_delay(port)
+    }
+
+    pub fn set_turbo_button(&self, button: TurboButton) {
+        self.turbo.lock().unwrap().set_button(button);
+    }
+
+    pub fn remove_turbo_button(&self, port: i16, retro: u32) {
+        self.turbo.lock().unwrap().remove_button(port, retro);
+    }
+
+    pub fn set_turbo_mode(&self, mode: TurboMode) {
+        self.turbo.lock().unwrap().set_mode(mode);
+    }
+
+    pub fn add_hotkey(&self, hotkey: Hotkey) {
+        self.hotkeys.lock().unwrap().add(hotkey);
+    }
+
+    pub fn remove_hotkey(&self, action: &HotkeyAction) {
+        self.hotkeys.lock().unwrap().remove(action);
+    }
+
+    pub fn set_gesture_config(&self, config: GestureConfig) {
+        self.gestures.lock().unwrap().set_config(config);
+    }
+
+    #[doc = "um servidor que já estava em execução é encerrado antes, retorna o endereço em que o novo servidor está escutando"]
+    pub fn start_remote_server(
+        &mut self,
+        config: RemoteServerConfig,
+    ) -> Result<SocketAddr, ErroHandle> {
+        self.stop_remote_server();
+
+        let server = RemoteServer::start(
+            config,
+            self.connected_gamepads.clone(),
+            self.virtual_gamepads.clone(),
+            self.max_ports.clone(),
+            Some(self.shared_listener()),
+            self.ports.clone(),
+        )?;
+        let local_addr = server.local_addr();
+        *self.remote_server.lock().unwrap() = Some(server);
+
+        Ok(local_addr)
+    }
+
+    pub fn stop_remote_server(&self) {
+        if let Some(mut server) = self.take_remote_server() {
+            server.stop();
+        }
+    }
+
+    #[doc = "o servidor chama o listener ao desconectar os controles, use para encerrar o servidor sem segurar o lock do DevicesManager"]
+    pub fn take_remote_server(&self) -> Option<RemoteServer> {
+        self.remote_server.lock().unwrap().take()
+    }
+
+    #[doc = "o overlay recebe o mesmo frame respondido ao Core, retorna o endereço em que o servidor está escutando"]
+    pub fn start_overlay_server(
+        &self,
+        config: OverlayServerConfig,
+    ) -> Result<SocketAddr, ErroHandle> {
+        let mut overlay_server = self.overlay_server.lock().unwrap();
+
+        if let Some(mut server) = overlay_server.take() {
+            server.stop();
+        }
+
+        let server = OverlayServer::start(config, self.current_frame.clone())?;
+        let local_addr = server.local_addr();
+        *overlay_server = Some(server);
+
+        Ok(local_addr)
+    }
+
+    pub fn stop_overlay_server(&self) {
+        if let Some(mut server) = self.overlay_server.lock().unwrap().take() {
+            server.stop();
+        }
+    }
+
+    #[cfg(all(unix, feature = "control-socket"))]
+    #[doc = "um servidor que já estava em execução é encerrado antes, veja ControlServer para o protocolo"]
+    pub fn start_control_server(&mut self, path: &Path) -> Result<(), ErroHandle> {
+        self.stop_control_server();
+
+        let listener = self.shared_listener();
+        let server = ControlServer::start(
+            path,
+            self.port_devices(),
+            self.current_frame.clone(),
+            &listener,
+        )?;
+        *self.control_server.lock().unwrap() = Some(server);
+
+        Ok(())
+    }
+
+    #[cfg(all(unix, feature = "control-socket"))]
+    pub fn stop_control_server(&self) {
+        if let Some(mut server) = self.control_server.lock().unwrap().take() {
+            server.stop();
+        }
+    }
+
+    pub fn apply_rumble(&self, rubble: DeviceRubble) -> bool {
+        println!("{:?}", rubble);
+        true
+    }
+}
+
+//somente os Arcs usados para listar os devices e mover eles entre as portas, assim o ControlServer
+//não precisa guardar uma cópia do DevicesManager (que guarda o próprio ControlServer)
+#[derive(Clone)]
+pub(crate) struct PortDevices {
+    connected_gamepads: Arc<Mutex<Vec<RetroGamePad>>>,
+    virtual_gamepads: Arc<Mutex<Vec<VirtualGamePad>>>,
+    max_ports: Arc<Mutex<usize>>,
+    ports: Arc<Mutex<PortAssignment>>,
+    listener: Option<Arc<Mutex<DeviceStateListener>>>,
+    turbo: Arc<Mutex<Turbo>>,
+    macros: Arc<Mutex<Macros>>,
+    input_delay: Arc<Mutex<InputDelay>>,
+}
+
+impl PortDevices {
     pub fn get_gamepads(&self) -> Vec<RetroGamePad> {
         let gamepads = self.connected_gamepads.lock().unwrap_or_else(|op| {
             let mut gamepads = op.into_inner();
@@ -100,23 +1087,239 @@ impl DevicesManager {
         gamepads.clone()
     }
 
-    pub fn get_input_state(&self, port: i16, key_id: i16) -> i16 {
-        for gamepad in &*self.connected_gamepads.lock().unwrap() {
-            if gamepad.retro_port == port {
-                return if key_id as u32 != RETRO_DEVICE_ID_JOYPAD_MASK {
-                    gamepad.get_key_pressed(key_id)
-                } else {
-                    gamepad.get_key_bitmask()
-                };
+    pub fn get_virtual_gamepads(&self) -> Vec<VirtualGamePad> {
+        self.virtual_gamepads.lock().unwrap().clone()
+    }
+
+    pub fn set_gamepad_key_map(
+        &self,
+        gamepad_id: Uuid,
+        native: Button,
+        retro: u32,
+    ) -> Result<(), ErroHandle> {
+        if retro >= JOYPAD_BUTTON_COUNT {
+            return Err(ErroHandle {
+                level: retro_log_level::RETRO_LOG_WARN,
+                message: format!("{retro} não é um RETRO_DEVICE_ID_JOYPAD_*"),
+            });
+        }
+
+        let mut gamepads = self.connected_gamepads.lock().unwrap();
+        let gamepad = gamepads
+            .iter_mut()
+            .find(|gamepad| gamepad.id == gamepad_id)
+            .ok_or_else(|| gamepad_not_found(gamepad_id))?;
+
+        gamepad
+            .key_map
+            .retain(|key| key.native == native || key.retro != retro);
+
+        match gamepad.key_map.iter_mut().find(|key| key.native == native) {
+            Some(key_map) => key_map.retro = retro,
+            None => gamepad.key_map.push(GamepadKeyMap::new(native, retro)),
+        }
+
+        Ok(())
+    }
+
+    pub fn assign_port(&self, gamepad_id: Uuid, port: i16) -> Result<(), ErroHandle> {
+        self.place_gamepad(gamepad_id, port, false)
+    }
+
+    pub fn share_port(&self, gamepad_id: Uuid, port: i16) -> Result<(), ErroHandle> {
+        self.place_gamepad(gamepad_id, port, true)
+    }
+
+    fn place_gamepad(&self, gamepad_id: Uuid, port: i16, shared: bool) -> Result<(), ErroHandle> {
+        let change = {
+            let mut gamepads = self.connected_gamepads.lock().unwrap();
+            let mut virtual_gamepads = self.virtual_gamepads.lock().unwrap();
+            self.check_port(port)?;
+
+            let in_use = gamepads
+                .iter()
+                .any(|gamepad| gamepad.retro_port == port && gamepad.id != gamepad_id)
+                || virtual_gamepads
+                    .iter()
+                    .any(|gamepad| gamepad.retro_port == port && gamepad.id != gamepad_id);
+
+            if !shared && in_use {
+                return Err(ErroHandle {
+                    level: retro_log_level::RETRO_LOG_WARN,
+                    message: format!("a porta {port} já está em uso"),
+                });
+            }
+
+            let mut ports = self.ports.lock().unwrap();
+            let change = Self::move_device(
+                &mut gamepads,
+                &mut virtual_gamepads,
+                &mut ports,
+                gamepad_id,
+                port,
+            )?;
+
+            ports.claim_port(port);
+            change
+        };
+
+        self.notify_port_changes(change);
+
+        Ok(())
+    }
+
+    pub fn swap_ports(&self, port: i16, other: i16) -> Result<(), ErroHandle> {
+        let changes = {
+            let mut gamepads = self.connected_gamepads.lock().unwrap();
+            let mut virtual_gamepads = self.virtual_gamepads.lock().unwrap();
+            self.check_port(port)?;
+            self.check_port(other)?;
+
+            let mut ports = self.ports.lock().unwrap();
+
+            if ports.is_ports_locked() {
+                return Err(ports_locked_erro());
+            }
+
+            let ids: Vec<(Uuid, i16)> = gamepads
+                .iter()
+                .map(|gamepad| (gamepad.id, gamepad.retro_port))
+                .chain(
+                    virtual_gamepads
+                        .iter()
+                        .map(|gamepad| (gamepad.id, gamepad.retro_port)),
+                )
+                .collect();
+
+            let mut changes = Vec::new();
+
+            for (id, retro_port) in ids {
+                changes.extend(Self::move_device(
+                    &mut gamepads,
+                    &mut virtual_gamepads,
+                    &mut ports,
+                    id,
+                    swapped_port(retro_port, port, other),
+                )?);
             }
+
+            //as configurações por porta acompanham os jogadores
+            ports.swap_ports(port, other);
+            self.turbo.lock().unwrap().swap_ports(port, other);
+            self.macros.lock().unwrap().swap_ports(port, other);
+            self.input_delay.lock().unwrap().swap_ports(port, other);
+
+            changes
+        };
+
+        self.notify_port_changes(changes);
+
+        Ok(())
+    }
+
+    pub fn unassign_port(&self, gamepad_id: Uuid) -> Result<(), ErroHandle> {
+        let change = {
+            let mut gamepads = self.connected_gamepads.lock().unwrap();
+            let mut virtual_gamepads = self.virtual_gamepads.lock().unwrap();
+            let mut ports = self.ports.lock().unwrap();
+
+            if ports.is_ports_locked() {
+                return Err(ports_locked_erro());
+            }
+
+            Self::move_device(
+                &mut gamepads,
+                &mut virtual_gamepads,
+                &mut ports,
+                gamepad_id,
+                -1,
+            )?
+        };
+
+        self.notify_port_changes(change);
+
+        Ok(())
+    }
+
+    fn check_port(&self, port: i16) -> Result<(), ErroHandle> {
+        let max_ports = *self.max_ports.lock().unwrap();
+
+        if port < 0 || port as usize >= max_ports {
+            return Err(ErroHandle {
+                level: retro_log_level::RETRO_LOG_WARN,
+                message: format!("a porta {port} não existe, o máximo de portas é {max_ports}"),
+            });
         }
 
-        0
+        Ok(())
     }
 
-    pub fn apply_rumble(&self, rubble: DeviceRubble) -> bool {
-        println!("{:?}", rubble);
-        true
+    //com as portas travadas somente um device sem porta pode receber uma porta
+    fn move_device(
+        gamepads: &mut [RetroGamePad],
+        virtual_gamepads: &mut [VirtualGamePad],
+        ports: &mut PortAssignment,
+        id: Uuid,
+        port: i16,
+    ) -> Result<Option<(i16, Device)>, ErroHandle> {
+        let from = gamepads
+            .iter()
+            .map(|gamepad| (gamepad.id, gamepad.retro_port))
+            .chain(virtual_gamepads.iter().map(|g| (g.id, g.retro_port)))
+            .find(|(gamepad_id, _)| *gamepad_id == id)
+            .map(|(_, retro_port)| retro_port)
+            .ok_or_else(|| gamepad_not_found(id))?;
+
+        if from == port {
+            return Ok(None);
+        }
+
+        if ports.is_ports_locked() && from >= 0 {
+            return Err(ports_locked_erro());
+        }
+
+        let device = match gamepads.iter_mut().find(|gamepad| gamepad.id == id) {
+            Some(gamepad) => {
+                gamepad.retro_port = port;
+                Device::from_gamepad(gamepad)
+            }
+            None => {
+                let gamepad = virtual_gamepads
+                    .iter_mut()
+                    .find(|gamepad| gamepad.id == id)
+                    .ok_or_else(|| gamepad_not_found(id))?;
+
+                gamepad.retro_port = port;
+                ports.move_virtual(id, port);
+                Device::from_virtual(gamepad)
+            }
+        };
+
+        Ok(Some((from, device)))
+    }
+
+    fn notify_port_changes(&self, changes: impl IntoIterator<Item = (i16, Device)>) {
+        if let Some(listener) = &self.listener {
+            let listener = listener.lock().unwrap();
+
+            for (from, device) in changes {
+                listener(DeviceState::PortChanged(from, device.retro_port), device);
+            }
+        }
+    }
+}
+
+fn gamepad_not_found(gamepad_id: Uuid) -> ErroHandle {
+    ErroHandle {
+        level: retro_log_level::RETRO_LOG_WARN,
+        message: format!("o gamepad {gamepad_id} não está conectado"),
+    }
+}
+
+fn ports_locked_erro() -> ErroHandle {
+    ErroHandle {
+        level: retro_log_level::RETRO_LOG_WARN,
+        message: "as portas estão travadas, destrave as portas para mudar os jogadores".to_string(),
     }
 }
 
@@ -126,3 +1329,208 @@ pub trait DevicesRequireFunctions {
 
     fn get_key_bitmask(&self) -> i16;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use retro_ab::retro_sys::{RETRO_DEVICE_ID_JOYPAD_B, RETRO_DEVICE_ID_JOYPAD_MASK};
+
+    static EVENTS: Mutex<Vec<(Uuid, String)>> = Mutex::new(Vec::new());
+
+    fn manager() -> DevicesManager {
+        DevicesManager::new(Some(Arc::new(Mutex::new(|state, device| {
+            EVENTS
+                .lock()
+                .unwrap()
+                .push((device.id, format!("{state:?}")));
+        }))))
+    }
+
+    fn events(id: Uuid) -> Vec<String> {
+        EVENTS
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(device, _)| *device == id)
+            .map(|(_, state)| state.clone())
+            .collect()
+    }
+
+    fn bitmask(manager: &DevicesManager, port: i16) -> i16 {
+        manager.get_input_state(port, RETRO_DEVICE_ID_JOYPAD_MASK as i16)
+    }
+
+    #[test]
+    fn movie_hands_control_back_at_the_take_over_frame() {
+        let mut manager = manager();
+        let mut gamepad = RetroGamePad::test_gamepad(0, 0);
+        gamepad.test_press(gilrs::Button::South, true);
+        manager.connected_gamepads.lock().unwrap().push(gamepad);
+
+        let frames = (1..=3)
+            .map(|bitmask| InputFrame {
+                ports: vec![PortInput {
+                    bitmask: bitmask << 8,
+                    analog: [0; 4],
+                }],
+                keyboard: Vec::new(),
+            })
+            .collect();
+        let header = MovieHeader {
+            port_devices: vec![1],
+            ..Default::default()
+        };
+
+        manager.load_movie(MoviePlayer::new(header, frames));
+        manager.take_over_movie_at(2).unwrap();
+
+        manager.update_frame_state();
+        assert_eq!(bitmask(&manager, 0), 1 << 8);
+        manager.update_frame_state();
+        assert_eq!(bitmask(&manager, 0), 2 << 8);
+        assert!(manager.take_over_movie_at(1).is_err());
+        assert!(events(MOVIE_DEVICE_ID).is_empty());
+
+        manager.update_frame_state();
+        assert_eq!(bitmask(&manager, 0), 1 << RETRO_DEVICE_ID_JOYPAD_B);
+        assert!(!manager.is_playing_movie());
+        assert_eq!(events(MOVIE_DEVICE_ID), vec!["MovieEnded(2)"]);
+    }
+
+    #[test]
+    fn recording_header_lists_the_virtual_pads() {
+        let manager = manager();
+        let path = std::env::temp_dir().join(format!("retro_ab_gamepad_{}.movie", Uuid::new_v4()));
+        manager
+            .virtual_gamepads
+            .lock()
+            .unwrap()
+            .push(VirtualGamePad::new("celular".to_string(), 1, 5));
+
+        manager.start_recording(&path, "core", &[]).unwrap();
+        manager.stop_recording().unwrap();
+
+        let header = MovieHeader::read_from(&mut File::open(&path).unwrap());
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(header.unwrap().port_devices[..2], [RETRO_DEVICE_NONE, 5]);
+    }
+
+    //aperta o bit 'frame' do bitmask, para mostrar o frame recebido no poll
+    #[derive(Debug)]
+    struct FrameBits;
+
+    impl InputProvider for FrameBits {
+        fn poll(&mut self, frame: u64) -> PortInput {
+            PortInput {
+                bitmask: 1 << frame,
+                analog: [0; 4],
+            }
+        }
+    }
+
+    #[test]
+    fn providers_replace_or_combine_with_the_port() {
+        let mut manager = manager();
+        let b = 1 << RETRO_DEVICE_ID_JOYPAD_B;
+
+        for port in 0..2 {
+            let mut gamepad = RetroGamePad::test_gamepad(port, port as i16);
+            gamepad.test_press(gilrs::Button::South, true);
+            manager.connected_gamepads.lock().unwrap().push(gamepad);
+        }
+
+        manager.attach_input_provider(0, Box::new(FrameBits), ProviderMode::Replace);
+        manager.attach_input_provider(1, Box::new(FrameBits), ProviderMode::Combine);
+        manager.attach_input_provider(6, Box::new(FrameBits), ProviderMode::Replace);
+
+        manager.update_frame_state();
+        assert_eq!(bitmask(&manager, 0), 1 << 1);
+        assert_eq!(bitmask(&manager, 1), b | 1 << 1);
+        assert_eq!(bitmask(&manager, 6), 1 << 1);
+
+        manager.update_frame_state();
+        assert_eq!(bitmask(&manager, 0), 1 << 2);
+
+        assert!(manager.detach_input_provider(0).is_some());
+        assert!(manager.detach_input_provider(0).is_none());
+
+        manager.update_frame_state();
+        assert_eq!(bitmask(&manager, 0), b);
+        assert_eq!(bitmask(&manager, 1), b | 1 << 3);
+    }
+
+    fn connect(manager: &DevicesManager, index: usize, port: i16) -> Uuid {
+        let gamepad = RetroGamePad::test_gamepad(index, port);
+        let id = gamepad.id;
+        manager.connected_gamepads.lock().unwrap().push(gamepad);
+        id
+    }
+
+    fn port_of(manager: &DevicesManager, id: Uuid) -> i16 {
+        manager
+            .connected_gamepads
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|gamepad| gamepad.id == id)
+            .map_or(-1, |gamepad| gamepad.retro_port)
+    }
+
+    #[test]
+    fn players_move_between_ports_with_port_changed() {
+        let manager = manager();
+        let first = connect(&manager, 0, 0);
+        let second = connect(&manager, 1, -1);
+
+        assert!(manager.assign_port(second, 0).is_err());
+        assert!(manager.assign_port(second, 2).is_err());
+        assert!(manager.assign_port(Uuid::new_v4(), 1).is_err());
+        manager.assign_port(second, 1).unwrap();
+        assert_eq!(events(second), vec!["PortChanged(-1, 1)"]);
+
+        manager.set_input_delay(0, 2);
+        manager.swap_ports(0, 1).unwrap();
+        assert_eq!(
+            (port_of(&manager, first), port_of(&manager, second)),
+            (1, 0)
+        );
+        assert_eq!(events(first), vec!["PortChanged(0, 1)"]);
+        assert_eq!(
+            events(second),
+            vec!["PortChanged(-1, 1)", "PortChanged(1, 0)"]
+        );
+        assert_eq!(manager.get_input_delay(1), 2);
+        assert_eq!(manager.get_input_delay(0), 0);
+
+        manager.unassign_port(first).unwrap();
+        manager.unassign_port(first).unwrap();
+        assert_eq!(port_of(&manager, first), -1);
+        assert_eq!(
+            events(first),
+            vec!["PortChanged(0, 1)", "PortChanged(1, -1)"]
+        );
+    }
+
+    #[test]
+    fn locked_ports_only_accept_players_without_a_port() {
+        let manager = manager();
+        let first = connect(&manager, 0, 0);
+        let second = connect(&manager, 1, -1);
+        manager.set_ports_locked(true);
+
+        assert!(manager.swap_ports(0, 1).is_err());
+        assert!(manager.unassign_port(first).is_err());
+        assert!(manager.assign_port(first, 1).is_err());
+
+        manager.assign_port(second, 1).unwrap();
+        assert_eq!(port_of(&manager, second), 1);
+        assert!(manager.assign_port(second, 0).is_err());
+
+        manager.set_ports_locked(false);
+        manager.swap_ports(0, 1).unwrap();
+        assert_eq!(
+            (port_of(&manager, first), port_of(&manager, second)),
+            (1, 0)
+        );
+    }
+}
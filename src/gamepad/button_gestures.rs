@@ -0,0 +1,242 @@
+use super::gamepad_key_map::GamepadKeyMap;
+use crate::devices_manager::DeviceState;
+use gilrs::{Button, GamepadId};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GestureConfig {
+    #[doc = "tempo que o botão precisa ficar pressionado para gerar DeviceState::ButtonHeld"]
+    pub long_press: Duration,
+    #[doc = "intervalo máximo entre dois toques para gerar DeviceState::ButtonDoubleTapped"]
+    pub double_tap: Duration,
+}
+
+impl Default for GestureConfig {
+    fn default() -> Self {
+        Self {
+            long_press: Duration::from_millis(800),
+            double_tap: Duration::from_millis(300),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ButtonPress {
+    gamepad: GamepadId,
+    button: Button,
+    pressed_at: Instant,
+    held_notified: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct ButtonGestures {
+    config: GestureConfig,
+    pressed: Vec<ButtonPress>,
+    last_taps: Vec<(GamepadId, Button, Instant)>,
+}
+
+impl ButtonGestures {
+    pub fn new() -> Self {
+        Self {
+            config: GestureConfig::default(),
+            pressed: Vec::new(),
+            last_taps: Vec::new(),
+        }
+    }
+
+    pub fn set_config(&mut self, config: GestureConfig) {
+        self.config = config;
+    }
+
+    pub fn pressed(
+        &mut self,
+        gamepad: GamepadId,
+        button: Button,
+        time: Instant,
+    ) -> Option<DeviceState> {
+        self.pressed
+            .retain(|press| press.gamepad != gamepad || press.button != button);
+        self.pressed.push(ButtonPress {
+            gamepad,
+            button,
+            pressed_at: time,
+            held_notified: false,
+        });
+
+        let last_tap = self
+            .last_taps
+            .iter()
+            .position(|(id, btn, _)| *id == gamepad && *btn == button);
+
+        if let Some(index) = last_tap {
+            let (_, _, tapped_at) = self.last_taps.remove(index);
+
+            if elapsed(tapped_at, time) <= self.config.double_tap {
+                return Some(DeviceState::ButtonDoubleTapped(key_name(&button)));
+            }
+        }
+
+        self.last_taps.push((gamepad, button, time));
+
+        None
+    }
+
+    pub fn released(
+        &mut self,
+        gamepad: GamepadId,
+        button: Button,
+        time: Instant,
+    ) -> Option<DeviceState> {
+        let index = self
+            .pressed
+            .iter()
+            .position(|press| press.gamepad == gamepad && press.button == button)?;
+
+        let press = self.pressed.remove(index);
+        let held_for = elapsed(press.pressed_at, time);
+
+        if held_for >= self.config.long_press {
+            return Some(DeviceState::ButtonLongReleased(key_name(&button), held_for));
+        }
+
+        None
+    }
+
+    #[doc = "deve ser chamado a cada atualização, mesmo sem novos eventos, para detectar botões que ainda estão pressionados"]
+    pub fn check_held(&mut self, now: Instant) -> Vec<(GamepadId, DeviceState)> {
+        let mut states = Vec::new();
+
+        for press in &mut self.pressed {
+            if press.held_notified {
+                continue;
+            }
+
+            let held_for = elapsed(press.pressed_at, now);
+
+            if held_for >= self.config.long_press {
+                press.held_notified = true;
+                states.push((
+                    press.gamepad,
+                    DeviceState::ButtonHeld(key_name(&press.button), held_for),
+                ));
+            }
+        }
+
+        let double_tap = self.config.double_tap;
+        self.last_taps
+            .retain(|(_, _, tapped_at)| elapsed(*tapped_at, now) <= double_tap);
+
+        states
+    }
+
+    pub fn forget(&mut self, gamepad: GamepadId) {
+        self.pressed.retain(|press| press.gamepad != gamepad);
+        self.last_taps.retain(|(id, _, _)| *id != gamepad);
+    }
+}
+
+//o Instant é monotônico, um ajuste no relógio do sistema não muda a duração dos gestos
+fn elapsed(from: Instant, to: Instant) -> Duration {
+    to.saturating_duration_since(from)
+}
+
+fn key_name(button: &Button) -> String {
+    GamepadKeyMap::get_key_name_from_native_button(button).to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gamepad::retro_gamepad::RetroGamePad;
+
+    fn gestures() -> (ButtonGestures, GamepadId, Instant) {
+        let mut gestures = ButtonGestures::new();
+        gestures.set_config(GestureConfig {
+            long_press: Duration::from_millis(500),
+            double_tap: Duration::from_millis(200),
+        });
+
+        (
+            gestures,
+            RetroGamePad::test_gamepad(0, 0).inner_id,
+            Instant::now(),
+        )
+    }
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn held_is_notified_once_after_the_threshold() {
+        let (mut gestures, id, start) = gestures();
+        gestures.pressed(id, Button::South, start);
+
+        assert!(gestures.check_held(start + ms(499)).is_empty());
+
+        let held = gestures.check_held(start + ms(500));
+        assert_eq!(held.len(), 1);
+        assert_eq!(held[0].0, id);
+        assert!(matches!(
+            &held[0].1,
+            DeviceState::ButtonHeld(name, duration) if name == "B" && *duration == ms(500)
+        ));
+
+        assert!(gestures.check_held(start + ms(900)).is_empty());
+    }
+
+    #[test]
+    fn long_release_needs_the_threshold() {
+        let (mut gestures, id, start) = gestures();
+
+        gestures.pressed(id, Button::South, start);
+        assert!(gestures
+            .released(id, Button::South, start + ms(499))
+            .is_none());
+
+        gestures.pressed(id, Button::South, start + ms(1000));
+        let released = gestures.released(id, Button::South, start + ms(1600));
+        assert!(matches!(
+            released,
+            Some(DeviceState::ButtonLongReleased(_, duration)) if duration == ms(600)
+        ));
+
+        //soltar um botão que não estava pressionado não gera nada
+        assert!(gestures.released(id, Button::East, start).is_none());
+    }
+
+    #[test]
+    fn double_tap_needs_two_presses_inside_the_window() {
+        let (mut gestures, id, start) = gestures();
+
+        assert!(gestures.pressed(id, Button::South, start).is_none());
+        gestures.released(id, Button::South, start + ms(50));
+        assert!(matches!(
+            gestures.pressed(id, Button::South, start + ms(200)),
+            Some(DeviceState::ButtonDoubleTapped(_))
+        ));
+
+        //o segundo toque do double tap não conta como o primeiro de outro
+        assert!(gestures
+            .pressed(id, Button::South, start + ms(300))
+            .is_none());
+        assert!(gestures
+            .pressed(id, Button::South, start + ms(501))
+            .is_none());
+
+        //botões diferentes não formam um double tap
+        assert!(gestures
+            .pressed(id, Button::East, start + ms(550))
+            .is_none());
+    }
+
+    #[test]
+    fn earlier_time_counts_as_no_time() {
+        let (mut gestures, id, start) = gestures();
+        let later = start + ms(1000);
+
+        gestures.pressed(id, Button::South, later);
+        assert!(gestures.check_held(start).is_empty());
+        assert!(gestures.released(id, Button::South, start).is_none());
+    }
+}
@@ -109,4 +109,39 @@ impl GamepadKeyMap {
             _ => "Chave desconhecida",
         }
     }
+
+    #[doc = "o inverso de get_key_name_from_native_button"]
+    pub fn get_native_button_from_key_name(name: &str) -> Option<Button> {
+        let button = match name {
+            //DPads
+            "DPad-up" => Button::DPadUp,
+            "DPad-down" => Button::DPadDown,
+            "DPad-left" => Button::DPadLeft,
+            "DPad-right" => Button::DPadRight,
+
+            //Buttons
+            "B" => Button::South,
+            "A" => Button::East,
+            "X" => Button::North,
+            "Y" => Button::West,
+
+            //Trigger
+            "L" => Button::LeftTrigger,
+            "R" => Button::RightTrigger,
+            "L2" => Button::LeftTrigger2,
+            "R2" => Button::RightTrigger2,
+
+            //Thumb
+            "LeftThumb" => Button::LeftThumb,
+            "RightThumb" => Button::RightThumb,
+
+            "Start" => Button::Start,
+            "Select" => Button::Select,
+            "mode" => Button::Mode,
+
+            _ => return None,
+        };
+
+        Some(button)
+    }
 }
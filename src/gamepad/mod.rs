@@ -1,3 +1,5 @@
+pub mod button_gestures;
 pub mod gamepad_key_map;
 pub mod retro_gamepad;
-mod update_gamepad_state_handle;
+pub(crate) mod update_gamepad_state_handle;
+pub mod virtual_gamepad;
@@ -1,10 +1,21 @@
 use super::{
+    button_gestures::ButtonGestures,
     gamepad_key_map::GamepadKeyMap,
-    update_gamepad_state_handle::{connect_handle, disconnect_handle, pressed_button_handle},
+    update_gamepad_state_handle::{
+        connect_handle, disconnect_handle, expired_handle, gesture_handle, lineup_handle,
+        pressed_button_handle, promote_handle,
+    },
+    virtual_gamepad::VirtualGamePad,
+};
+use crate::{
+    devices_manager::{DeviceStateListener, DevicesRequireFunctions},
+    port_assignment::PortAssignment,
+};
+use gilrs::{Axis, Button, Event, GamepadId, Gilrs};
+use std::{
+    sync::{Arc, Mutex},
+    time::Instant,
 };
-use crate::devices_manager::{DeviceStateListener, DevicesRequireFunctions};
-use gilrs::{Event, GamepadId, Gilrs};
-use std::sync::{Arc, Mutex};
 use uuid::Uuid;
 
 #[derive(Debug, Clone)]
@@ -12,6 +23,8 @@ pub struct RetroGamePad {
     pub id: Uuid,
     #[doc = "identificação do gamepad fornecida pelo crate gilrs"]
     pub inner_id: GamepadId,
+    #[doc = "uuid do modelo do controle fornecido pelo gilrs, é o mesmo em controles do mesmo modelo"]
+    pub native_uuid: Uuid,
     #[doc = "nome do gamepad"]
     pub name: String,
     #[doc = "indicar ao Core em qual porta o controle esta conectado, se o valor for -1 significa que todas as porta suportas pelo Core ja estão sendo usadas"]
@@ -19,6 +32,10 @@ pub struct RetroGamePad {
     #[doc = "padrão RETRO_DEVICE_JOYPAD"]
     pub retro_type: u32,
     pub key_map: Vec<GamepadKeyMap>,
+    #[doc = "[esquerdo x, esquerdo y, direito x, direito y] no padrão RETRO_DEVICE_ANALOG"]
+    pub analog: [i16; 4],
+    #[doc = "botões do gilrs pressionados, incluindo os que não têm mapeamento como o Button::Mode"]
+    pub native_pressed: Vec<Button>,
 }
 
 impl RetroGamePad {
@@ -31,10 +48,25 @@ impl RetroGamePad {
         Self {
             id: Uuid::new_v4(),
             inner_id,
+            native_uuid: Uuid::nil(),
             name,
             retro_port,
             retro_type,
             key_map: GamepadKeyMap::get_default_key_maps(),
+            analog: [0; 4],
+            native_pressed: Vec::new(),
+        }
+    }
+
+    pub fn is_native_pressed(&self, button: Button) -> bool {
+        self.native_pressed.contains(&button)
+    }
+
+    fn set_native_pressed(&mut self, button: Button, pressed: bool) {
+        self.native_pressed.retain(|native| *native != button);
+
+        if pressed {
+            self.native_pressed.push(button);
         }
     }
 
@@ -44,36 +76,88 @@ impl RetroGamePad {
         for key_info in &mut self.key_map {
             key_info.pressed = gamepad.is_pressed(key_info.native);
         }
+
+        //no RETRO_DEVICE_ANALOG o eixo y positivo aponta para baixo
+        self.analog = [
+            (gamepad.value(Axis::LeftStickX) * i16::MAX as f32) as i16,
+            (-gamepad.value(Axis::LeftStickY) * i16::MAX as f32) as i16,
+            (gamepad.value(Axis::RightStickX) * i16::MAX as f32) as i16,
+            (-gamepad.value(Axis::RightStickY) * i16::MAX as f32) as i16,
+        ];
     }
 
     pub fn update(
-        gilrs_instance: &Arc<Mutex<Gilrs>>,
+        gilrs_instance: &Arc<Mutex<Option<Gilrs>>>,
         connected_gamepads: &Arc<Mutex<Vec<RetroGamePad>>>,
+        virtual_gamepads: &Arc<Mutex<Vec<VirtualGamePad>>>,
         max_ports: &Arc<Mutex<usize>>,
         listener: &Option<Arc<Mutex<DeviceStateListener>>>,
+        gestures: &Arc<Mutex<ButtonGestures>>,
+        ports: &Arc<Mutex<PortAssignment>>,
     ) {
-        let gilrs = &mut *gilrs_instance.lock().unwrap();
+        let mut gilrs_instance = gilrs_instance.lock().unwrap();
 
-        while let Some(Event { id, event, .. }) = gilrs.next_event() {
+        //sem o gilrs somente os gamepads virtuais e os InputProvider geram input
+        while let Some((gilrs, Event { id, event, .. })) = gilrs_instance
+            .as_mut()
+            .and_then(|gilrs| gilrs.next_event().map(|event| (gilrs, event)))
+        {
             match event {
                 gilrs::EventType::Connected => {
-                    connect_handle(id, gilrs, &connected_gamepads, &max_ports, &listener);
+                    connect_handle(id, gilrs, connected_gamepads, max_ports, listener, ports);
                 }
                 gilrs::EventType::Disconnected => {
-                    disconnect_handle(id, &connected_gamepads, &listener)
+                    gestures.lock().unwrap().forget(id);
+                    disconnect_handle(id, connected_gamepads, listener, ports)
                 }
                 gilrs::EventType::ButtonPressed(button, _) => {
-                    pressed_button_handle(&button, id, &connected_gamepads, &listener)
+                    pressed_button_handle(&button, id, &connected_gamepads, &listener);
+
+                    let state = gestures.lock().unwrap().pressed(id, button, Instant::now());
+                    gesture_handle(state.map(|s| vec![(id, s)]), connected_gamepads, listener);
+                }
+                gilrs::EventType::ButtonReleased(button, _) => {
+                    let state = gestures
+                        .lock()
+                        .unwrap()
+                        .released(id, button, Instant::now());
+                    gesture_handle(state.map(|s| vec![(id, s)]), connected_gamepads, listener);
                 }
                 _ => {}
             }
 
             for gamepad_info in &mut *connected_gamepads.lock().unwrap() {
                 if gamepad_info.inner_id == id {
-                    gamepad_info.update_key_pressed(&gilrs);
+                    match event {
+                        gilrs::EventType::ButtonPressed(button, _) => {
+                            gamepad_info.set_native_pressed(button, true)
+                        }
+                        gilrs::EventType::ButtonReleased(button, _) => {
+                            gamepad_info.set_native_pressed(button, false)
+                        }
+                        _ => {}
+                    }
+
+                    gamepad_info.update_key_pressed(gilrs);
                 }
             }
         }
+
+        let held = gestures.lock().unwrap().check_held(Instant::now());
+        gesture_handle(Some(held), connected_gamepads, listener);
+
+        expired_handle(listener, ports);
+        promote_handle(
+            connected_gamepads,
+            virtual_gamepads,
+            max_ports,
+            listener,
+            ports,
+        );
+        lineup_handle(connected_gamepads, max_ports, listener, ports);
+
+        let gamepads = connected_gamepads.lock().unwrap();
+        ports.lock().unwrap().update_awaiting_controller(&gamepads);
     }
 }
 
@@ -93,9 +177,28 @@ impl DevicesRequireFunctions for RetroGamePad {
 
         for key in &self.key_map {
             let pressed = if key.pressed { 1 } else { 0 };
-            bitmask += pressed << key.retro;
+            bitmask |= pressed << key.retro;
         }
 
         bitmask
     }
 }
+
+#[cfg(test)]
+impl RetroGamePad {
+    //o gilrs não permite criar um GamepadId, nos testes ele é lido no formato do serde-serialize
+    pub(crate) fn test_gamepad(index: usize, retro_port: i16) -> Self {
+        let inner_id = serde_json::from_str(&index.to_string()).unwrap();
+        Self::new(inner_id, format!("gamepad {index}"), retro_port, 1)
+    }
+
+    pub(crate) fn test_press(&mut self, button: Button, pressed: bool) {
+        self.set_native_pressed(button, pressed);
+
+        for key_map in &mut self.key_map {
+            if key_map.native == button {
+                key_map.pressed = pressed;
+            }
+        }
+    }
+}
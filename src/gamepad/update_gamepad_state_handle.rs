@@ -1,31 +1,78 @@
-use super::{gamepad_key_map::GamepadKeyMap, retro_gamepad::RetroGamePad};
-use crate::devices_manager::{Device, DeviceState, DeviceStateListener};
+use super::{
+    gamepad_key_map::GamepadKeyMap, retro_gamepad::RetroGamePad, virtual_gamepad::VirtualGamePad,
+};
+use crate::{
+    devices_manager::{Device, DeviceState, DeviceStateListener},
+    port_assignment::PortAssignment,
+};
 use gilrs::{Button, GamepadId, Gilrs};
 use retro_ab::retro_sys::RETRO_DEVICE_JOYPAD;
 use std::sync::{Arc, Mutex};
+use uuid::Uuid;
 
-//se o valor retornado for -1 significa que todas as portas suportas pelo Core ja estão sendo usadas
-fn get_available_port(
-    max_ports: &Arc<Mutex<usize>>,
+//adiciona o gamepad a lista, restaurando o controle se ele estava esperando a reconexão
+//ou com a porta escolhida pela política de portas
+fn add_gamepad(
+    mut gamepad: RetroGamePad,
     connected_gamepads: &Arc<Mutex<Vec<RetroGamePad>>>,
-) -> i16 {
-    let invalid_port = -1;
+    max_ports: &Arc<Mutex<usize>>,
+    ports: &Arc<Mutex<PortAssignment>>,
+) -> (RetroGamePad, DeviceState) {
+    let mut gamepads = connected_gamepads.lock().unwrap();
+    let max_ports = *max_ports.lock().unwrap();
+    let mut ports = ports.lock().unwrap();
+
+    let state = match ports.reconnect(&gamepad, &gamepads, max_ports) {
+        Some(restored) => {
+            gamepad = restored;
+            DeviceState::Reconnected
+        }
+        None => {
+            gamepad.retro_port = ports.assign(&gamepad, &gamepads, max_ports);
+            DeviceState::Connected
+        }
+    };
+
+    ports.wait_for_port(&gamepad);
+    gamepads.push(gamepad.clone());
 
-    let mut connected_gamepads = connected_gamepads.lock().unwrap();
+    (gamepad, state)
+}
+
+//o gamepad já deve ter sido removido de connected_gamepads, os eventos da porta só são enviados
+//se nenhum outro device continua nela (ver share_port)
+fn release_gamepad(
+    gamepad: RetroGamePad,
+    connected_gamepads: &Arc<Mutex<Vec<RetroGamePad>>>,
+    listener: &Option<Arc<Mutex<DeviceStateListener>>>,
+    ports: &Arc<Mutex<PortAssignment>>,
+) {
+    let (reserved, pause) = {
+        let gamepads = connected_gamepads.lock().unwrap();
+        let mut ports = ports.lock().unwrap();
+        let pause = ports.await_controller(gamepad.retro_port, &gamepads);
 
-    connected_gamepads.sort_by(|gmp, f_gmp| gmp.retro_port.cmp(&f_gmp.retro_port));
+        (ports.disconnect(gamepad.clone(), &gamepads), pause)
+    };
 
-    if let Some(gamepad) = connected_gamepads.last() {
-        let current_port = gamepad.retro_port + 1;
+    if let Some(listener) = listener {
+        let listener = listener.lock().unwrap();
+        listener(DeviceState::Disconnected, Device::from_gamepad(&gamepad));
 
-        if current_port as usize > *max_ports.lock().unwrap() {
-            return invalid_port;
+        if reserved {
+            listener(
+                DeviceState::PortReserved(gamepad.retro_port),
+                Device::from_gamepad(&gamepad),
+            );
         }
 
-        return current_port;
+        if pause {
+            listener(
+                DeviceState::AutoPause(gamepad.retro_port),
+                Device::from_gamepad(&gamepad),
+            );
+        }
     }
-
-    0
 }
 
 pub fn remove(
@@ -54,23 +101,22 @@ pub fn connect_handle(
     connected_gamepads: &Arc<Mutex<Vec<RetroGamePad>>>,
     max_ports: &Arc<Mutex<usize>>,
     listener: &Option<Arc<Mutex<DeviceStateListener>>>,
+    ports: &Arc<Mutex<PortAssignment>>,
 ) {
-    if let Some(gamepad) = gilrs.connected_gamepad(gamepad_id) {
-        let port = get_available_port(&max_ports, &connected_gamepads);
-
-        let gamepad = RetroGamePad::new(
+    if let Some(native) = gilrs.connected_gamepad(gamepad_id) {
+        let mut gamepad = RetroGamePad::new(
             gamepad_id,
-            gamepad.name().to_string(),
-            port,
+            native.name().to_string(),
+            -1,
             RETRO_DEVICE_JOYPAD,
         );
+        gamepad.native_uuid = Uuid::from_bytes(native.uuid());
 
-        let mut gamepads = connected_gamepads.lock().unwrap();
-        gamepads.push(gamepad.clone());
+        let (gamepad, state) = add_gamepad(gamepad, connected_gamepads, max_ports, ports);
 
         if let Some(listener) = listener {
             let listener = listener.lock().unwrap();
-            listener(DeviceState::Connected, Device::from_gamepad(&gamepad));
+            listener(state, Device::from_gamepad(&gamepad));
         }
     }
 }
@@ -79,11 +125,149 @@ pub fn disconnect_handle(
     id: GamepadId,
     connected_gamepads: &Arc<Mutex<Vec<RetroGamePad>>>,
     listener: &Option<Arc<Mutex<DeviceStateListener>>>,
+    ports: &Arc<Mutex<PortAssignment>>,
 ) {
     if let Ok(gamepad) = remove(id, &connected_gamepads) {
-        if let Some(listener) = listener {
-            let listener = listener.lock().unwrap();
-            listener(DeviceState::Disconnected, Device::from_gamepad(&gamepad));
+        release_gamepad(gamepad, connected_gamepads, listener, ports);
+    }
+}
+
+pub fn connect_virtual_handle(
+    name: String,
+    connected_gamepads: &Arc<Mutex<Vec<RetroGamePad>>>,
+    virtual_gamepads: &Arc<Mutex<Vec<VirtualGamePad>>>,
+    max_ports: &Arc<Mutex<usize>>,
+    listener: &Option<Arc<Mutex<DeviceStateListener>>>,
+    ports: &Arc<Mutex<PortAssignment>>,
+) -> VirtualGamePad {
+    let gamepad = {
+        let gamepads = connected_gamepads.lock().unwrap();
+        let mut virtual_gamepads = virtual_gamepads.lock().unwrap();
+        let max_ports = *max_ports.lock().unwrap();
+
+        let mut gamepad = VirtualGamePad::new(name, -1, RETRO_DEVICE_JOYPAD);
+        gamepad.retro_port = ports
+            .lock()
+            .unwrap()
+            .connect_virtual(&gamepad, &gamepads, max_ports);
+        virtual_gamepads.push(gamepad.clone());
+
+        gamepad
+    };
+
+    if let Some(listener) = listener {
+        let listener = listener.lock().unwrap();
+        listener(DeviceState::Connected, Device::from_virtual(&gamepad));
+    }
+
+    gamepad
+}
+
+pub fn disconnect_virtual_handle(
+    id: Uuid,
+    connected_gamepads: &Arc<Mutex<Vec<RetroGamePad>>>,
+    virtual_gamepads: &Arc<Mutex<Vec<VirtualGamePad>>>,
+    listener: &Option<Arc<Mutex<DeviceStateListener>>>,
+    ports: &Arc<Mutex<PortAssignment>>,
+) {
+    let gamepad = {
+        let mut gamepads = virtual_gamepads.lock().unwrap();
+
+        match gamepads.iter().position(|gamepad| gamepad.id == id) {
+            Some(index) => gamepads.remove(index),
+            None => return,
+        }
+    };
+
+    let pause = {
+        let gamepads = connected_gamepads.lock().unwrap();
+        let mut ports = ports.lock().unwrap();
+        ports.disconnect_virtual(&gamepad);
+        ports.await_controller(gamepad.retro_port, &gamepads)
+    };
+
+    if let Some(listener) = listener {
+        let listener = listener.lock().unwrap();
+        listener(DeviceState::Disconnected, Device::from_virtual(&gamepad));
+
+        if pause {
+            listener(
+                DeviceState::AutoPause(gamepad.retro_port),
+                Device::from_virtual(&gamepad),
+            );
+        }
+    }
+}
+
+pub fn expired_handle(
+    listener: &Option<Arc<Mutex<DeviceStateListener>>>,
+    ports: &Arc<Mutex<PortAssignment>>,
+) {
+    let expired = ports.lock().unwrap().release_expired();
+
+    if let Some(listener) = listener {
+        let listener = listener.lock().unwrap();
+
+        for gamepad in expired {
+            listener(
+                DeviceState::ReconnectExpired(gamepad.retro_port),
+                Device::from_gamepad(&gamepad),
+            );
+        }
+    }
+}
+
+pub fn promote_handle(
+    connected_gamepads: &Arc<Mutex<Vec<RetroGamePad>>>,
+    virtual_gamepads: &Arc<Mutex<Vec<VirtualGamePad>>>,
+    max_ports: &Arc<Mutex<usize>>,
+    listener: &Option<Arc<Mutex<DeviceStateListener>>>,
+    ports: &Arc<Mutex<PortAssignment>>,
+) {
+    let promoted = {
+        let mut gamepads = connected_gamepads.lock().unwrap();
+        let mut virtual_gamepads = virtual_gamepads.lock().unwrap();
+        let max_ports = *max_ports.lock().unwrap();
+
+        ports
+            .lock()
+            .unwrap()
+            .promote_waiting(&mut gamepads, &mut virtual_gamepads, max_ports)
+    };
+
+    if let Some(listener) = listener {
+        let listener = listener.lock().unwrap();
+
+        for device in promoted {
+            listener(DeviceState::Promoted(device.retro_port), device);
+        }
+    }
+}
+
+pub fn lineup_handle(
+    connected_gamepads: &Arc<Mutex<Vec<RetroGamePad>>>,
+    max_ports: &Arc<Mutex<usize>>,
+    listener: &Option<Arc<Mutex<DeviceStateListener>>>,
+    ports: &Arc<Mutex<PortAssignment>>,
+) {
+    let changes = {
+        let mut gamepads = connected_gamepads.lock().unwrap();
+        let max_ports = *max_ports.lock().unwrap();
+
+        ports
+            .lock()
+            .unwrap()
+            .update_lineup(&mut gamepads, max_ports)
+    };
+
+    if let Some(listener) = listener {
+        let listener = listener.lock().unwrap();
+
+        for (from, gamepad) in changes {
+            listener(
+                DeviceState::PortChanged(from, gamepad.retro_port),
+                Device::from_gamepad(&gamepad),
+            );
         }
     }
 }
@@ -111,3 +295,356 @@ pub fn pressed_button_handle(
         }
     }
 }
+
+pub fn gesture_handle(
+    states: Option<Vec<(GamepadId, DeviceState)>>,
+    connected_gamepads: &Arc<Mutex<Vec<RetroGamePad>>>,
+    listener: &Option<Arc<Mutex<DeviceStateListener>>>,
+) {
+    if let (Some(states), Some(listener)) = (states, listener) {
+        for (gamepad_id, state) in states {
+            let gamepads = connected_gamepads.lock().unwrap();
+
+            if let Some(gamepad) = gamepads.iter().find(|g| g.inner_id == gamepad_id) {
+                let listener = listener.lock().unwrap();
+                listener(state, Device::from_gamepad(gamepad));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::port_assignment::JoinMode;
+    use std::{thread::sleep, time::Duration};
+
+    static EVENTS: Mutex<Vec<(Uuid, String)>> = Mutex::new(Vec::new());
+
+    fn record(state: DeviceState, device: Device) {
+        EVENTS
+            .lock()
+            .unwrap()
+            .push((device.id, format!("{state:?}")));
+    }
+
+    fn events(id: Uuid) -> Vec<String> {
+        EVENTS
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(device, _)| *device == id)
+            .map(|(_, state)| state.clone())
+            .collect()
+    }
+
+    struct Devices {
+        connected_gamepads: Arc<Mutex<Vec<RetroGamePad>>>,
+        virtual_gamepads: Arc<Mutex<Vec<VirtualGamePad>>>,
+        max_ports: Arc<Mutex<usize>>,
+        listener: Option<Arc<Mutex<DeviceStateListener>>>,
+        ports: Arc<Mutex<PortAssignment>>,
+    }
+
+    impl Devices {
+        fn new(max_ports: usize) -> Self {
+            Self {
+                connected_gamepads: Arc::new(Mutex::new(Vec::new())),
+                virtual_gamepads: Arc::new(Mutex::new(Vec::new())),
+                max_ports: Arc::new(Mutex::new(max_ports)),
+                listener: Some(Arc::new(Mutex::new(record))),
+                ports: Arc::new(Mutex::new(PortAssignment::new())),
+            }
+        }
+
+        //controles com o mesmo nome e modelo são o mesmo controle para a reconexão
+        fn connect(&self, index: usize, name: &str) -> RetroGamePad {
+            let mut gamepad = RetroGamePad::test_gamepad(index, -1);
+            gamepad.name = name.to_string();
+
+            let (gamepad, state) = add_gamepad(
+                gamepad,
+                &self.connected_gamepads,
+                &self.max_ports,
+                &self.ports,
+            );
+            record(state, Device::from_gamepad(&gamepad));
+
+            gamepad
+        }
+
+        fn connect_virtual(&self, name: &str) -> VirtualGamePad {
+            connect_virtual_handle(
+                name.to_string(),
+                &self.connected_gamepads,
+                &self.virtual_gamepads,
+                &self.max_ports,
+                &self.listener,
+                &self.ports,
+            )
+        }
+
+        fn disconnect_virtual(&self, gamepad: &VirtualGamePad) {
+            disconnect_virtual_handle(
+                gamepad.id,
+                &self.connected_gamepads,
+                &self.virtual_gamepads,
+                &self.listener,
+                &self.ports,
+            );
+        }
+
+        fn press(&self, gamepad: &RetroGamePad, button: Button, pressed: bool) {
+            for connected in &mut *self.connected_gamepads.lock().unwrap() {
+                if connected.id == gamepad.id {
+                    connected.test_press(button, pressed);
+                }
+            }
+
+            lineup_handle(
+                &self.connected_gamepads,
+                &self.max_ports,
+                &self.listener,
+                &self.ports,
+            );
+        }
+
+        fn promote(&self) {
+            promote_handle(
+                &self.connected_gamepads,
+                &self.virtual_gamepads,
+                &self.max_ports,
+                &self.listener,
+                &self.ports,
+            );
+        }
+
+        fn disconnect(&self, gamepad: &RetroGamePad) {
+            disconnect_handle(
+                gamepad.inner_id,
+                &self.connected_gamepads,
+                &self.listener,
+                &self.ports,
+            );
+        }
+    }
+
+    #[test]
+    fn controller_back_within_grace_gets_its_id_port_and_key_map() {
+        let devices = Devices::new(2);
+        devices
+            .ports
+            .lock()
+            .unwrap()
+            .set_reconnect_grace(Duration::from_secs(60));
+
+        let other = devices.connect(0, "other");
+        let first = devices.connect(1, "pad");
+        devices.connected_gamepads.lock().unwrap()[1].key_map[0].retro = 9;
+        devices.disconnect(&first);
+
+        //a porta reservada não é oferecida para outro controle
+        let stranger = devices.connect(2, "stranger");
+        assert_eq!(stranger.retro_port, -1);
+
+        let back = devices.connect(3, "pad");
+        assert_eq!(back.id, first.id);
+        assert_eq!(back.inner_id, RetroGamePad::test_gamepad(3, -1).inner_id);
+        assert_eq!(back.retro_port, 1);
+        assert_eq!(back.key_map[0].retro, 9);
+        assert_eq!(
+            events(first.id),
+            vec!["Connected", "Disconnected", "Reconnected"]
+        );
+        assert_eq!(other.retro_port, 0);
+    }
+
+    #[test]
+    fn last_controller_to_leave_is_the_first_restored() {
+        let devices = Devices::new(2);
+        devices
+            .ports
+            .lock()
+            .unwrap()
+            .set_reconnect_grace(Duration::from_secs(60));
+
+        let first = devices.connect(0, "pad");
+        let second = devices.connect(1, "pad");
+        devices.disconnect(&first);
+        devices.disconnect(&second);
+
+        assert_eq!(devices.connect(2, "pad").id, second.id);
+        assert_eq!(devices.connect(3, "pad").id, first.id);
+    }
+
+    #[test]
+    fn reservation_ends_when_the_grace_expires() {
+        let devices = Devices::new(1);
+        devices
+            .ports
+            .lock()
+            .unwrap()
+            .set_reconnect_grace(Duration::from_millis(1));
+
+        let first = devices.connect(0, "pad");
+        devices.disconnect(&first);
+        sleep(Duration::from_millis(5));
+        expired_handle(&devices.listener, &devices.ports);
+
+        assert_eq!(
+            events(first.id),
+            vec!["Connected", "Disconnected", "ReconnectExpired(0)"]
+        );
+
+        let back = devices.connect(1, "pad");
+        assert_ne!(back.id, first.id);
+        assert_eq!(back.retro_port, 0);
+        assert_eq!(events(back.id), vec!["Connected"]);
+    }
+
+    #[test]
+    fn waiting_devices_are_promoted_in_arrival_order() {
+        let devices = Devices::new(1);
+        let first = devices.connect(0, "a");
+        let second = devices.connect(1, "b");
+        let remote = devices.connect_virtual("remote");
+        let third = devices.connect(2, "c");
+        let gone = devices.connect_virtual("gone");
+
+        assert_eq!(first.retro_port, 0);
+        assert_eq!(remote.retro_port, -1);
+        devices.disconnect_virtual(&gone);
+        assert_eq!(
+            devices.ports.lock().unwrap().get_waiting(),
+            vec![second.id, remote.id, third.id]
+        );
+
+        devices.disconnect(&first);
+        devices.promote();
+        assert_eq!(events(second.id), vec!["Connected", "Promoted(0)"]);
+
+        devices.disconnect(&second);
+        devices.promote();
+        assert_eq!(events(remote.id), vec!["Connected", "Promoted(0)"]);
+        assert_eq!(devices.virtual_gamepads.lock().unwrap()[0].retro_port, 0);
+        assert!(devices.ports.lock().unwrap().is_virtual_port(0));
+        assert_eq!(events(third.id), vec!["Connected"]);
+
+        devices.disconnect_virtual(&remote);
+        devices.promote();
+        assert_eq!(events(third.id), vec!["Connected", "Promoted(0)"]);
+        assert!(devices.ports.lock().unwrap().get_waiting().is_empty());
+    }
+
+    #[test]
+    fn join_mode_joins_on_press_and_leaves_after_the_hold() {
+        let devices = Devices::new(2);
+        devices.ports.lock().unwrap().set_join_mode(Some(JoinMode {
+            leave_hold: Duration::from_millis(100),
+            ..Default::default()
+        }));
+
+        let gamepad = devices.connect(0, "pad");
+        assert_eq!(gamepad.retro_port, -1);
+        assert!(devices.ports.lock().unwrap().get_waiting().is_empty());
+
+        devices.press(&gamepad, Button::Start, true);
+        devices.press(&gamepad, Button::Start, false);
+        assert_eq!(events(gamepad.id), vec!["Connected", "PortChanged(-1, 0)"]);
+
+        //soltar antes do tempo recomeça a contagem
+        devices.press(&gamepad, Button::Select, true);
+        sleep(Duration::from_millis(60));
+        devices.press(&gamepad, Button::Select, false);
+        devices.press(&gamepad, Button::Select, true);
+        sleep(Duration::from_millis(60));
+        devices.press(&gamepad, Button::Select, true);
+        assert_eq!(events(gamepad.id).len(), 2);
+
+        sleep(Duration::from_millis(60));
+        devices.press(&gamepad, Button::Select, true);
+        assert_eq!(
+            events(gamepad.id),
+            vec!["Connected", "PortChanged(-1, 0)", "PortChanged(0, -1)"]
+        );
+
+        devices.press(&gamepad, Button::Select, false);
+        devices.ports.lock().unwrap().set_lineup_locked(true);
+        devices.press(&gamepad, Button::Start, true);
+        assert_eq!(events(gamepad.id).len(), 3);
+    }
+
+    #[test]
+    fn locked_ports_are_reserved_until_the_controller_returns() {
+        let devices = Devices::new(2);
+        let first = devices.connect(0, "a");
+        let second = devices.connect(1, "b");
+        devices.ports.lock().unwrap().set_ports_locked(true);
+
+        devices.disconnect(&first);
+        devices.disconnect(&second);
+        assert_eq!(
+            events(first.id),
+            vec!["Connected", "Disconnected", "PortReserved(0)"]
+        );
+
+        //ninguém recebe as portas reservadas enquanto elas estiverem travadas
+        let newcomer = devices.connect(2, "c");
+        devices.promote();
+        expired_handle(&devices.listener, &devices.ports);
+        assert_eq!(newcomer.retro_port, -1);
+        assert_eq!(events(newcomer.id), vec!["Connected"]);
+
+        let back = devices.connect(3, "a");
+        assert_eq!((back.id, back.retro_port), (first.id, 0));
+
+        //sem tempo de reconexão a reserva de 'b' acaba assim que as portas são destravadas
+        devices.ports.lock().unwrap().set_ports_locked(false);
+        expired_handle(&devices.listener, &devices.ports);
+        devices.promote();
+        assert_eq!(
+            events(second.id),
+            vec![
+                "Connected",
+                "Disconnected",
+                "PortReserved(1)",
+                "ReconnectExpired(1)"
+            ]
+        );
+        assert_eq!(events(newcomer.id), vec!["Connected", "Promoted(1)"]);
+    }
+
+    #[test]
+    fn auto_pause_waits_until_the_port_has_a_device_again() {
+        let devices = Devices::new(2);
+        let first = devices.connect(0, "a");
+        let remote = devices.connect_virtual("remote");
+
+        //sem o auto pause a desconexão não pausa
+        devices.disconnect(&first);
+        assert_eq!(events(first.id), vec!["Connected", "Disconnected"]);
+
+        devices.ports.lock().unwrap().set_auto_pause(true);
+        let second = devices.connect(1, "b");
+        devices.disconnect(&second);
+        devices.disconnect_virtual(&remote);
+        assert_eq!(
+            events(second.id),
+            vec!["Connected", "Disconnected", "AutoPause(0)"]
+        );
+        assert_eq!(
+            events(remote.id),
+            vec!["Connected", "Disconnected", "AutoPause(1)"]
+        );
+        assert_eq!(
+            devices.ports.lock().unwrap().get_awaiting_controller(),
+            vec![0, 1]
+        );
+
+        devices.connect(2, "c");
+        let gamepads = devices.connected_gamepads.lock().unwrap();
+        let mut ports = devices.ports.lock().unwrap();
+        ports.update_awaiting_controller(&gamepads);
+        assert_eq!(ports.get_awaiting_controller(), vec![1]);
+    }
+}
@@ -0,0 +1,34 @@
+use uuid::Uuid;
+
+#[doc = "gamepad sem um device do gilrs (ex: controles remotos), o estado é informado com set_state"]
+#[derive(Debug, Clone)]
+pub struct VirtualGamePad {
+    pub id: Uuid,
+    pub name: String,
+    #[doc = "-1 quando o gamepad está sem porta"]
+    pub retro_port: i16,
+    #[doc = "padrão RETRO_DEVICE_JOYPAD"]
+    pub retro_type: u32,
+    #[doc = "botões pressionados no mesmo formato de RETRO_DEVICE_ID_JOYPAD_MASK"]
+    pub bitmask: i16,
+    #[doc = "[esquerdo x, esquerdo y, direito x, direito y] no padrão RETRO_DEVICE_ANALOG"]
+    pub analog: [i16; 4],
+}
+
+impl VirtualGamePad {
+    pub fn new(name: String, retro_port: i16, retro_type: u32) -> VirtualGamePad {
+        Self {
+            id: Uuid::new_v4(),
+            name,
+            retro_port,
+            retro_type,
+            bitmask: 0,
+            analog: [0; 4],
+        }
+    }
+
+    pub fn set_state(&mut self, bitmask: i16, analog: [i16; 4]) {
+        self.bitmask = bitmask;
+        self.analog = analog;
+    }
+}
@@ -0,0 +1,345 @@
+use crate::{
+    devices_manager::{Device, DeviceState, DeviceStateListener},
+    gamepad::retro_gamepad::RetroGamePad,
+    input_frame::InputFrame,
+    middleware::{InputMiddleware, MiddlewareContext, HOTKEYS_STAGE},
+};
+use gilrs::Button;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HotkeyAction {
+    Menu,
+    SaveState,
+    LoadState,
+    FastForward,
+    Rewind,
+    Screenshot,
+    Custom(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hotkey {
+    #[doc = "todos os botões precisam estar pressionados ao mesmo tempo, botões sem mapeamento como o Button::Mode também são aceitos"]
+    pub combo: Vec<Button>,
+    pub action: HotkeyAction,
+    #[doc = "se verdadeiro, depois que o combo é disparado os botões do combo não chegam ao Core até todos serem soltos. Os botões pressionados antes do combo estar completo chegam ao Core normalmente"]
+    pub suppress: bool,
+}
+
+//combo disparado em um gamepad, fica ativo até todos os botões do combo serem soltos
+#[derive(Debug, Clone)]
+struct ActiveHotkey {
+    gamepad: Uuid,
+    hotkey: usize,
+    #[doc = "falso depois que algum botão foi solto, o combo dispara de novo se for completado outra vez"]
+    pressed: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Hotkeys {
+    hotkeys: Vec<Hotkey>,
+    active: Vec<ActiveHotkey>,
+}
+
+impl Hotkeys {
+    pub fn new() -> Self {
+        Self {
+            hotkeys: Vec::new(),
+            active: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, hotkey: Hotkey) {
+        self.remove(&hotkey.action);
+        self.hotkeys.push(hotkey);
+    }
+
+    pub fn remove(&mut self, action: &HotkeyAction) {
+        self.hotkeys.retain(|hotkey| &hotkey.action != action);
+        self.active.clear();
+    }
+
+    pub fn update(
+        &mut self,
+        gamepads: &[RetroGamePad],
+        listener: &Option<Arc<Mutex<DeviceStateListener>>>,
+    ) {
+        for gamepad in gamepads {
+            for (index, hotkey) in self.hotkeys.iter().enumerate() {
+                let pressed = !hotkey.combo.is_empty()
+                    && hotkey
+                        .combo
+                        .iter()
+                        .all(|button| gamepad.is_native_pressed(*button));
+
+                let any_pressed = hotkey
+                    .combo
+                    .iter()
+                    .any(|button| gamepad.is_native_pressed(*button));
+
+                let position = self
+                    .active
+                    .iter()
+                    .position(|active| active.gamepad == gamepad.id && active.hotkey == index);
+
+                let was_pressed = position.is_some_and(|position| self.active[position].pressed);
+
+                if pressed && !was_pressed {
+                    match position {
+                        Some(position) => self.active[position].pressed = true,
+                        None => self.active.push(ActiveHotkey {
+                            gamepad: gamepad.id,
+                            hotkey: index,
+                            pressed: true,
+                        }),
+                    }
+
+                    Self::notify(
+                        listener,
+                        DeviceState::HotkeyPressed(hotkey.action.clone()),
+                        gamepad,
+                    );
+                } else if !pressed && was_pressed {
+                    if let Some(position) = position {
+                        self.active[position].pressed = false;
+                    }
+
+                    Self::notify(
+                        listener,
+                        DeviceState::HotkeyReleased(hotkey.action.clone()),
+                        gamepad,
+                    );
+                }
+
+                if !any_pressed {
+                    self.active
+                        .retain(|active| active.gamepad != gamepad.id || active.hotkey != index);
+                }
+            }
+        }
+
+        self.active
+            .retain(|active| gamepads.iter().any(|gamepad| gamepad.id == active.gamepad));
+    }
+
+    #[doc = "retorna os botões (em formato de bitmask) que não devem chegar ao Core em cada porta, somente os botões que nenhum outro device da porta está pressionando"]
+    pub fn suppressed_masks(&self, context: &MiddlewareContext) -> Vec<(i16, i16)> {
+        self.active
+            .iter()
+            .filter(|active| self.hotkeys[active.hotkey].suppress)
+            .filter_map(|active| {
+                let gamepad = context
+                    .gamepads
+                    .iter()
+                    .find(|gamepad| gamepad.id == active.gamepad && gamepad.retro_port >= 0)?;
+
+                let mask = Self::combo_mask(&self.hotkeys[active.hotkey], gamepad);
+
+                Some((gamepad.retro_port, mask & !context.bitmask_without(gamepad)))
+            })
+            .collect()
+    }
+
+    fn combo_mask(hotkey: &Hotkey, gamepad: &RetroGamePad) -> i16 {
+        Self::buttons_mask(&hotkey.combo, gamepad)
+    }
+
+    fn buttons_mask(buttons: &[Button], gamepad: &RetroGamePad) -> i16 {
+        let mut mask = 0i16;
+
+        for key_map in &gamepad.key_map {
+            if buttons.contains(&key_map.native) {
+                if let Some(bit) = 1i16.checked_shl(key_map.retro) {
+                    mask |= bit;
+                }
+            }
+        }
+
+        mask
+    }
+
+    fn notify(
+        listener: &Option<Arc<Mutex<DeviceStateListener>>>,
+        state: DeviceState,
+        gamepad: &RetroGamePad,
+    ) {
+        if let Some(listener) = listener {
+            let listener = listener.lock().unwrap();
+            listener(state, Device::from_gamepad(gamepad));
+        }
+    }
+}
+
+impl InputMiddleware for Hotkeys {
+    fn name(&self) -> &str {
+        HOTKEYS_STAGE
+    }
+
+    fn process(&mut self, context: &MiddlewareContext, frame: &mut InputFrame) {
+        for (port, mask) in self.suppressed_masks(context) {
+            if let Some(input) = frame.ports.get_mut(port as usize) {
+                input.bitmask &= !mask;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{devices_manager::DevicesRequireFunctions, input_frame::PortInput};
+    use retro_ab::retro_sys::{RETRO_DEVICE_ID_JOYPAD_SELECT, RETRO_DEVICE_ID_JOYPAD_START};
+
+    static EVENTS: Mutex<Vec<(Uuid, String)>> = Mutex::new(Vec::new());
+
+    fn listener() -> Option<Arc<Mutex<DeviceStateListener>>> {
+        Some(Arc::new(Mutex::new(|state, device| {
+            EVENTS
+                .lock()
+                .unwrap()
+                .push((device.id, format!("{state:?}")));
+        })))
+    }
+
+    fn events(gamepad: &RetroGamePad) -> Vec<String> {
+        EVENTS
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(id, _)| *id == gamepad.id)
+            .map(|(_, state)| state.clone())
+            .collect()
+    }
+
+    fn menu() -> Hotkeys {
+        let mut hotkeys = Hotkeys::new();
+        hotkeys.add(Hotkey {
+            combo: vec![Button::Select, Button::Start],
+            action: HotkeyAction::Menu,
+            suppress: true,
+        });
+
+        hotkeys
+    }
+
+    fn run(hotkeys: &mut Hotkeys, gamepads: &[RetroGamePad]) -> i16 {
+        run_shared(hotkeys, gamepads, 0)
+    }
+
+    //o que chega ao Core na porta 0 depois do estágio, 'shared' são os botões que os gamepads
+    //virtuais e os InputProvider colocaram na porta
+    fn run_shared(hotkeys: &mut Hotkeys, gamepads: &[RetroGamePad], shared: i16) -> i16 {
+        hotkeys.update(gamepads, &listener());
+
+        let mut frame = InputFrame {
+            ports: vec![PortInput {
+                bitmask: shared,
+                ..Default::default()
+            }],
+            keyboard: Vec::new(),
+        };
+
+        for gamepad in gamepads {
+            frame.ports[0].bitmask |= gamepad.get_key_bitmask();
+        }
+
+        let context = MiddlewareContext {
+            frame_count: 0,
+            gamepads,
+            gilrs: None,
+            disabled_ports: &[],
+            shared_bitmasks: &[shared],
+        };
+        hotkeys.process(&context, &mut frame);
+
+        frame.ports[0].bitmask
+    }
+
+    #[test]
+    fn short_tap_of_a_combo_button_reaches_the_core() {
+        let start = 1 << RETRO_DEVICE_ID_JOYPAD_START;
+        let mut hotkeys = menu();
+        let mut gamepads = vec![RetroGamePad::test_gamepad(0, 0)];
+
+        gamepads[0].test_press(Button::Start, true);
+        assert_eq!(run(&mut hotkeys, &gamepads), start);
+
+        gamepads[0].test_press(Button::Start, false);
+        assert_eq!(run(&mut hotkeys, &gamepads), 0);
+        assert!(events(&gamepads[0]).is_empty());
+    }
+
+    #[test]
+    fn completed_combo_is_suppressed_until_every_button_is_released() {
+        let select = 1 << RETRO_DEVICE_ID_JOYPAD_SELECT;
+        let mut hotkeys = menu();
+        let mut gamepads = vec![RetroGamePad::test_gamepad(0, 0)];
+
+        gamepads[0].test_press(Button::Select, true);
+        assert_eq!(run(&mut hotkeys, &gamepads), select);
+
+        gamepads[0].test_press(Button::Start, true);
+        assert_eq!(run(&mut hotkeys, &gamepads), 0);
+        assert_eq!(events(&gamepads[0]), vec!["HotkeyPressed(Menu)"]);
+
+        //o Select continua retido depois que o Start foi solto
+        gamepads[0].test_press(Button::Start, false);
+        assert_eq!(run(&mut hotkeys, &gamepads), 0);
+        assert_eq!(
+            events(&gamepads[0]),
+            vec!["HotkeyPressed(Menu)", "HotkeyReleased(Menu)"]
+        );
+
+        gamepads[0].test_press(Button::Select, false);
+        assert_eq!(run(&mut hotkeys, &gamepads), 0);
+
+        gamepads[0].test_press(Button::Select, true);
+        assert_eq!(run(&mut hotkeys, &gamepads), select);
+    }
+
+    #[test]
+    fn combo_without_suppress_reaches_the_core() {
+        let both = 1 << RETRO_DEVICE_ID_JOYPAD_SELECT | 1 << RETRO_DEVICE_ID_JOYPAD_START;
+        let mut hotkeys = Hotkeys::new();
+        hotkeys.add(Hotkey {
+            combo: vec![Button::Select, Button::Start],
+            action: HotkeyAction::Screenshot,
+            suppress: false,
+        });
+        let mut gamepads = vec![RetroGamePad::test_gamepad(0, -1)];
+
+        gamepads[0].test_press(Button::Select, true);
+        gamepads[0].test_press(Button::Start, true);
+        run(&mut hotkeys, &gamepads);
+
+        //um gamepad sem porta também dispara os hotkeys
+        assert_eq!(events(&gamepads[0]), vec!["HotkeyPressed(Screenshot)"]);
+
+        gamepads[0].retro_port = 0;
+        assert_eq!(run(&mut hotkeys, &gamepads), both);
+    }
+
+    #[test]
+    fn buttons_held_by_other_devices_of_the_port_reach_the_core() {
+        let select = 1 << RETRO_DEVICE_ID_JOYPAD_SELECT;
+        let start = 1 << RETRO_DEVICE_ID_JOYPAD_START;
+        let mut hotkeys = menu();
+        let mut gamepads = vec![
+            RetroGamePad::test_gamepad(0, 0),
+            RetroGamePad::test_gamepad(1, 0),
+        ];
+
+        gamepads[0].test_press(Button::Select, true);
+        gamepads[0].test_press(Button::Start, true);
+        gamepads[1].test_press(Button::Start, true);
+        assert_eq!(run_shared(&mut hotkeys, &gamepads, select), select | start);
+        assert_eq!(events(&gamepads[0]), vec!["HotkeyPressed(Menu)"]);
+        assert!(events(&gamepads[1]).is_empty());
+
+        gamepads[1].test_press(Button::Start, false);
+        assert_eq!(run_shared(&mut hotkeys, &gamepads, select), select);
+        assert_eq!(run(&mut hotkeys, &gamepads), 0);
+    }
+}
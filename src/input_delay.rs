@@ -0,0 +1,161 @@
+use crate::{
+    input_frame::{InputFrame, PortInput},
+    middleware::{swapped_port, InputMiddleware, MiddlewareContext, DELAY_STAGE},
+};
+use std::collections::VecDeque;
+
+#[derive(Debug, Clone)]
+struct PortDelay {
+    port: i16,
+    frames: usize,
+    queue: VecDeque<PortInput>,
+}
+
+/// # Input delay
+///
+/// Cada porta com atraso guarda o input dos últimos frames em uma fila e responde ao Core com o
+/// input capturado 'frames' frames antes. Enquanto a fila não enche a porta fica solta.
+#[derive(Debug, Clone, Default)]
+pub struct InputDelay {
+    ports: Vec<PortDelay>,
+}
+
+impl InputDelay {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[doc = "com 0 frames o atraso da porta é removido"]
+    pub fn set_delay(&mut self, port: i16, frames: usize) {
+        if frames == 0 {
+            self.ports.retain(|delay| delay.port != port);
+            return;
+        }
+
+        match self.ports.iter_mut().find(|delay| delay.port == port) {
+            Some(delay) => {
+                delay.frames = frames;
+
+                while delay.queue.len() > frames {
+                    delay.queue.pop_front();
+                }
+            }
+            None => self.ports.push(PortDelay {
+                port,
+                frames,
+                queue: VecDeque::with_capacity(frames + 1),
+            }),
+        }
+    }
+
+    #[doc = "o atraso e a fila das duas portas são trocados, para acompanhar os jogadores"]
+    pub fn swap_ports(&mut self, port: i16, other: i16) {
+        for delay in &mut self.ports {
+            delay.port = swapped_port(delay.port, port, other);
+        }
+    }
+
+    pub fn get_delay(&self, port: i16) -> usize {
+        self.ports
+            .iter()
+            .find(|delay| delay.port == port)
+            .map_or(0, |delay| delay.frames)
+    }
+}
+
+impl InputMiddleware for InputDelay {
+    fn name(&self) -> &str {
+        DELAY_STAGE
+    }
+
+    fn process(&mut self, context: &MiddlewareContext, frame: &mut InputFrame) {
+        for delay in &mut self.ports {
+            if delay.port < 0 || !context.is_port_enabled(delay.port) {
+                continue;
+            }
+
+            let index = delay.port as usize;
+
+            if frame.ports.len() <= index {
+                frame.ports.resize(index + 1, PortInput::default());
+            }
+
+            delay.queue.push_back(frame.ports[index]);
+
+            frame.ports[index] = if delay.queue.len() > delay.frames {
+                delay.queue.pop_front().unwrap_or_default()
+            } else {
+                PortInput::default()
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(delay: &mut InputDelay, bitmasks: [i16; 2]) -> [i16; 2] {
+        let mut frame = InputFrame {
+            ports: bitmasks
+                .iter()
+                .map(|bitmask| PortInput {
+                    bitmask: *bitmask,
+                    ..Default::default()
+                })
+                .collect(),
+            keyboard: Vec::new(),
+        };
+        let context = MiddlewareContext {
+            frame_count: 0,
+            gamepads: &[],
+            gilrs: None,
+            disabled_ports: &[],
+            shared_bitmasks: &[],
+        };
+
+        delay.process(&context, &mut frame);
+        [frame.ports[0].bitmask, frame.ports[1].bitmask]
+    }
+
+    #[test]
+    fn port_answers_with_the_input_of_frames_before() {
+        let mut delay = InputDelay::new();
+        delay.set_delay(0, 2);
+
+        assert_eq!(step(&mut delay, [1, 1]), [0, 1]);
+        assert_eq!(step(&mut delay, [2, 2]), [0, 2]);
+        assert_eq!(step(&mut delay, [3, 3]), [1, 3]);
+        assert_eq!(step(&mut delay, [4, 4]), [2, 4]);
+    }
+
+    #[test]
+    fn shorter_delay_drops_the_oldest_frames() {
+        let mut delay = InputDelay::new();
+        delay.set_delay(0, 3);
+
+        for bitmask in 1..=3 {
+            step(&mut delay, [bitmask, 0]);
+        }
+
+        delay.set_delay(0, 1);
+        assert_eq!(delay.get_delay(0), 1);
+        assert_eq!(step(&mut delay, [4, 0]), [3, 0]);
+
+        delay.set_delay(0, 0);
+        assert_eq!(delay.get_delay(0), 0);
+        assert_eq!(step(&mut delay, [5, 0]), [5, 0]);
+    }
+
+    #[test]
+    fn swap_moves_the_queue_with_the_player() {
+        let mut delay = InputDelay::new();
+        delay.set_delay(0, 1);
+        step(&mut delay, [7, 0]);
+
+        delay.swap_ports(0, 1);
+        assert_eq!(delay.get_delay(0), 0);
+        assert_eq!(delay.get_delay(1), 1);
+        assert_eq!(step(&mut delay, [0, 8]), [0, 7]);
+    }
+}
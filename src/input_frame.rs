@@ -0,0 +1,187 @@
+use retro_ab::retro_sys::{
+    RETRO_DEVICE_ID_ANALOG_Y, RETRO_DEVICE_ID_JOYPAD_MASK, RETRO_DEVICE_INDEX_ANALOG_BUTTON,
+    RETRO_DEVICE_INDEX_ANALOG_RIGHT,
+};
+
+#[doc = "quantidade de RETRO_DEVICE_ID_JOYPAD_* que cabem no bitmask de uma porta"]
+pub const JOYPAD_BUTTON_COUNT: u32 = 16;
+
+#[doc = "como os eixos analógicos são combinados quando vários devices estão na mesma porta"]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AnalogMerge {
+    #[doc = "em cada eixo fica o valor de maior magnitude"]
+    #[default]
+    LargestMagnitude,
+    #[doc = "os valores de cada eixo são somados, limitados ao intervalo de i16"]
+    Sum,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PortInput {
+    #[doc = "botões RETRO_DEVICE_ID_JOYPAD_* no mesmo formato de RETRO_DEVICE_ID_JOYPAD_MASK"]
+    pub bitmask: i16,
+    #[doc = "[esquerdo x, esquerdo y, direito x, direito y] no padrão RETRO_DEVICE_ANALOG"]
+    pub analog: [i16; 4],
+}
+
+impl PortInput {
+    #[doc = "os botões são combinados com OU e em cada eixo analógico fica o valor de maior magnitude"]
+    pub fn combine(&self, other: &PortInput) -> PortInput {
+        self.merge(other, AnalogMerge::LargestMagnitude)
+    }
+
+    #[doc = "os botões são combinados com OU e os eixos analógicos de acordo com 'analog_merge'"]
+    pub fn merge(&self, other: &PortInput, analog_merge: AnalogMerge) -> PortInput {
+        let mut analog = self.analog;
+
+        for (value, other) in analog.iter_mut().zip(other.analog) {
+            *value = match analog_merge {
+                AnalogMerge::LargestMagnitude if other.unsigned_abs() > value.unsigned_abs() => {
+                    other
+                }
+                AnalogMerge::LargestMagnitude => *value,
+                AnalogMerge::Sum => value.saturating_add(other),
+            };
+        }
+
+        PortInput {
+            bitmask: self.bitmask | other.bitmask,
+            analog,
+        }
+    }
+
+    pub fn get_key_pressed(&self, key_id: i16) -> i16 {
+        if key_id as u32 == RETRO_DEVICE_ID_JOYPAD_MASK {
+            self.bitmask
+        } else if (0..JOYPAD_BUTTON_COUNT as i16).contains(&key_id) {
+            (self.bitmask >> key_id) & 1
+        } else {
+            0
+        }
+    }
+
+    pub fn get_analog(&self, index: i16, id: i16) -> i16 {
+        if index as u32 == RETRO_DEVICE_INDEX_ANALOG_BUTTON {
+            return if self.get_key_pressed(id) == 1 {
+                i16::MAX
+            } else {
+                0
+            };
+        }
+
+        if index as u32 > RETRO_DEVICE_INDEX_ANALOG_RIGHT || id as u32 > RETRO_DEVICE_ID_ANALOG_Y {
+            return 0;
+        }
+
+        self.analog[(index * 2 + id) as usize]
+    }
+}
+
+#[doc = "estado de todas as portas e do teclado em um único frame (input_poll_callback)"]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InputFrame {
+    pub ports: Vec<PortInput>,
+    #[doc = "teclas RETROK_* pressionadas"]
+    pub keyboard: Vec<u32>,
+}
+
+impl InputFrame {
+    pub fn port(&self, port: i16) -> Option<&PortInput> {
+        if port < 0 {
+            return None;
+        }
+
+        self.ports.get(port as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use retro_ab::retro_sys::{RETRO_DEVICE_ID_ANALOG_X, RETRO_DEVICE_INDEX_ANALOG_LEFT};
+
+    fn input(bitmask: i16, analog: [i16; 4]) -> PortInput {
+        PortInput { bitmask, analog }
+    }
+
+    #[test]
+    fn merge_combines_buttons_with_or() {
+        let first = input(0b0011, [0; 4]);
+        let second = input(i16::MIN | 0b0110, [0; 4]);
+
+        for analog_merge in [AnalogMerge::LargestMagnitude, AnalogMerge::Sum] {
+            let merged = first.merge(&second, analog_merge);
+            assert_eq!(merged.bitmask, i16::MIN | 0b0111);
+        }
+    }
+
+    #[test]
+    fn merge_keeps_the_largest_magnitude() {
+        let first = input(0, [100, -300, i16::MIN, 5]);
+        let second = input(0, [-200, 200, i16::MAX, -5]);
+
+        //em um empate o valor do primeiro device é mantido
+        assert_eq!(
+            first.merge(&second, AnalogMerge::LargestMagnitude).analog,
+            [-200, -300, i16::MIN, 5]
+        );
+        assert_eq!(
+            first.combine(&second),
+            first.merge(&second, AnalogMerge::LargestMagnitude)
+        );
+    }
+
+    #[test]
+    fn merge_sum_saturates() {
+        let first = input(0, [i16::MAX, i16::MIN, 100, -100]);
+        let second = input(0, [1, -1, -300, 100]);
+
+        assert_eq!(
+            first.merge(&second, AnalogMerge::Sum).analog,
+            [i16::MAX, i16::MIN, -200, 0]
+        );
+    }
+
+    #[test]
+    fn reads_buttons_and_analog() {
+        let port = input(i16::MIN | 0b10, [1, 2, 3, 4]);
+
+        assert_eq!(port.get_key_pressed(1), 1);
+        assert_eq!(port.get_key_pressed(0), 0);
+        assert_eq!(port.get_key_pressed(15), 1);
+        assert_eq!(port.get_key_pressed(16), 0);
+        assert_eq!(port.get_key_pressed(-1), 0);
+        assert_eq!(
+            port.get_key_pressed(RETRO_DEVICE_ID_JOYPAD_MASK as i16),
+            port.bitmask
+        );
+
+        let left = RETRO_DEVICE_INDEX_ANALOG_LEFT as i16;
+        let right = RETRO_DEVICE_INDEX_ANALOG_RIGHT as i16;
+        let x = RETRO_DEVICE_ID_ANALOG_X as i16;
+        let y = RETRO_DEVICE_ID_ANALOG_Y as i16;
+
+        assert_eq!(port.get_analog(left, x), 1);
+        assert_eq!(port.get_analog(left, y), 2);
+        assert_eq!(port.get_analog(right, x), 3);
+        assert_eq!(port.get_analog(right, y), 4);
+        assert_eq!(port.get_analog(right, y + 1), 0);
+
+        let button = RETRO_DEVICE_INDEX_ANALOG_BUTTON as i16;
+        assert_eq!(port.get_analog(button, 1), i16::MAX);
+        assert_eq!(port.get_analog(button, 0), 0);
+        assert_eq!(port.get_analog(button + 1, 0), 0);
+    }
+
+    #[test]
+    fn negative_ports_are_not_found() {
+        let frame = InputFrame {
+            ports: vec![PortInput::default()],
+            keyboard: Vec::new(),
+        };
+
+        assert!(frame.port(0).is_some());
+        assert!(frame.port(1).is_none());
+        assert!(frame.port(-1).is_none());
+    }
+}
@@ -0,0 +1,337 @@
+use crate::{
+    input_frame::{InputFrame, PortInput},
+    middleware::{swapped_port, InputMiddleware, MiddlewareContext, MACROS_STAGE},
+    movie::{read_frame, write_frame},
+};
+use gilrs::Button;
+use retro_ab::{erro_handle::ErroHandle, retro_sys::retro_log_level};
+
+#[doc = "sequência de input de uma porta, um item por frame"]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InputMacro {
+    pub name: String,
+    pub frames: Vec<PortInput>,
+}
+
+impl InputMacro {
+    #[doc = "o crate não possui perfis, use isso para salvar a macro no perfil do frontend"]
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ErroHandle> {
+        let mut bytes = Vec::new();
+        let name = self.name.as_bytes();
+        let too_long = |message: &str| ErroHandle {
+            level: retro_log_level::RETRO_LOG_ERROR,
+            message: message.to_string(),
+        };
+
+        let name_len = u16::try_from(name.len())
+            .map_err(|_| too_long("o nome da macro deve ter no máximo 65535 bytes"))?;
+        let frames_len = u32::try_from(self.frames.len())
+            .map_err(|_| too_long("a macro possui frames demais"))?;
+
+        bytes.extend_from_slice(&name_len.to_le_bytes());
+        bytes.extend_from_slice(name);
+        bytes.extend_from_slice(&frames_len.to_le_bytes());
+
+        for input in &self.frames {
+            let frame = InputFrame {
+                ports: vec![*input],
+                keyboard: Vec::new(),
+            };
+            write_frame(&mut bytes, &frame, 1)?;
+        }
+
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ErroHandle> {
+        let invalid = || ErroHandle {
+            level: retro_log_level::RETRO_LOG_ERROR,
+            message: "macro inválida".to_string(),
+        };
+
+        let len = u16::from_le_bytes(bytes.get(0..2).ok_or_else(invalid)?.try_into().unwrap());
+        let name_end = 2 + len as usize;
+        let name = bytes.get(2..name_end).ok_or_else(invalid)?;
+        let count = bytes.get(name_end..name_end + 4).ok_or_else(invalid)?;
+        let count = u32::from_le_bytes(count.try_into().unwrap());
+
+        let mut reader = &bytes[name_end + 4..];
+        let mut frames = Vec::new();
+
+        for _ in 0..count {
+            let frame = read_frame(&mut reader, 1)?.ok_or_else(invalid)?;
+            frames.push(frame.ports[0]);
+        }
+
+        Ok(Self {
+            name: String::from_utf8(name.to_vec()).map_err(|_| invalid())?,
+            frames,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MacroBinding {
+    pub name: String,
+    pub port: i16,
+    #[doc = "use um botão sem mapeamento (ex: Button::Mode ou Button::C), o botão continua chegando ao Core se estiver mapeado"]
+    pub trigger: Button,
+}
+
+#[derive(Debug, Clone)]
+struct PlayingMacro {
+    port: i16,
+    frames: Vec<PortInput>,
+    position: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Macros {
+    macros: Vec<InputMacro>,
+    bindings: Vec<MacroBinding>,
+    recording: Option<(i16, Vec<PortInput>)>,
+    playing: Vec<PlayingMacro>,
+    triggers_pressed: Vec<(i16, Button)>,
+}
+
+impl Macros {
+    pub fn new() -> Self {
+        Self {
+            macros: Vec::new(),
+            bindings: Vec::new(),
+            recording: None,
+            playing: Vec::new(),
+            triggers_pressed: Vec::new(),
+        }
+    }
+
+    pub fn start_recording(&mut self, port: i16) {
+        self.recording = Some((port, Vec::new()));
+    }
+
+    #[doc = "finaliza a gravação e guarda a macro com o nome informado"]
+    pub fn stop_recording(&mut self, name: &str) -> Option<InputMacro> {
+        let (_, frames) = self.recording.take()?;
+        let input_macro = InputMacro {
+            name: name.to_string(),
+            frames,
+        };
+
+        self.add(input_macro.clone());
+
+        Some(input_macro)
+    }
+
+    pub fn add(&mut self, input_macro: InputMacro) {
+        self.remove(&input_macro.name);
+        self.macros.push(input_macro);
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.macros.retain(|m| m.name != name);
+    }
+
+    pub fn get_macros(&self) -> Vec<InputMacro> {
+        self.macros.clone()
+    }
+
+    pub fn bind(&mut self, binding: MacroBinding) {
+        self.unbind(binding.port, binding.trigger);
+        self.bindings.push(binding);
+    }
+
+    pub fn unbind(&mut self, port: i16, trigger: Button) {
+        self.bindings
+            .retain(|binding| binding.port != port || binding.trigger != trigger);
+    }
+
+    #[doc = "as ligações, a gravação e as macros em execução das duas portas são trocadas, para acompanhar os jogadores"]
+    pub fn swap_ports(&mut self, port: i16, other: i16) {
+        for binding in &mut self.bindings {
+            binding.port = swapped_port(binding.port, port, other);
+        }
+
+        if let Some((recording, _)) = &mut self.recording {
+            *recording = swapped_port(*recording, port, other);
+        }
+
+        for playing in &mut self.playing {
+            playing.port = swapped_port(playing.port, port, other);
+        }
+
+        for (trigger_port, _) in &mut self.triggers_pressed {
+            *trigger_port = swapped_port(*trigger_port, port, other);
+        }
+    }
+
+    #[doc = "grava o input da porta e substitui o input das portas com macros em execução"]
+    fn update(&mut self, context: &MiddlewareContext, frame: &mut InputFrame) {
+        if let Some((port, frames)) = &mut self.recording {
+            if context.is_port_enabled(*port) {
+                frames.push(frame.port(*port).copied().unwrap_or_default());
+            }
+        }
+
+        self.check_triggers(context);
+
+        for playing in &mut self.playing {
+            if playing.port < 0 || !context.is_port_enabled(playing.port) {
+                continue;
+            }
+
+            let port = playing.port as usize;
+
+            if frame.ports.len() <= port {
+                frame.ports.resize(port + 1, PortInput::default());
+            }
+
+            frame.ports[port] = playing.frames[playing.position];
+            playing.position += 1;
+        }
+
+        self.playing
+            .retain(|playing| playing.position < playing.frames.len());
+    }
+
+    fn check_triggers(&mut self, context: &MiddlewareContext) {
+        for binding in &self.bindings {
+            if !context.is_port_enabled(binding.port) {
+                continue;
+            }
+
+            let pressed = context.gamepads.iter().any(|gamepad| {
+                gamepad.retro_port == binding.port && gamepad.is_native_pressed(binding.trigger)
+            });
+
+            let key = (binding.port, binding.trigger);
+            let was_pressed = self.triggers_pressed.contains(&key);
+
+            if pressed && !was_pressed {
+                self.triggers_pressed.push(key);
+
+                let is_playing = self.playing.iter().any(|p| p.port == binding.port);
+                let input_macro = self.macros.iter().find(|m| m.name == binding.name);
+
+                if let (false, Some(input_macro)) = (is_playing, input_macro) {
+                    if !input_macro.frames.is_empty() {
+                        self.playing.push(PlayingMacro {
+                            port: binding.port,
+                            frames: input_macro.frames.clone(),
+                            position: 0,
+                        });
+                    }
+                }
+            } else if !pressed && was_pressed {
+                self.triggers_pressed.retain(|k| *k != key);
+            }
+        }
+    }
+}
+
+impl InputMiddleware for Macros {
+    fn name(&self) -> &str {
+        MACROS_STAGE
+    }
+
+    fn process(&mut self, context: &MiddlewareContext, frame: &mut InputFrame) {
+        self.update(context, frame);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> InputMacro {
+        InputMacro {
+            name: "hadouken ↓↘→".to_string(),
+            frames: vec![
+                PortInput {
+                    bitmask: 1 << 5,
+                    analog: [0; 4],
+                },
+                PortInput {
+                    bitmask: 0,
+                    analog: [100, -100, 0, i16::MIN],
+                },
+                PortInput::default(),
+            ],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let input_macro = sample();
+        let bytes = input_macro.to_bytes().unwrap();
+
+        assert_eq!(InputMacro::from_bytes(&bytes).unwrap(), input_macro);
+
+        let empty = InputMacro::default();
+        assert_eq!(
+            InputMacro::from_bytes(&empty.to_bytes().unwrap()).unwrap(),
+            empty
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_bytes() {
+        let bytes = sample().to_bytes().unwrap();
+
+        assert!(InputMacro::from_bytes(&[]).is_err());
+        assert!(InputMacro::from_bytes(&bytes[..5]).is_err());
+        assert!(InputMacro::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+
+        let mut invalid_name = InputMacro::default().to_bytes().unwrap();
+        invalid_name.splice(0..2, [1, 0, 0xff]);
+        assert!(InputMacro::from_bytes(&invalid_name).is_err());
+    }
+
+    #[test]
+    fn rejects_names_too_long() {
+        let input_macro = InputMacro {
+            name: "a".repeat(u16::MAX as usize + 1),
+            frames: Vec::new(),
+        };
+
+        assert!(input_macro.to_bytes().is_err());
+    }
+
+    #[test]
+    fn add_replaces_macros_with_the_same_name() {
+        let mut macros = Macros::new();
+        macros.add(sample());
+        macros.add(InputMacro {
+            name: sample().name,
+            frames: Vec::new(),
+        });
+
+        assert_eq!(macros.get_macros().len(), 1);
+        assert!(macros.get_macros()[0].frames.is_empty());
+    }
+
+    #[test]
+    fn bindings_follow_swapped_ports() {
+        let mut macros = Macros::new();
+        macros.bind(MacroBinding {
+            name: "a".to_string(),
+            port: 0,
+            trigger: Button::Mode,
+        });
+        macros.bind(MacroBinding {
+            name: "b".to_string(),
+            port: 2,
+            trigger: Button::Mode,
+        });
+        macros.start_recording(1);
+
+        macros.swap_ports(0, 1);
+
+        let ports: Vec<i16> = macros.bindings.iter().map(|b| b.port).collect();
+        assert_eq!(ports, vec![1, 2]);
+        assert_eq!(macros.recording.as_ref().unwrap().0, 0);
+
+        macros.unbind(1, Button::Mode);
+        assert_eq!(macros.bindings.len(), 1);
+        assert_eq!(macros.bindings[0].name, "b");
+    }
+}
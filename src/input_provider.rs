@@ -0,0 +1,23 @@
+use crate::input_frame::PortInput;
+use std::fmt::Debug;
+
+#[doc = "permite que o input de uma porta venha do código do frontend (bots, scripts, testes) ao invés de um gamepad"]
+pub trait InputProvider: Debug + Send {
+    #[doc = "chamado uma vez por frame (input_poll_callback), deve retornar o estado da porta nesse frame"]
+    fn poll(&mut self, frame: u64) -> PortInput;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProviderMode {
+    #[doc = "o input do provider substitui o dos devices conectados na porta"]
+    Replace,
+    #[doc = "o input do provider é combinado com o dos devices conectados na porta, ex: um provider que traduz as teclas do teclado para o joypad"]
+    Combine,
+}
+
+#[derive(Debug)]
+pub struct AttachedProvider {
+    pub port: i16,
+    pub mode: ProviderMode,
+    pub provider: Box<dyn InputProvider>,
+}
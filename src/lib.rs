@@ -3,12 +3,44 @@ extern crate gilrs;
 extern crate lazy_static;
 
 mod constants;
+#[cfg(all(unix, feature = "control-socket"))]
+mod control_socket;
 mod gamepad;
+mod hotkeys;
+mod input_delay;
+mod input_frame;
+mod input_macro;
+mod input_provider;
 mod keyboard;
+mod middleware;
+mod port_assignment;
+mod port_policy;
 mod retro_ab_controller;
+#[cfg(feature = "scripting")]
+mod scripting;
 mod state_thread;
+mod turbo;
 
 pub mod devices_manager;
+pub mod movie;
+pub mod netplay;
+pub mod overlay_server;
+pub mod remote_server;
+pub mod test_harness;
 pub use retro_ab_controller::{
     input_poll_callback, input_state_callback, rumble_callback, RetroAbController,
 };
+pub use gamepad::{
+    button_gestures::GestureConfig, retro_gamepad::RetroGamePad, virtual_gamepad::VirtualGamePad,
+};
+pub use hotkeys::{Hotkey, HotkeyAction};
+pub use input_frame::{AnalogMerge, InputFrame, PortInput};
+pub use input_macro::{InputMacro, MacroBinding};
+pub use input_provider::{InputProvider, ProviderMode};
+pub use middleware::{
+    InputMiddleware, MiddlewareContext, DELAY_STAGE, HOTKEYS_STAGE, MACROS_STAGE, SCRIPT_STAGE,
+    TURBO_STAGE,
+};
+pub use port_assignment::{JoinMode, PortAssignment};
+pub use port_policy::{LowestFreePort, ManualOnly, PortPolicy, RememberLastPort};
+pub use turbo::{TurboButton, TurboMode};
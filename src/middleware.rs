@@ -0,0 +1,303 @@
+use crate::{
+    devices_manager::DevicesRequireFunctions, gamepad::retro_gamepad::RetroGamePad,
+    input_frame::InputFrame,
+};
+use gilrs::Gilrs;
+use retro_ab::{erro_handle::ErroHandle, retro_sys::retro_log_level};
+use std::{
+    fmt::Debug,
+    sync::{Arc, Mutex},
+};
+
+pub static HOTKEYS_STAGE: &str = "hotkeys";
+pub static TURBO_STAGE: &str = "turbo";
+pub static MACROS_STAGE: &str = "macros";
+pub static SCRIPT_STAGE: &str = "script";
+pub static DELAY_STAGE: &str = "delay";
+
+#[derive(Debug)]
+pub struct MiddlewareContext<'a> {
+    #[doc = "quantidade de input_poll_callback recebidos, incluindo o atual"]
+    pub frame_count: u64,
+    pub gamepads: &'a [RetroGamePad],
+    #[doc = "None quando o gilrs não pôde ser iniciado, por exemplo no CI sem udev"]
+    pub gilrs: Option<&'a Gilrs>,
+    #[doc = "portas em que o estágio atual está desabilitado, o estágio não deve avançar o estado dessas portas"]
+    pub disabled_ports: &'a [i16],
+    #[doc = "botões de cada porta que não vêm de 'gamepads', como os dos gamepads virtuais e dos InputProvider"]
+    pub shared_bitmasks: &'a [i16],
+}
+
+impl MiddlewareContext<'_> {
+    pub fn is_port_enabled(&self, port: i16) -> bool {
+        !self.disabled_ports.contains(&port)
+    }
+
+    #[doc = "botões pressionados por todos os outros devices da porta do gamepad, use para remover do frame somente o que veio desse gamepad"]
+    pub fn bitmask_without(&self, gamepad: &RetroGamePad) -> i16 {
+        let shared = if gamepad.retro_port >= 0 {
+            *self
+                .shared_bitmasks
+                .get(gamepad.retro_port as usize)
+                .unwrap_or(&0)
+        } else {
+            0
+        };
+
+        self.gamepads
+            .iter()
+            .filter(|other| other.id != gamepad.id && other.retro_port == gamepad.retro_port)
+            .fold(shared, |bitmask, other| bitmask | other.get_key_bitmask())
+    }
+}
+
+//a porta que 'value' passa a ser depois de trocar os jogadores das portas 'port' e 'other'
+pub(crate) fn swapped_port(value: i16, port: i16, other: i16) -> i16 {
+    if value == port {
+        other
+    } else if value == other {
+        port
+    } else {
+        value
+    }
+}
+
+#[doc = "um estágio que transforma o input entre os devices e o Core, executado uma vez por frame"]
+pub trait InputMiddleware: Debug + Send {
+    #[doc = "nome único do estágio, usado para definir a ordem e habilitar ou desabilitar o estágio por porta"]
+    fn name(&self) -> &str;
+
+    fn process(&mut self, context: &MiddlewareContext, frame: &mut InputFrame);
+}
+
+#[derive(Debug)]
+struct Stage {
+    middleware: Arc<Mutex<dyn InputMiddleware>>,
+    name: String,
+    disabled_ports: Vec<i16>,
+}
+
+/// # Middleware pipeline
+///
+/// Os estágios são executados na ordem da lista, a ordem padrão é:
+/// hotkeys (remove os botões dos combos) → turbo → macros → script → delay → estágios do frontend.
+///
+/// Quando um estágio está desabilitado em uma porta, o input dessa porta volta a ser o que era antes
+/// do estágio ser executado. O estágio recebe essas portas em `MiddlewareContext::disabled_ports`
+/// para não avançar o estado delas (ex: a fase do turbo ou a fila do delay).
+#[derive(Debug)]
+pub struct MiddlewarePipeline {
+    stages: Vec<Stage>,
+}
+
+impl MiddlewarePipeline {
+    pub fn new() -> Self {
+        Self { stages: Vec::new() }
+    }
+
+    pub fn push(&mut self, middleware: Arc<Mutex<dyn InputMiddleware>>) -> Result<(), ErroHandle> {
+        let index = self.stages.len();
+        self.insert(index, middleware)
+    }
+
+    pub fn insert_before(
+        &mut self,
+        stage_name: &str,
+        middleware: Arc<Mutex<dyn InputMiddleware>>,
+    ) -> Result<(), ErroHandle> {
+        let index = self.position(stage_name)?;
+        self.insert(index, middleware)
+    }
+
+    pub fn insert_after(
+        &mut self,
+        stage_name: &str,
+        middleware: Arc<Mutex<dyn InputMiddleware>>,
+    ) -> Result<(), ErroHandle> {
+        let index = self.position(stage_name)?;
+        self.insert(index + 1, middleware)
+    }
+
+    pub fn remove(&mut self, stage_name: &str) -> Option<Arc<Mutex<dyn InputMiddleware>>> {
+        let index = self.position(stage_name).ok()?;
+        Some(self.stages.remove(index).middleware)
+    }
+
+    pub fn set_port_enabled(
+        &mut self,
+        stage_name: &str,
+        port: i16,
+        enabled: bool,
+    ) -> Result<(), ErroHandle> {
+        let index = self.position(stage_name)?;
+        let disabled_ports = &mut self.stages[index].disabled_ports;
+
+        disabled_ports.retain(|p| *p != port);
+
+        if !enabled {
+            disabled_ports.push(port);
+        }
+
+        Ok(())
+    }
+
+    pub fn get_stage_names(&self) -> Vec<String> {
+        self.stages.iter().map(|stage| stage.name.clone()).collect()
+    }
+
+    pub fn process(&self, context: &MiddlewareContext, frame: &mut InputFrame) {
+        for stage in &self.stages {
+            let before = if stage.disabled_ports.is_empty() {
+                None
+            } else {
+                Some(frame.ports.clone())
+            };
+
+            let context = MiddlewareContext {
+                disabled_ports: &stage.disabled_ports,
+                ..*context
+            };
+
+            stage.middleware.lock().unwrap().process(&context, frame);
+
+            if let Some(before) = before {
+                for port in &stage.disabled_ports {
+                    let port = *port as usize;
+
+                    if let (Some(input), Some(previous)) =
+                        (frame.ports.get_mut(port), before.get(port))
+                    {
+                        *input = *previous;
+                    }
+                }
+            }
+        }
+    }
+
+    fn insert(
+        &mut self,
+        index: usize,
+        middleware: Arc<Mutex<dyn InputMiddleware>>,
+    ) -> Result<(), ErroHandle> {
+        let name = middleware.lock().unwrap().name().to_string();
+
+        if self.position(&name).is_ok() {
+            return Err(ErroHandle {
+                level: retro_log_level::RETRO_LOG_WARN,
+                message: format!("já existe um estágio com o nome '{name}'"),
+            });
+        }
+
+        self.stages.insert(
+            index,
+            Stage {
+                middleware,
+                name,
+                disabled_ports: Vec::new(),
+            },
+        );
+
+        Ok(())
+    }
+
+    fn position(&self, stage_name: &str) -> Result<usize, ErroHandle> {
+        self.stages
+            .iter()
+            .position(|stage| stage.name == stage_name)
+            .ok_or_else(|| ErroHandle {
+                level: retro_log_level::RETRO_LOG_WARN,
+                message: format!("o estágio '{stage_name}' não existe"),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input_frame::PortInput;
+
+    //adiciona o próprio dígito ao analog[0] de todas as portas, para mostrar a ordem dos estágios
+    #[derive(Debug)]
+    struct Digit {
+        name: String,
+        digit: i16,
+        disabled_seen: Vec<i16>,
+    }
+
+    impl InputMiddleware for Digit {
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        fn process(&mut self, context: &MiddlewareContext, frame: &mut InputFrame) {
+            self.disabled_seen = context.disabled_ports.to_vec();
+
+            for input in &mut frame.ports {
+                input.analog[0] = input.analog[0] * 10 + self.digit;
+            }
+        }
+    }
+
+    fn digit(digit: i16) -> Arc<Mutex<Digit>> {
+        Arc::new(Mutex::new(Digit {
+            name: digit.to_string(),
+            digit,
+            disabled_seen: Vec::new(),
+        }))
+    }
+
+    fn run(pipeline: &MiddlewarePipeline) -> Vec<i16> {
+        let mut frame = InputFrame {
+            ports: vec![PortInput::default(); 2],
+            keyboard: Vec::new(),
+        };
+        let context = MiddlewareContext {
+            frame_count: 1,
+            gamepads: &[],
+            gilrs: None,
+            disabled_ports: &[],
+            shared_bitmasks: &[],
+        };
+
+        pipeline.process(&context, &mut frame);
+        frame.ports.iter().map(|input| input.analog[0]).collect()
+    }
+
+    #[test]
+    fn stages_run_in_list_order() {
+        let mut pipeline = MiddlewarePipeline::new();
+        pipeline.push(digit(1)).unwrap();
+        pipeline.push(digit(3)).unwrap();
+        pipeline.insert_before("3", digit(2)).unwrap();
+        pipeline.insert_after("3", digit(4)).unwrap();
+
+        assert_eq!(pipeline.get_stage_names(), vec!["1", "2", "3", "4"]);
+        assert_eq!(run(&pipeline), vec![1234, 1234]);
+
+        assert!(pipeline.push(digit(2)).is_err());
+        assert!(pipeline.insert_before("9", digit(5)).is_err());
+        assert!(pipeline.remove("9").is_none());
+
+        assert!(pipeline.remove("2").is_some());
+        assert_eq!(run(&pipeline), vec![134, 134]);
+    }
+
+    #[test]
+    fn disabled_stage_leaves_the_port_untouched() {
+        let middle = digit(2);
+        let mut pipeline = MiddlewarePipeline::new();
+        pipeline.push(digit(1)).unwrap();
+        pipeline.push(middle.clone()).unwrap();
+        pipeline.push(digit(3)).unwrap();
+
+        pipeline.set_port_enabled("2", 1, false).unwrap();
+        assert_eq!(run(&pipeline), vec![123, 13]);
+        assert_eq!(middle.lock().unwrap().disabled_seen, vec![1]);
+
+        pipeline.set_port_enabled("2", 1, true).unwrap();
+        assert_eq!(run(&pipeline), vec![123, 123]);
+        assert!(middle.lock().unwrap().disabled_seen.is_empty());
+
+        assert!(pipeline.set_port_enabled("9", 0, false).is_err());
+    }
+}
@@ -0,0 +1,77 @@
+static ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn encode(bytes: &[u8]) -> String {
+    let mut text = String::new();
+
+    for chunk in bytes.chunks(3) {
+        let value = chunk.iter().enumerate().fold(0u32, |value, (i, byte)| {
+            value | (*byte as u32) << (16 - i * 8)
+        });
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(ALPHABET[(value >> (18 - i * 6)) as usize & 0x3f] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+
+    text
+}
+
+pub fn decode(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim().trim_end_matches('=');
+    let mut bytes = Vec::new();
+    let mut value = 0u32;
+    let mut bits = 0;
+
+    for c in text.bytes() {
+        let index = ALPHABET.iter().position(|a| *a == c)? as u32;
+        value = (value << 6) | index;
+        bits += 6;
+
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((value >> bits) as u8);
+        }
+    }
+
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_matches_rfc_4648() {
+        let vectors = [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("fooba", "Zm9vYmE="),
+            ("foobar", "Zm9vYmFy"),
+        ];
+
+        for (text, encoded) in vectors {
+            assert_eq!(encode(text.as_bytes()), encoded);
+            assert_eq!(decode(encoded).unwrap(), text.as_bytes());
+        }
+    }
+
+    #[test]
+    fn round_trip_all_bytes() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        assert_eq!(decode(&encode(&bytes)).unwrap(), bytes);
+    }
+
+    #[test]
+    fn decode_rejects_invalid_characters() {
+        assert_eq!(decode("Zm9v\n"), Some(b"foo".to_vec()));
+        assert_eq!(decode("Zm-v"), None);
+        assert_eq!(decode("Zm 9v"), None);
+    }
+}
@@ -0,0 +1,371 @@
+use super::{invalid_movie, player::MoviePlayer, MovieHeader};
+use crate::input_frame::{InputFrame, PortInput};
+use retro_ab::{
+    erro_handle::ErroHandle,
+    retro_sys::{
+        RETRO_DEVICE_ID_JOYPAD_A, RETRO_DEVICE_ID_JOYPAD_B, RETRO_DEVICE_ID_JOYPAD_DOWN,
+        RETRO_DEVICE_ID_JOYPAD_L, RETRO_DEVICE_ID_JOYPAD_L2, RETRO_DEVICE_ID_JOYPAD_L3,
+        RETRO_DEVICE_ID_JOYPAD_LEFT, RETRO_DEVICE_ID_JOYPAD_R, RETRO_DEVICE_ID_JOYPAD_R2,
+        RETRO_DEVICE_ID_JOYPAD_R3, RETRO_DEVICE_ID_JOYPAD_RIGHT, RETRO_DEVICE_ID_JOYPAD_SELECT,
+        RETRO_DEVICE_ID_JOYPAD_START, RETRO_DEVICE_ID_JOYPAD_UP, RETRO_DEVICE_ID_JOYPAD_X,
+        RETRO_DEVICE_ID_JOYPAD_Y, RETRO_DEVICE_JOYPAD, RETRO_DEVICE_NONE,
+    },
+};
+
+static BK2_CORE_NAME: &str = "BizHawk";
+
+#[doc = "ordem dos botões de um controle de NES no BizHawk"]
+pub static BK2_NES_BUTTONS: [u32; 8] = [
+    RETRO_DEVICE_ID_JOYPAD_UP,
+    RETRO_DEVICE_ID_JOYPAD_DOWN,
+    RETRO_DEVICE_ID_JOYPAD_LEFT,
+    RETRO_DEVICE_ID_JOYPAD_RIGHT,
+    RETRO_DEVICE_ID_JOYPAD_START,
+    RETRO_DEVICE_ID_JOYPAD_SELECT,
+    RETRO_DEVICE_ID_JOYPAD_B,
+    RETRO_DEVICE_ID_JOYPAD_A,
+];
+
+#[doc = "ordem dos botões de um controle de SNES no BizHawk"]
+pub static BK2_SNES_BUTTONS: [u32; 12] = [
+    RETRO_DEVICE_ID_JOYPAD_UP,
+    RETRO_DEVICE_ID_JOYPAD_DOWN,
+    RETRO_DEVICE_ID_JOYPAD_LEFT,
+    RETRO_DEVICE_ID_JOYPAD_RIGHT,
+    RETRO_DEVICE_ID_JOYPAD_SELECT,
+    RETRO_DEVICE_ID_JOYPAD_START,
+    RETRO_DEVICE_ID_JOYPAD_Y,
+    RETRO_DEVICE_ID_JOYPAD_B,
+    RETRO_DEVICE_ID_JOYPAD_X,
+    RETRO_DEVICE_ID_JOYPAD_A,
+    RETRO_DEVICE_ID_JOYPAD_L,
+    RETRO_DEVICE_ID_JOYPAD_R,
+];
+
+#[derive(Debug, Clone, Copy)]
+enum LogColumn {
+    #[doc = "botões do console como Reset e Power, são ignorados"]
+    Console,
+    Button(usize, u32),
+}
+
+#[doc = "converte o conteúdo do 'Input Log.txt' de um .bk2, o arquivo .bk2 é um zip e precisa ser extraído pelo frontend"]
+pub fn import_bk2_input_log(input_log: &str) -> Result<MoviePlayer, ErroHandle> {
+    let mut columns: Vec<Vec<LogColumn>> = Vec::new();
+    let mut frames = Vec::new();
+    let mut ports = 0;
+
+    for (number, line) in input_log.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+
+        if let Some(log_key) = line.strip_prefix("LogKey:") {
+            columns = parse_log_key(log_key)?;
+            ports = columns
+                .iter()
+                .flatten()
+                .map(|column| match column {
+                    LogColumn::Button(port, _) => port + 1,
+                    LogColumn::Console => 0,
+                })
+                .max()
+                .unwrap_or(0);
+        } else if line.starts_with('|') {
+            if columns.is_empty() {
+                return Err(invalid_movie(&format!(
+                    "linha {}: o Input Log não possui LogKey",
+                    number + 1
+                )));
+            }
+
+            frames.push(parse_input_line(line, number + 1, &columns, ports)?);
+        }
+    }
+
+    let header = MovieHeader {
+        core_name: BK2_CORE_NAME.to_string(),
+        port_devices: vec![RETRO_DEVICE_JOYPAD; ports],
+        ..Default::default()
+    };
+
+    Ok(MoviePlayer::new(header, frames))
+}
+
+#[doc = "gera o conteúdo do 'Input Log.txt', 'buttons' define a ordem dos botões de cada controle e deve ser a mesma do sistema no BizHawk (ex: BK2_NES_BUTTONS)"]
+pub fn export_bk2_input_log(
+    header: &MovieHeader,
+    frames: &[InputFrame],
+    buttons: &[u32],
+) -> Result<String, ErroHandle> {
+    let ports = header.port_devices.len();
+
+    for (port, device) in header.port_devices.iter().enumerate() {
+        if *device != RETRO_DEVICE_JOYPAD && *device != RETRO_DEVICE_NONE {
+            return Err(invalid_movie(&format!(
+                "o device {device} da porta {port} não é suportado pelo bk2"
+            )));
+        }
+    }
+
+    let mut names = Vec::new();
+
+    for retro in buttons {
+        names.push(button_name(*retro).ok_or_else(|| {
+            invalid_movie(&format!("o botão {retro} não possui nome no BizHawk"))
+        })?);
+    }
+
+    let mut log_key = String::from("LogKey:#Reset|Power|");
+
+    for port in 0..ports {
+        log_key.push('#');
+
+        for (name, _) in &names {
+            log_key.push_str(&format!("P{} {}|", port + 1, name));
+        }
+    }
+
+    let supported = buttons.iter().fold(0u16, |mask, retro| mask | 1 << retro);
+    let mut text = format!("[Input]\n{log_key}\n");
+
+    for (number, frame) in frames.iter().enumerate() {
+        text.push_str("|..|");
+
+        for port in 0..ports {
+            let input = frame.ports.get(port).copied().unwrap_or_default();
+
+            if input.bitmask as u16 & !supported != 0 {
+                return Err(invalid_movie(&format!(
+                    "frame {number}: a porta {port} usa botões que não existem no layout informado"
+                )));
+            }
+
+            for (retro, (_, mnemonic)) in buttons.iter().zip(&names) {
+                let pressed = (input.bitmask >> retro) & 1 == 1;
+                text.push(if pressed { *mnemonic } else { '.' });
+            }
+
+            text.push('|');
+        }
+
+        text.push('\n');
+    }
+
+    text.push_str("[/Input]\n");
+
+    Ok(text)
+}
+
+fn parse_log_key(log_key: &str) -> Result<Vec<Vec<LogColumn>>, ErroHandle> {
+    let mut groups = Vec::new();
+
+    for group in log_key.split('#').filter(|group| !group.is_empty()) {
+        let mut columns = Vec::new();
+
+        for name in group.split('|').filter(|name| !name.is_empty()) {
+            let Some((player, button)) = name.split_once(' ') else {
+                columns.push(LogColumn::Console);
+                continue;
+            };
+
+            let port = player
+                .strip_prefix('P')
+                .and_then(|number| number.parse::<usize>().ok())
+                .filter(|number| *number > 0);
+
+            let Some(port) = port else {
+                columns.push(LogColumn::Console);
+                continue;
+            };
+
+            let retro = retro_from_name(button).ok_or_else(|| {
+                invalid_movie(&format!(
+                    "o botão '{name}' do bk2 não é suportado, somente controles digitais podem ser importados"
+                ))
+            })?;
+
+            columns.push(LogColumn::Button(port - 1, retro));
+        }
+
+        groups.push(columns);
+    }
+
+    Ok(groups)
+}
+
+fn parse_input_line(
+    line: &str,
+    number: usize,
+    columns: &[Vec<LogColumn>],
+    ports: usize,
+) -> Result<InputFrame, ErroHandle> {
+    let fields: Vec<&str> = line
+        .trim_start_matches('|')
+        .trim_end_matches('|')
+        .split('|')
+        .collect();
+
+    if fields.len() != columns.len() {
+        return Err(invalid_movie(&format!(
+            "linha {number}: esperado {} grupos mas foram encontrados {}",
+            columns.len(),
+            fields.len()
+        )));
+    }
+
+    let mut frame = InputFrame {
+        ports: vec![PortInput::default(); ports],
+        keyboard: Vec::new(),
+    };
+
+    for (group, field) in columns.iter().zip(fields) {
+        if field.contains(',') {
+            return Err(invalid_movie(&format!(
+                "linha {number}: valores analógicos não são suportados"
+            )));
+        }
+
+        if field.chars().count() != group.len() {
+            return Err(invalid_movie(&format!(
+                "linha {number}: o grupo '{field}' não corresponde ao LogKey"
+            )));
+        }
+
+        for (column, state) in group.iter().zip(field.chars()) {
+            if let LogColumn::Button(port, retro) = column {
+                if state != '.' && state != ' ' {
+                    frame.ports[*port].bitmask |= 1 << retro;
+                }
+            }
+        }
+    }
+
+    Ok(frame)
+}
+
+fn retro_from_name(name: &str) -> Option<u32> {
+    let retro = match name {
+        "Up" => RETRO_DEVICE_ID_JOYPAD_UP,
+        "Down" => RETRO_DEVICE_ID_JOYPAD_DOWN,
+        "Left" => RETRO_DEVICE_ID_JOYPAD_LEFT,
+        "Right" => RETRO_DEVICE_ID_JOYPAD_RIGHT,
+        "Start" => RETRO_DEVICE_ID_JOYPAD_START,
+        "Select" => RETRO_DEVICE_ID_JOYPAD_SELECT,
+        "A" | "Circle" => RETRO_DEVICE_ID_JOYPAD_A,
+        "B" | "Cross" => RETRO_DEVICE_ID_JOYPAD_B,
+        "X" | "Triangle" => RETRO_DEVICE_ID_JOYPAD_X,
+        "Y" | "Square" => RETRO_DEVICE_ID_JOYPAD_Y,
+        "L" | "L1" => RETRO_DEVICE_ID_JOYPAD_L,
+        "R" | "R1" => RETRO_DEVICE_ID_JOYPAD_R,
+        "L2" => RETRO_DEVICE_ID_JOYPAD_L2,
+        "R2" => RETRO_DEVICE_ID_JOYPAD_R2,
+        "L3" => RETRO_DEVICE_ID_JOYPAD_L3,
+        "R3" => RETRO_DEVICE_ID_JOYPAD_R3,
+        _ => return None,
+    };
+
+    Some(retro)
+}
+
+fn button_name(retro: u32) -> Option<(&'static str, char)> {
+    let name = match retro {
+        RETRO_DEVICE_ID_JOYPAD_UP => ("Up", 'U'),
+        RETRO_DEVICE_ID_JOYPAD_DOWN => ("Down", 'D'),
+        RETRO_DEVICE_ID_JOYPAD_LEFT => ("Left", 'L'),
+        RETRO_DEVICE_ID_JOYPAD_RIGHT => ("Right", 'R'),
+        RETRO_DEVICE_ID_JOYPAD_START => ("Start", 'S'),
+        RETRO_DEVICE_ID_JOYPAD_SELECT => ("Select", 's'),
+        RETRO_DEVICE_ID_JOYPAD_A => ("A", 'A'),
+        RETRO_DEVICE_ID_JOYPAD_B => ("B", 'B'),
+        RETRO_DEVICE_ID_JOYPAD_X => ("X", 'X'),
+        RETRO_DEVICE_ID_JOYPAD_Y => ("Y", 'Y'),
+        RETRO_DEVICE_ID_JOYPAD_L => ("L", 'l'),
+        RETRO_DEVICE_ID_JOYPAD_R => ("R", 'r'),
+        RETRO_DEVICE_ID_JOYPAD_L2 => ("L2", '['),
+        RETRO_DEVICE_ID_JOYPAD_R2 => ("R2", ']'),
+        RETRO_DEVICE_ID_JOYPAD_L3 => ("L3", '{'),
+        RETRO_DEVICE_ID_JOYPAD_R3 => ("R3", '}'),
+        _ => return None,
+    };
+
+    Some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(ports: &[&[u32]]) -> InputFrame {
+        InputFrame {
+            ports: ports
+                .iter()
+                .map(|buttons| PortInput {
+                    bitmask: buttons.iter().fold(0, |mask, retro| mask | 1 << retro),
+                    analog: [0; 4],
+                })
+                .collect(),
+            keyboard: Vec::new(),
+        }
+    }
+
+    fn header(ports: usize) -> MovieHeader {
+        MovieHeader {
+            port_devices: vec![RETRO_DEVICE_JOYPAD; ports],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn round_trip_nes() {
+        let frames = vec![
+            frame(&[&[RETRO_DEVICE_ID_JOYPAD_UP], &[]]),
+            frame(&[
+                &[],
+                &[RETRO_DEVICE_ID_JOYPAD_A, RETRO_DEVICE_ID_JOYPAD_START],
+            ]),
+        ];
+
+        let text = export_bk2_input_log(&header(2), &frames, &BK2_NES_BUTTONS).unwrap();
+        assert!(text.contains("LogKey:#Reset|Power|#P1 Up|P1 Down|"));
+        assert!(text.contains("|..|U.......|........|\n"));
+
+        let player = import_bk2_input_log(&text).unwrap();
+        assert_eq!(player.header.port_devices, header(2).port_devices);
+        assert_eq!(player.frames(), frames.as_slice());
+    }
+
+    #[test]
+    fn round_trip_shoulder_buttons() {
+        let buttons = [
+            RETRO_DEVICE_ID_JOYPAD_L2,
+            RETRO_DEVICE_ID_JOYPAD_R2,
+            RETRO_DEVICE_ID_JOYPAD_L3,
+            RETRO_DEVICE_ID_JOYPAD_R3,
+        ];
+        let frames = vec![frame(&[&buttons])];
+
+        let text = export_bk2_input_log(&header(1), &frames, &buttons).unwrap();
+        assert!(text.contains("|..|[]{}|\n"));
+
+        let player = import_bk2_input_log(&text).unwrap();
+        assert_eq!(player.frames(), frames.as_slice());
+    }
+
+    #[test]
+    fn rejects_malformed_input_logs() {
+        assert!(import_bk2_input_log("[Input]\n|..|U.......|\n").is_err());
+
+        let log_key = "LogKey:#Reset|Power|#P1 Up|P1 Down|\n";
+        assert!(import_bk2_input_log(&format!("{log_key}|..|U.|\n")).is_ok());
+        assert!(import_bk2_input_log(&format!("{log_key}|..|U|\n")).is_err());
+        assert!(import_bk2_input_log(&format!("{log_key}|..|\n")).is_err());
+        assert!(import_bk2_input_log(&format!("{log_key}|..|  0,  0|\n")).is_err());
+        assert!(import_bk2_input_log("LogKey:#P1 X Axis|\n").is_err());
+    }
+
+    #[test]
+    fn export_rejects_buttons_outside_the_layout() {
+        let frames = vec![frame(&[&[RETRO_DEVICE_ID_JOYPAD_X]])];
+        assert!(export_bk2_input_log(&header(1), &frames, &BK2_NES_BUTTONS).is_err());
+        assert!(export_bk2_input_log(&header(1), &[], &[99]).is_err());
+    }
+}
@@ -0,0 +1,383 @@
+use super::{base64, invalid_movie, player::MoviePlayer, MovieHeader};
+use crate::input_frame::{InputFrame, PortInput};
+use retro_ab::{
+    erro_handle::ErroHandle,
+    retro_sys::{
+        RETRO_DEVICE_ID_JOYPAD_A, RETRO_DEVICE_ID_JOYPAD_B, RETRO_DEVICE_ID_JOYPAD_DOWN,
+        RETRO_DEVICE_ID_JOYPAD_LEFT, RETRO_DEVICE_ID_JOYPAD_RIGHT, RETRO_DEVICE_ID_JOYPAD_SELECT,
+        RETRO_DEVICE_ID_JOYPAD_START, RETRO_DEVICE_ID_JOYPAD_UP, RETRO_DEVICE_JOYPAD,
+        RETRO_DEVICE_NONE,
+    },
+};
+use uuid::Uuid;
+
+static FM2_CORE_NAME: &str = "FCEUX";
+static SI_NONE: &str = "0";
+static SI_GAMEPAD: &str = "1";
+static FM2_CMD_POWER: u8 = 2;
+
+#[doc = "ordem dos botões em cada porta do fm2: RLDUTSBA"]
+static FM2_BUTTONS: [u32; 8] = [
+    RETRO_DEVICE_ID_JOYPAD_RIGHT,
+    RETRO_DEVICE_ID_JOYPAD_LEFT,
+    RETRO_DEVICE_ID_JOYPAD_DOWN,
+    RETRO_DEVICE_ID_JOYPAD_UP,
+    RETRO_DEVICE_ID_JOYPAD_START,
+    RETRO_DEVICE_ID_JOYPAD_SELECT,
+    RETRO_DEVICE_ID_JOYPAD_B,
+    RETRO_DEVICE_ID_JOYPAD_A,
+];
+static FM2_MNEMONICS: &[u8; 8] = b"RLDUTSBA";
+
+#[doc = "converte um movie texto do FCEUX, somente controles padrão do NES (SI_GAMEPAD) são suportados, com ou sem fourscore"]
+pub fn import_fm2(text: &str) -> Result<MoviePlayer, ErroHandle> {
+    let mut header = MovieHeader {
+        core_name: FM2_CORE_NAME.to_string(),
+        ..Default::default()
+    };
+    let mut frames = Vec::new();
+    let mut fourscore = false;
+    let mut ports = [SI_NONE; 2];
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+
+        if line.starts_with('|') {
+            if header.port_devices.is_empty() {
+                header.port_devices = port_devices(fourscore, &ports)?;
+            }
+
+            check_commands(line, number + 1, frames.is_empty())?;
+            frames.push(parse_input_line(line, number + 1, &header.port_devices)?);
+            continue;
+        }
+
+        let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+
+        match key {
+            "binary" if value != "0" => {
+                return Err(invalid_movie("fm2 binário não é suportado"));
+            }
+            "fourscore" => fourscore = value == "1",
+            "port0" => ports[0] = value,
+            "port1" => ports[1] = value,
+            "port2" if value != SI_NONE => {
+                return Err(invalid_movie(
+                    "dispositivos de expansão do fm2 (port2) não são suportados",
+                ));
+            }
+            "romChecksum" => {
+                let checksum = value.strip_prefix("base64:").unwrap_or(value);
+                header.content_hash = base64::decode(checksum)
+                    .ok_or_else(|| invalid_movie("romChecksum do fm2 inválido"))?;
+            }
+            _ => {}
+        }
+    }
+
+    if header.port_devices.is_empty() {
+        header.port_devices = port_devices(fourscore, &ports)?;
+    }
+
+    Ok(MoviePlayer::new(header, frames))
+}
+
+#[doc = "'rom_filename' é o nome da rom sem a extensão, o FCEUX precisa dele para carregar o movie"]
+pub fn export_fm2(
+    header: &MovieHeader,
+    frames: &[InputFrame],
+    rom_filename: &str,
+) -> Result<String, ErroHandle> {
+    let ports = header.port_devices.len();
+
+    if rom_filename.contains(['\n', '\r']) {
+        return Err(invalid_movie("o nome da rom não pode ter quebras de linha"));
+    }
+
+    if ports > 4 {
+        return Err(invalid_movie("o fm2 suporta no máximo 4 controles"));
+    }
+
+    for (port, device) in header.port_devices.iter().enumerate() {
+        if *device != RETRO_DEVICE_JOYPAD && *device != RETRO_DEVICE_NONE {
+            return Err(invalid_movie(&format!(
+                "o device {device} da porta {port} não é suportado pelo fm2"
+            )));
+        }
+    }
+
+    let fourscore = ports > 2;
+    let is_gamepad = |port: usize| header.port_devices.get(port) == Some(&RETRO_DEVICE_JOYPAD);
+
+    let mut text = String::new();
+    text.push_str("version 3\n");
+    text.push_str("emuVersion 22020\n");
+    text.push_str("rerecordCount 0\n");
+    text.push_str("palFlag 0\n");
+    text.push_str(&format!("romFilename {rom_filename}\n"));
+    text.push_str(&format!(
+        "romChecksum base64:{}\n",
+        base64::encode(&header.content_hash)
+    ));
+    text.push_str(&format!(
+        "guid {}\n",
+        Uuid::new_v4().to_string().to_uppercase()
+    ));
+    text.push_str(&format!("fourscore {}\n", if fourscore { 1 } else { 0 }));
+    text.push_str("microphone 0\n");
+
+    for port in 0..2 {
+        let device = if !fourscore && is_gamepad(port) {
+            SI_GAMEPAD
+        } else {
+            SI_NONE
+        };
+        text.push_str(&format!("port{port} {device}\n"));
+    }
+
+    text.push_str("port2 0\n");
+    text.push_str("FDS 0\n");
+    text.push_str("NewPPU 0\n");
+
+    for (number, frame) in frames.iter().enumerate() {
+        text.push_str("|0|");
+
+        for port in 0..if fourscore { 4 } else { 2 } {
+            let input = frame.ports.get(port).copied().unwrap_or_default();
+            let unsupported = FM2_BUTTONS
+                .iter()
+                .fold(input.bitmask as u16, |mask, retro| mask & !(1 << retro));
+
+            if unsupported != 0 {
+                return Err(invalid_movie(&format!(
+                    "frame {number}: a porta {port} usa botões que não existem no NES"
+                )));
+            }
+
+            if !fourscore && !is_gamepad(port) {
+                text.push('|');
+                continue;
+            }
+
+            for (retro, mnemonic) in FM2_BUTTONS.iter().zip(FM2_MNEMONICS) {
+                let pressed = (input.bitmask >> retro) & 1 == 1;
+                text.push(if pressed { *mnemonic as char } else { '.' });
+            }
+
+            text.push('|');
+        }
+
+        text.push_str("|\n");
+    }
+
+    Ok(text)
+}
+
+fn port_devices(fourscore: bool, ports: &[&str; 2]) -> Result<Vec<u32>, ErroHandle> {
+    if fourscore {
+        return Ok(vec![RETRO_DEVICE_JOYPAD; 4]);
+    }
+
+    ports
+        .iter()
+        .enumerate()
+        .map(|(port, device)| match *device {
+            "0" => Ok(RETRO_DEVICE_NONE),
+            "1" => Ok(RETRO_DEVICE_JOYPAD),
+            "2" => Err(invalid_movie(&format!(
+                "a porta {port} do fm2 usa um Zapper, que não é suportado"
+            ))),
+            _ => Err(invalid_movie(&format!(
+                "a porta {port} do fm2 usa um device desconhecido ({device})"
+            ))),
+        })
+        .collect()
+}
+
+#[doc = "o movie sempre começa com o core recém carregado, então somente um power no primeiro frame é aceito, os outros comandos (reset, power, fds, vs) não podem ser reproduzidos"]
+fn check_commands(line: &str, number: usize, first_frame: bool) -> Result<(), ErroHandle> {
+    let commands = line.split('|').nth(1).unwrap_or_default();
+    let commands: u8 = commands.trim().parse().map_err(|_| {
+        invalid_movie(&format!(
+            "linha {number}: coluna de comandos do fm2 inválida ({commands})"
+        ))
+    })?;
+
+    if commands == 0 || (first_frame && commands == FM2_CMD_POWER) {
+        return Ok(());
+    }
+
+    Err(invalid_movie(&format!(
+        "linha {number}: comandos do fm2 ({commands}) como reset e power não são suportados"
+    )))
+}
+
+fn parse_input_line(
+    line: &str,
+    number: usize,
+    port_devices: &[u32],
+) -> Result<InputFrame, ErroHandle> {
+    //|comandos|porta 0|porta 1|...|expansão|
+    let fields: Vec<&str> = line.split('|').skip(2).collect();
+    let mut frame = InputFrame {
+        ports: vec![PortInput::default(); port_devices.len()],
+        keyboard: Vec::new(),
+    };
+
+    for (port, device) in port_devices.iter().enumerate() {
+        if *device != RETRO_DEVICE_JOYPAD {
+            continue;
+        }
+
+        let field = fields.get(port).copied().unwrap_or_default();
+
+        if field.len() != FM2_BUTTONS.len() {
+            return Err(invalid_movie(&format!(
+                "linha {number}: a porta {port} deveria ter 8 botões (RLDUTSBA)"
+            )));
+        }
+
+        for (retro, state) in FM2_BUTTONS.iter().zip(field.bytes()) {
+            if state != b'.' && state != b' ' {
+                frame.ports[port].bitmask |= 1 << retro;
+            }
+        }
+    }
+
+    Ok(frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pressed(buttons: &[u32]) -> PortInput {
+        PortInput {
+            bitmask: buttons.iter().fold(0, |mask, retro| mask | 1 << retro),
+            analog: [0; 4],
+        }
+    }
+
+    fn movie(ports: usize) -> (MovieHeader, Vec<InputFrame>) {
+        let header = MovieHeader {
+            content_hash: vec![1, 2, 3, 4, 5],
+            port_devices: vec![RETRO_DEVICE_JOYPAD; ports],
+            ..Default::default()
+        };
+
+        let frames = (0..ports)
+            .map(|port| InputFrame {
+                ports: (0..ports)
+                    .map(|p| {
+                        if p == port {
+                            pressed(&FM2_BUTTONS[p..p + 2])
+                        } else {
+                            PortInput::default()
+                        }
+                    })
+                    .collect(),
+                keyboard: Vec::new(),
+            })
+            .collect();
+
+        (header, frames)
+    }
+
+    #[test]
+    fn round_trip_two_ports() {
+        let (header, frames) = movie(2);
+        let text = export_fm2(&header, &frames, "rom").unwrap();
+
+        assert!(text.contains("|0|RL......|........||\n"));
+
+        let player = import_fm2(&text).unwrap();
+        assert_eq!(player.header.content_hash, header.content_hash);
+        assert_eq!(player.header.port_devices, header.port_devices);
+        assert_eq!(player.frames(), frames.as_slice());
+    }
+
+    #[test]
+    fn header_has_the_keys_fceux_requires() {
+        let (header, frames) = movie(2);
+        let text = export_fm2(&header, &frames, "Super Mario Bros").unwrap();
+
+        for key in [
+            "version",
+            "emuVersion",
+            "romFilename",
+            "romChecksum",
+            "guid",
+        ] {
+            assert!(
+                text.lines()
+                    .any(|line| line.starts_with(&format!("{key} "))),
+                "{key}"
+            );
+        }
+
+        assert!(text.contains("romFilename Super Mario Bros\n"));
+        assert!(export_fm2(&header, &frames, "rom\nport0 0").is_err());
+    }
+
+    #[test]
+    fn round_trip_fourscore() {
+        let (header, frames) = movie(4);
+        let text = export_fm2(&header, &frames, "rom").unwrap();
+
+        assert!(text.contains("fourscore 1\n"));
+
+        let player = import_fm2(&text).unwrap();
+        assert_eq!(player.header.port_devices, header.port_devices);
+        assert_eq!(player.frames(), frames.as_slice());
+    }
+
+    #[test]
+    fn empty_port_is_skipped() {
+        let header = MovieHeader {
+            port_devices: vec![RETRO_DEVICE_JOYPAD, RETRO_DEVICE_NONE],
+            ..Default::default()
+        };
+        let frames = vec![InputFrame {
+            ports: vec![pressed(&[RETRO_DEVICE_ID_JOYPAD_A]), PortInput::default()],
+            keyboard: Vec::new(),
+        }];
+
+        let text = export_fm2(&header, &frames, "rom").unwrap();
+        assert!(text.contains("port1 0\n"));
+
+        let player = import_fm2(&text).unwrap();
+        assert_eq!(player.header.port_devices, header.port_devices);
+        assert_eq!(player.frames(), frames.as_slice());
+    }
+
+    #[test]
+    fn only_power_on_the_first_frame_is_accepted() {
+        let text = "port0 1\nport1 0\n|2|........||\n|0|.......A||\n";
+        assert_eq!(import_fm2(text).unwrap().frame_count(), 2);
+
+        assert!(import_fm2("port0 1\nport1 0\n|1|........||\n").is_err());
+        assert!(import_fm2("port0 1\nport1 0\n|0|........||\n|2|........||\n").is_err());
+        assert!(import_fm2("port0 1\nport1 0\n|x|........||\n").is_err());
+    }
+
+    #[test]
+    fn rejects_unsupported_files() {
+        assert!(import_fm2("binary 1\n").is_err());
+        assert!(import_fm2("port0 2\nport1 0\n").is_err());
+        assert!(import_fm2("port0 1\nport1 0\nport2 3\n").is_err());
+        assert!(import_fm2("port0 1\nport1 0\n|0|RLDU||\n").is_err());
+    }
+
+    #[test]
+    fn export_rejects_buttons_and_ports_the_nes_does_not_have() {
+        let (header, _) = movie(1);
+        let frames = vec![InputFrame {
+            ports: vec![pressed(&[retro_ab::retro_sys::RETRO_DEVICE_ID_JOYPAD_X])],
+            keyboard: Vec::new(),
+        }];
+        assert!(export_fm2(&header, &frames, "rom").is_err());
+
+        let (header, frames) = movie(5);
+        assert!(export_fm2(&header, &frames, "rom").is_err());
+    }
+}
@@ -0,0 +1,340 @@
+mod base64;
+pub mod bk2;
+pub mod fm2;
+pub mod player;
+pub mod recorder;
+
+use crate::input_frame::{InputFrame, PortInput};
+use retro_ab::{erro_handle::ErroHandle, retro_sys::retro_log_level};
+use std::io::{ErrorKind, Read, Write};
+use uuid::Uuid;
+
+pub static MOVIE_MAGIC: &[u8; 4] = b"RABM";
+pub static MOVIE_VERSION: u16 = 1;
+#[doc = "id do Device enviado nos eventos do movie, é sempre o mesmo e não se confunde com o id de nenhum gamepad"]
+pub static MOVIE_DEVICE_ID: Uuid = Uuid::from_u128(0x5241424d_0000_4000_8000_000000000001);
+
+static ANALOG_FLAG: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MovieHeader {
+    #[doc = "somente a versão MOVIE_VERSION pode ser escrita, versões anteriores são aceitas na leitura"]
+    pub version: u16,
+    #[doc = "nome do Core usado na gravação"]
+    pub core_name: String,
+    #[doc = "hash da rom, o formato fica a critério do frontend"]
+    pub content_hash: Vec<u8>,
+    #[doc = "tipo do device (RETRO_DEVICE_*) de cada porta, a quantidade de portas dos frames é a mesma dessa lista"]
+    pub port_devices: Vec<u32>,
+}
+
+impl Default for MovieHeader {
+    fn default() -> Self {
+        Self {
+            version: MOVIE_VERSION,
+            core_name: String::new(),
+            content_hash: Vec::new(),
+            port_devices: Vec::new(),
+        }
+    }
+}
+
+impl MovieHeader {
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), ErroHandle> {
+        if self.version != MOVIE_VERSION {
+            return Err(invalid_movie(&format!(
+                "o movie só pode ser escrito na versão {MOVIE_VERSION}"
+            )));
+        }
+
+        write_bytes(writer, MOVIE_MAGIC)?;
+        write_bytes(writer, &self.version.to_le_bytes())?;
+        write_len(writer, self.core_name.len())?;
+        write_bytes(writer, self.core_name.as_bytes())?;
+        write_len(writer, self.content_hash.len())?;
+        write_bytes(writer, &self.content_hash)?;
+        write_len(writer, self.port_devices.len())?;
+
+        for device in &self.port_devices {
+            write_bytes(writer, &device.to_le_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Self, ErroHandle> {
+        let mut magic = [0u8; 4];
+        read_exact(reader, &mut magic)?;
+
+        if &magic != MOVIE_MAGIC {
+            return Err(invalid_movie(
+                "o arquivo não é um movie do retro_ab_gamepad",
+            ));
+        }
+
+        let version = read_u16(reader)?;
+
+        if version > MOVIE_VERSION {
+            return Err(invalid_movie(&format!(
+                "versão do movie ({version}) não suportada"
+            )));
+        }
+
+        let len = read_u16(reader)? as usize;
+        let mut core_name = vec![0u8; len];
+        read_exact(reader, &mut core_name)?;
+
+        let len = read_u16(reader)? as usize;
+        let mut content_hash = vec![0u8; len];
+        read_exact(reader, &mut content_hash)?;
+
+        let len = read_u16(reader)? as usize;
+        let mut port_devices = Vec::with_capacity(len);
+
+        for _ in 0..len {
+            let mut device = [0u8; 4];
+            read_exact(reader, &mut device)?;
+            port_devices.push(u32::from_le_bytes(device));
+        }
+
+        Ok(Self {
+            version,
+            core_name: String::from_utf8(core_name)
+                .map_err(|_| invalid_movie("nome do Core inválido"))?,
+            content_hash,
+            port_devices,
+        })
+    }
+}
+
+#[doc = "escreve um frame com exatamente 'ports' portas, portas ausentes são gravadas como soltas"]
+pub fn write_frame<W: Write>(
+    writer: &mut W,
+    frame: &InputFrame,
+    ports: usize,
+) -> Result<(), ErroHandle> {
+    for port in 0..ports {
+        let input = frame.ports.get(port).copied().unwrap_or_default();
+        let has_analog = input.analog.iter().any(|value| *value != 0);
+
+        write_bytes(writer, &input.bitmask.to_le_bytes())?;
+        write_bytes(writer, &[if has_analog { ANALOG_FLAG } else { 0 }])?;
+
+        if has_analog {
+            for value in input.analog {
+                write_bytes(writer, &value.to_le_bytes())?;
+            }
+        }
+    }
+
+    write_len(writer, frame.keyboard.len())?;
+
+    for key in &frame.keyboard {
+        let key = u16::try_from(*key)
+            .map_err(|_| invalid_movie(&format!("a tecla {key} não cabe no movie")))?;
+        write_bytes(writer, &key.to_le_bytes())?;
+    }
+
+    Ok(())
+}
+
+#[doc = "retorna None quando não há mais frames para ler"]
+pub fn read_frame<R: Read>(reader: &mut R, ports: usize) -> Result<Option<InputFrame>, ErroHandle> {
+    let mut first = [0u8; 1];
+
+    loop {
+        match reader.read(&mut first) {
+            Ok(0) => return Ok(None),
+            Ok(_) => break,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(io_erro(e)),
+        }
+    }
+
+    let reader = &mut first.as_slice().chain(reader);
+    let mut frame = InputFrame::default();
+
+    for _ in 0..ports {
+        let mut input = PortInput {
+            bitmask: read_u16(reader)? as i16,
+            ..Default::default()
+        };
+
+        let mut flags = [0u8; 1];
+        read_exact(reader, &mut flags)?;
+
+        if flags[0] & ANALOG_FLAG != 0 {
+            for value in &mut input.analog {
+                *value = read_u16(reader)? as i16;
+            }
+        }
+
+        frame.ports.push(input);
+    }
+
+    for _ in 0..read_u16(reader)? {
+        frame.keyboard.push(read_u16(reader)? as u32);
+    }
+
+    Ok(Some(frame))
+}
+
+fn write_bytes<W: Write>(writer: &mut W, bytes: &[u8]) -> Result<(), ErroHandle> {
+    writer.write_all(bytes).map_err(io_erro)
+}
+
+fn write_len<W: Write>(writer: &mut W, len: usize) -> Result<(), ErroHandle> {
+    let len = u16::try_from(len).map_err(|_| invalid_movie("valor muito grande para o movie"))?;
+    write_bytes(writer, &len.to_le_bytes())
+}
+
+fn read_exact<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<(), ErroHandle> {
+    reader
+        .read_exact(buf)
+        .map_err(|_| invalid_movie("o movie terminou inesperadamente"))
+}
+
+fn read_u16<R: Read>(reader: &mut R) -> Result<u16, ErroHandle> {
+    let mut value = [0u8; 2];
+    read_exact(reader, &mut value)?;
+    Ok(u16::from_le_bytes(value))
+}
+
+fn io_erro(erro: std::io::Error) -> ErroHandle {
+    ErroHandle {
+        level: retro_log_level::RETRO_LOG_ERROR,
+        message: erro.to_string(),
+    }
+}
+
+fn invalid_movie(message: &str) -> ErroHandle {
+    ErroHandle {
+        level: retro_log_level::RETRO_LOG_ERROR,
+        message: message.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> MovieHeader {
+        MovieHeader {
+            version: MOVIE_VERSION,
+            core_name: "nestopia".to_string(),
+            content_hash: vec![0xde, 0xad, 0xbe, 0xef],
+            port_devices: vec![1, 5],
+        }
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let mut bytes = Vec::new();
+        header().write_to(&mut bytes).unwrap();
+
+        let read = MovieHeader::read_from(&mut bytes.as_slice()).unwrap();
+        assert_eq!(read, header());
+    }
+
+    #[test]
+    fn header_rejects_wrong_magic_and_newer_version() {
+        let mut bytes = Vec::new();
+        header().write_to(&mut bytes).unwrap();
+
+        let mut wrong_magic = bytes.clone();
+        wrong_magic[0] = b'X';
+        assert!(MovieHeader::read_from(&mut wrong_magic.as_slice()).is_err());
+
+        let mut newer = bytes.clone();
+        newer[4..6].copy_from_slice(&(MOVIE_VERSION + 1).to_le_bytes());
+        assert!(MovieHeader::read_from(&mut newer.as_slice()).is_err());
+
+        bytes.truncate(bytes.len() - 1);
+        assert!(MovieHeader::read_from(&mut bytes.as_slice()).is_err());
+    }
+
+    #[test]
+    fn frame_round_trip() {
+        let frame = InputFrame {
+            ports: vec![
+                PortInput {
+                    bitmask: -1,
+                    analog: [i16::MIN, i16::MAX, 0, -1],
+                },
+                PortInput {
+                    bitmask: 0b1001,
+                    analog: [0; 4],
+                },
+            ],
+            keyboard: vec![13, 273],
+        };
+
+        let mut bytes = Vec::new();
+        write_frame(&mut bytes, &frame, 2).unwrap();
+        write_frame(&mut bytes, &InputFrame::default(), 2).unwrap();
+
+        let reader = &mut bytes.as_slice();
+        assert_eq!(read_frame(reader, 2).unwrap(), Some(frame));
+
+        let released = read_frame(reader, 2).unwrap().unwrap();
+        assert_eq!(released.ports, vec![PortInput::default(); 2]);
+        assert!(released.keyboard.is_empty());
+
+        assert_eq!(read_frame(reader, 2).unwrap(), None);
+    }
+
+    #[test]
+    fn frame_keeps_the_header_port_count() {
+        let frame = InputFrame {
+            ports: vec![
+                PortInput {
+                    bitmask: 1,
+                    analog: [0; 4],
+                };
+                3
+            ],
+            keyboard: Vec::new(),
+        };
+
+        let mut bytes = Vec::new();
+        write_frame(&mut bytes, &frame, 1).unwrap();
+        write_frame(&mut bytes, &InputFrame::default(), 4).unwrap();
+
+        let reader = &mut bytes.as_slice();
+        assert_eq!(read_frame(reader, 1).unwrap().unwrap().ports.len(), 1);
+        assert_eq!(read_frame(reader, 4).unwrap().unwrap().ports.len(), 4);
+    }
+
+    #[test]
+    fn rejects_what_it_cannot_write() {
+        let older = MovieHeader {
+            version: MOVIE_VERSION - 1,
+            ..header()
+        };
+        assert!(older.write_to(&mut Vec::new()).is_err());
+        assert_eq!(MovieHeader::default().version, MOVIE_VERSION);
+
+        let frame = InputFrame {
+            ports: Vec::new(),
+            keyboard: vec![u16::MAX as u32 + 1],
+        };
+        assert!(write_frame(&mut Vec::new(), &frame, 0).is_err());
+    }
+
+    #[test]
+    fn truncated_frame_is_an_error() {
+        let frame = InputFrame {
+            ports: vec![PortInput {
+                bitmask: 1,
+                analog: [1, 2, 3, 4],
+            }],
+            keyboard: Vec::new(),
+        };
+
+        let mut bytes = Vec::new();
+        write_frame(&mut bytes, &frame, 1).unwrap();
+        bytes.truncate(bytes.len() - 3);
+
+        assert!(read_frame(&mut bytes.as_slice(), 1).is_err());
+    }
+}
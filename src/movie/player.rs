@@ -0,0 +1,145 @@
+use super::{read_frame, MovieHeader};
+use crate::input_frame::InputFrame;
+use retro_ab::erro_handle::ErroHandle;
+use std::io::Read;
+
+#[derive(Debug, Clone)]
+pub struct MoviePlayer {
+    pub header: MovieHeader,
+    frames: Vec<InputFrame>,
+    position: usize,
+    take_over_at: Option<u64>,
+}
+
+impl MoviePlayer {
+    pub fn new(header: MovieHeader, frames: Vec<InputFrame>) -> Self {
+        Self {
+            header,
+            frames,
+            position: 0,
+            take_over_at: None,
+        }
+    }
+
+    pub fn from_reader<R: Read>(reader: &mut R) -> Result<Self, ErroHandle> {
+        let header = MovieHeader::read_from(reader)?;
+        let mut frames = Vec::new();
+
+        while let Some(frame) = read_frame(reader, header.port_devices.len())? {
+            frames.push(frame);
+        }
+
+        Ok(Self::new(header, frames))
+    }
+
+    #[doc = "avança um frame, retorna None quando o movie chegou ao fim ou ao frame de take over"]
+    pub fn next_frame(&mut self) -> Option<&InputFrame> {
+        if self
+            .take_over_at
+            .is_some_and(|frame| self.position as u64 >= frame)
+        {
+            return None;
+        }
+
+        let frame = self.frames.get(self.position)?;
+        self.position += 1;
+
+        Some(frame)
+    }
+
+    #[doc = "o movie termina depois de entregar 'frame' frames, None executa o movie até o fim"]
+    pub fn set_take_over_frame(&mut self, frame: Option<u64>) {
+        self.take_over_at = frame;
+    }
+
+    pub fn get_take_over_frame(&self) -> Option<u64> {
+        self.take_over_at
+    }
+
+    #[doc = "quantidade de frames que já foram entregues ao Core"]
+    pub fn position(&self) -> u64 {
+        self.position as u64
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.frames.len() as u64
+    }
+
+    pub fn frames(&self) -> &[InputFrame] {
+        &self.frames
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{input_frame::PortInput, movie::recorder::MovieRecorder};
+
+    fn frames(count: i16) -> Vec<InputFrame> {
+        (0..count)
+            .map(|bitmask| InputFrame {
+                ports: vec![PortInput {
+                    bitmask,
+                    analog: [0; 4],
+                }],
+                keyboard: Vec::new(),
+            })
+            .collect()
+    }
+
+    fn header() -> MovieHeader {
+        MovieHeader {
+            port_devices: vec![1],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn plays_every_frame_in_order() {
+        let mut player = MoviePlayer::new(header(), frames(3));
+
+        for expected in frames(3) {
+            assert_eq!(player.next_frame(), Some(&expected));
+        }
+
+        assert_eq!(player.next_frame(), None);
+        assert_eq!(player.position(), 3);
+        assert_eq!(player.frame_count(), 3);
+    }
+
+    #[test]
+    fn stops_at_the_take_over_frame() {
+        let mut player = MoviePlayer::new(header(), frames(5));
+        player.set_take_over_frame(Some(2));
+
+        assert!(player.next_frame().is_some());
+        assert!(player.next_frame().is_some());
+        assert_eq!(player.next_frame(), None);
+        assert_eq!(player.position(), 2);
+
+        //sem o take over o movie continua de onde parou
+        player.set_take_over_frame(None);
+        assert_eq!(player.next_frame(), Some(&frames(5)[2]));
+
+        player.set_take_over_frame(Some(0));
+        assert_eq!(player.next_frame(), None);
+        assert_eq!(player.get_take_over_frame(), Some(0));
+    }
+
+    #[test]
+    fn reads_what_the_recorder_wrote() {
+        let mut bytes = Vec::new();
+        let mut recorder = MovieRecorder::new(&mut bytes, &header()).unwrap();
+
+        for frame in frames(4) {
+            recorder.record(&frame).unwrap();
+        }
+
+        assert_eq!(recorder.finish().unwrap(), 4);
+
+        let player = MoviePlayer::from_reader(&mut bytes.as_slice()).unwrap();
+
+        assert_eq!(player.header, header());
+        assert_eq!(player.frames(), frames(4).as_slice());
+    }
+}
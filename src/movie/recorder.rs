@@ -0,0 +1,44 @@
+use super::{write_frame, MovieHeader};
+use crate::input_frame::InputFrame;
+use retro_ab::erro_handle::ErroHandle;
+use std::io::Write;
+
+#[derive(Debug)]
+pub struct MovieRecorder<W: Write> {
+    writer: W,
+    ports: usize,
+    frame_count: u64,
+}
+
+impl<W: Write> MovieRecorder<W> {
+    #[doc = "escreve o cabeçalho imediatamente, os frames são escritos conforme forem gravados"]
+    pub fn new(mut writer: W, header: &MovieHeader) -> Result<Self, ErroHandle> {
+        header.write_to(&mut writer)?;
+
+        Ok(Self {
+            writer,
+            ports: header.port_devices.len(),
+            frame_count: 0,
+        })
+    }
+
+    pub fn record(&mut self, frame: &InputFrame) -> Result<(), ErroHandle> {
+        write_frame(&mut self.writer, frame, self.ports)?;
+        self.frame_count += 1;
+
+        Ok(())
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    pub fn finish(mut self) -> Result<u64, ErroHandle> {
+        self.writer.flush().map_err(|e| ErroHandle {
+            level: retro_ab::retro_sys::retro_log_level::RETRO_LOG_ERROR,
+            message: e.to_string(),
+        })?;
+
+        Ok(self.frame_count)
+    }
+}
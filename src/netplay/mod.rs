@@ -0,0 +1,148 @@
+pub mod remote;
+pub mod rollback;
+
+use crate::{
+    input_frame::InputFrame,
+    movie::{read_frame, write_frame},
+};
+use retro_ab::{erro_handle::ErroHandle, retro_sys::retro_log_level};
+
+pub static NETPLAY_VERSION: u8 = 1;
+
+/// # Formato
+///
+/// Todos os números são little endian:
+///
+/// | campo            | tamanho                                                    |
+/// |------------------|------------------------------------------------------------|
+/// | versão           | u8 (NETPLAY_VERSION)                                       |
+/// | número do frame  | u64                                                        |
+/// | portas           | u8                                                         |
+/// | cada porta       | u16 bitmask, u8 flags e 4 × i16 analog se a flag 1 estiver ativa |
+/// | teclado          | u16 quantidade de teclas e um u16 por tecla                |
+///
+/// O formato das portas e do teclado é o mesmo usado nos frames dos movies, um frame sem analógicos
+/// e sem teclas ocupa 12 bytes mais 3 bytes por porta.
+pub fn encode_frame(frame_number: u64, frame: &InputFrame) -> Result<Vec<u8>, ErroHandle> {
+    let ports = u8::try_from(frame.ports.len()).map_err(|_| {
+        invalid_frame(&format!(
+            "o frame possui {} portas, o limite é {}",
+            frame.ports.len(),
+            u8::MAX
+        ))
+    })?;
+
+    let mut bytes = Vec::with_capacity(12 + ports as usize * 3);
+    bytes.push(NETPLAY_VERSION);
+    bytes.extend_from_slice(&frame_number.to_le_bytes());
+    bytes.push(ports);
+
+    write_frame(&mut bytes, frame, ports as usize)?;
+
+    Ok(bytes)
+}
+
+#[doc = "retorna o número do frame e o input que foram enviados pelo encode_frame"]
+pub fn decode_frame(bytes: &[u8]) -> Result<(u64, InputFrame), ErroHandle> {
+    if bytes.len() < 10 {
+        return Err(invalid_frame("frame de netplay incompleto"));
+    }
+
+    if bytes[0] != NETPLAY_VERSION {
+        return Err(invalid_frame(&format!(
+            "versão do frame de netplay ({}) não suportada",
+            bytes[0]
+        )));
+    }
+
+    let frame_number = u64::from_le_bytes(bytes[1..9].try_into().unwrap());
+    let ports = bytes[9] as usize;
+    let mut reader = &bytes[10..];
+
+    let frame = read_frame(&mut reader, ports)?
+        .ok_or_else(|| invalid_frame("frame de netplay incompleto"))?;
+
+    if !reader.is_empty() {
+        return Err(invalid_frame("o frame de netplay possui bytes sobrando"));
+    }
+
+    Ok((frame_number, frame))
+}
+
+fn invalid_frame(message: &str) -> ErroHandle {
+    ErroHandle {
+        level: retro_log_level::RETRO_LOG_ERROR,
+        message: message.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input_frame::PortInput;
+
+    #[test]
+    fn round_trip() {
+        let frame = InputFrame {
+            ports: vec![
+                PortInput {
+                    bitmask: 0b101,
+                    analog: [0; 4],
+                },
+                PortInput {
+                    bitmask: -1,
+                    analog: [i16::MIN, 0, 0, i16::MAX],
+                },
+            ],
+            keyboard: vec![32],
+        };
+
+        let bytes = encode_frame(u64::MAX, &frame).unwrap();
+        assert_eq!(decode_frame(&bytes).unwrap(), (u64::MAX, frame));
+    }
+
+    #[test]
+    fn frame_without_analog_or_keys_is_small() {
+        let frame = InputFrame {
+            ports: vec![PortInput::default(); 4],
+            keyboard: Vec::new(),
+        };
+
+        let bytes = encode_frame(7, &frame).unwrap();
+        assert_eq!(bytes.len(), 12 + 4 * 3);
+        assert_eq!(decode_frame(&bytes).unwrap(), (7, frame));
+
+        let empty = encode_frame(0, &InputFrame::default()).unwrap();
+        assert_eq!(decode_frame(&empty).unwrap(), (0, InputFrame::default()));
+    }
+
+    #[test]
+    fn rejects_malformed_frames() {
+        let frame = InputFrame {
+            ports: vec![PortInput::default(); 2],
+            keyboard: Vec::new(),
+        };
+        let bytes = encode_frame(1, &frame).unwrap();
+
+        assert!(decode_frame(&bytes[..9]).is_err());
+        assert!(decode_frame(&bytes[..bytes.len() - 1]).is_err());
+
+        let mut newer = bytes.clone();
+        newer[0] = NETPLAY_VERSION + 1;
+        assert!(decode_frame(&newer).is_err());
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(decode_frame(&trailing).is_err());
+    }
+
+    #[test]
+    fn rejects_too_many_ports() {
+        let frame = InputFrame {
+            ports: vec![PortInput::default(); u8::MAX as usize + 1],
+            keyboard: Vec::new(),
+        };
+
+        assert!(encode_frame(0, &frame).is_err());
+    }
+}
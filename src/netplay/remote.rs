@@ -0,0 +1,149 @@
+use super::rollback::InputHistory;
+use crate::input_frame::{InputFrame, PortInput};
+use std::collections::BTreeMap;
+
+/// # Remote input
+///
+/// Guarda o input recebido de outros jogadores até que o frame correspondente seja montado.
+/// As portas marcadas como remotas ignoram o input local e respondem ao Core com o input recebido
+/// para aquele número de frame, se o input ainda não chegou a porta fica solta.
+///
+/// Com o rollback habilitado o input de todas as portas fica no InputHistory, a porta remota sem
+/// input usa a previsão do histórico e os frames refeitos depois de um rollback usam o input
+/// guardado, tanto das portas remotas quanto das locais.
+#[derive(Debug, Clone, Default)]
+pub struct RemoteInput {
+    ports: Vec<i16>,
+    frames: BTreeMap<u64, Vec<(i16, PortInput)>>,
+    history: Option<InputHistory>,
+}
+
+impl RemoteInput {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_remote_port(&mut self, port: i16, remote: bool) {
+        self.ports.retain(|p| *p != port);
+
+        if let Some(history) = &mut self.history {
+            history.remove_port(port);
+        }
+
+        if remote {
+            self.ports.push(port);
+        } else {
+            for inputs in self.frames.values_mut() {
+                inputs.retain(|(p, _)| *p != port);
+            }
+        }
+    }
+
+    pub fn get_remote_ports(&self) -> Vec<i16> {
+        self.ports.clone()
+    }
+
+    #[doc = "'capacity' é a quantidade máxima de frames que podem ser refeitos em um rollback"]
+    pub fn enable_rollback(&mut self, capacity: usize) {
+        self.history = Some(InputHistory::new(capacity));
+    }
+
+    pub fn disable_rollback(&mut self) {
+        self.history = None;
+    }
+
+    pub fn can_rollback_to(&self, frame_number: u64) -> bool {
+        self.history
+            .as_ref()
+            .and_then(|history| history.get_first_frame())
+            .is_some_and(|first| first <= frame_number)
+    }
+
+    pub fn take_mispredicted(&mut self) -> Vec<u64> {
+        match &mut self.history {
+            Some(history) => history.take_mispredicted(),
+            None => Vec::new(),
+        }
+    }
+
+    #[doc = "guarda o input de uma porta remota para o frame informado, input de portas locais é ignorado"]
+    pub fn submit(&mut self, frame_number: u64, port: i16, input: PortInput) {
+        if !self.ports.contains(&port) {
+            return;
+        }
+
+        if let Some(history) = &mut self.history {
+            if history.get(frame_number, port).is_some() {
+                history.confirm(frame_number, port, input);
+                return;
+            }
+        }
+
+        let inputs = self.frames.entry(frame_number).or_default();
+        inputs.retain(|(p, _)| *p != port);
+        inputs.push((port, input));
+    }
+
+    #[doc = "guarda o input de todas as portas remotas presentes no frame"]
+    pub fn submit_frame(&mut self, frame_number: u64, frame: &InputFrame) {
+        for port in self.ports.clone() {
+            if let Some(input) = frame.port(port) {
+                self.submit(frame_number, port, *input);
+            }
+        }
+    }
+
+    #[doc = "substitui as portas remotas do frame e descarta o input dos frames que já passaram"]
+    pub fn apply(&mut self, frame_number: u64, frame: &mut InputFrame) {
+        let received = self.frames.remove(&frame_number).unwrap_or_default();
+        self.frames = self.frames.split_off(&frame_number);
+
+        if let Some(history) = &mut self.history {
+            for (index, input) in frame.ports.iter_mut().enumerate() {
+                let port = index as i16;
+
+                if self.ports.contains(&port) {
+                    continue;
+                }
+
+                match history.get(frame_number, port) {
+                    Some(entry) => *input = entry.input,
+                    None => history.record(frame_number, port, *input, true),
+                }
+            }
+        }
+
+        for port in &self.ports {
+            if *port < 0 {
+                continue;
+            }
+
+            let index = *port as usize;
+
+            if frame.ports.len() <= index {
+                frame.ports.resize(index + 1, PortInput::default());
+            }
+
+            let input = received
+                .iter()
+                .find(|(p, _)| p == port)
+                .map(|(_, input)| *input);
+
+            frame.ports[index] = match (&mut self.history, input) {
+                (Some(history), Some(input)) => {
+                    history.record(frame_number, *port, input, true);
+                    input
+                }
+                (Some(history), None) => match history.get(frame_number, *port) {
+                    Some(entry) if entry.confirmed => entry.input,
+                    _ => {
+                        let predicted = history.predict(*port);
+                        history.record(frame_number, *port, predicted, false);
+                        predicted
+                    }
+                },
+                (None, input) => input.unwrap_or_default(),
+            };
+        }
+    }
+}
@@ -0,0 +1,252 @@
+use crate::input_frame::PortInput;
+use std::collections::VecDeque;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub frame: u64,
+    pub input: PortInput,
+    #[doc = "falso quando o input foi previsto e o input real ainda não chegou"]
+    pub confirmed: bool,
+}
+
+#[derive(Debug, Clone)]
+struct PortHistory {
+    port: i16,
+    entries: VecDeque<HistoryEntry>,
+    last_confirmed: Option<HistoryEntry>,
+}
+
+impl PortHistory {
+    fn index(&self, frame: u64) -> Option<usize> {
+        let first = self.entries.front()?.frame;
+
+        if frame < first {
+            return None;
+        }
+
+        let index = (frame - first) as usize;
+
+        match self.entries.get(index) {
+            Some(entry) if entry.frame == frame => Some(index),
+            _ => None,
+        }
+    }
+}
+
+/// # Input history
+///
+/// Ring buffer com o input de cada porta indexado pelo número do frame, usado para refazer os
+/// frames depois de um rollback.
+///
+/// Quando o input de uma porta remota não chegou a tempo o frame usa uma previsão, que é a repetição
+/// do último input confirmado da porta. Ao chegar o input real de um frame previsto os dois são
+/// comparados e, se forem diferentes, o frame é marcado como previsto errado.
+#[derive(Debug, Clone)]
+pub struct InputHistory {
+    capacity: usize,
+    ports: Vec<PortHistory>,
+    mispredicted: Vec<u64>,
+}
+
+impl InputHistory {
+    #[doc = "'capacity' é a quantidade de frames guardados por porta, ou seja, o máximo de frames de um rollback"]
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            ports: Vec::new(),
+            mispredicted: Vec::new(),
+        }
+    }
+
+    pub fn get(&self, frame: u64, port: i16) -> Option<HistoryEntry> {
+        let history = self.ports.iter().find(|history| history.port == port)?;
+        let index = history.index(frame)?;
+
+        history.entries.get(index).copied()
+    }
+
+    #[doc = "o frame mais antigo que ainda está no histórico de todas as portas"]
+    pub fn get_first_frame(&self) -> Option<u64> {
+        self.ports
+            .iter()
+            .filter_map(|history| history.entries.front())
+            .map(|entry| entry.frame)
+            .max()
+    }
+
+    #[doc = "repete o último input confirmado da porta, ou a porta solta se nada foi confirmado ainda"]
+    pub fn predict(&self, port: i16) -> PortInput {
+        self.ports
+            .iter()
+            .find(|history| history.port == port)
+            .and_then(|history| history.last_confirmed)
+            .map(|entry| entry.input)
+            .unwrap_or_default()
+    }
+
+    #[doc = "guarda o input usado pela porta no frame, substituindo o que já existia para esse frame"]
+    pub fn record(&mut self, frame: u64, port: i16, input: PortInput, confirmed: bool) {
+        let capacity = self.capacity;
+        let history = self.port_history(port);
+        let entry = HistoryEntry {
+            frame,
+            input,
+            confirmed,
+        };
+
+        if let Some(index) = history.index(frame) {
+            history.entries[index] = entry;
+        } else {
+            let is_next = history
+                .entries
+                .back()
+                .is_none_or(|last| last.frame + 1 == frame);
+
+            if !is_next {
+                history.entries.clear();
+            }
+
+            history.entries.push_back(entry);
+
+            while history.entries.len() > capacity {
+                history.entries.pop_front();
+            }
+        }
+
+        if confirmed
+            && history
+                .last_confirmed
+                .is_none_or(|last| last.frame <= frame)
+        {
+            history.last_confirmed = Some(entry);
+        }
+    }
+
+    #[doc = "confirma o input real de um frame que já foi montado, retorna verdadeiro se a previsão estava errada"]
+    pub fn confirm(&mut self, frame: u64, port: i16, input: PortInput) -> bool {
+        let Some(entry) = self.get(frame, port) else {
+            return false;
+        };
+
+        let mispredicted = !entry.confirmed && entry.input != input;
+        self.record(frame, port, input, true);
+
+        if mispredicted && !self.mispredicted.contains(&frame) {
+            self.mispredicted.push(frame);
+        }
+
+        mispredicted
+    }
+
+    #[doc = "retorna em ordem os frames previstos errado desde a última chamada"]
+    pub fn take_mispredicted(&mut self) -> Vec<u64> {
+        let mut frames = std::mem::take(&mut self.mispredicted);
+        frames.sort_unstable();
+        frames
+    }
+
+    pub fn remove_port(&mut self, port: i16) {
+        self.ports.retain(|history| history.port != port);
+    }
+
+    fn port_history(&mut self, port: i16) -> &mut PortHistory {
+        let index = match self.ports.iter().position(|history| history.port == port) {
+            Some(index) => index,
+            None => {
+                self.ports.push(PortHistory {
+                    port,
+                    entries: VecDeque::with_capacity(self.capacity),
+                    last_confirmed: None,
+                });
+                self.ports.len() - 1
+            }
+        };
+
+        &mut self.ports[index]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(bitmask: i16) -> PortInput {
+        PortInput {
+            bitmask,
+            analog: [0; 4],
+        }
+    }
+
+    #[test]
+    fn keeps_only_the_last_frames() {
+        let mut history = InputHistory::new(3);
+
+        for frame in 1..=5 {
+            history.record(frame, 0, input(frame as i16), true);
+        }
+
+        assert_eq!(history.get(2, 0), None);
+        assert_eq!(history.get(3, 0).unwrap().input, input(3));
+        assert_eq!(history.get(5, 0).unwrap().input, input(5));
+        assert_eq!(history.get(6, 0), None);
+        assert_eq!(history.get_first_frame(), Some(3));
+    }
+
+    #[test]
+    fn a_gap_restarts_the_port_history() {
+        let mut history = InputHistory::new(10);
+        history.record(1, 0, input(1), true);
+        history.record(2, 0, input(2), true);
+        history.record(5, 0, input(5), true);
+
+        assert_eq!(history.get(2, 0), None);
+        assert_eq!(history.get(5, 0).unwrap().input, input(5));
+    }
+
+    #[test]
+    fn first_frame_is_the_latest_start_of_all_ports() {
+        let mut history = InputHistory::new(10);
+        history.record(1, 0, input(0), true);
+        history.record(2, 0, input(0), true);
+        history.record(2, 1, input(0), true);
+
+        assert_eq!(history.get_first_frame(), Some(2));
+
+        history.remove_port(1);
+        assert_eq!(history.get_first_frame(), Some(1));
+        assert_eq!(InputHistory::new(0).get_first_frame(), None);
+    }
+
+    #[test]
+    fn predicts_the_last_confirmed_input() {
+        let mut history = InputHistory::new(10);
+        assert_eq!(history.predict(0), PortInput::default());
+
+        history.record(1, 0, input(4), true);
+        history.record(2, 0, input(8), false);
+        assert_eq!(history.predict(0), input(4));
+
+        //um input confirmado de um frame antigo não substitui o mais recente
+        history.record(3, 0, input(16), true);
+        history.record(1, 0, input(2), true);
+        assert_eq!(history.predict(0), input(16));
+    }
+
+    #[test]
+    fn confirm_reports_wrong_predictions_once() {
+        let mut history = InputHistory::new(10);
+        history.record(1, 0, input(1), false);
+        history.record(2, 0, input(1), false);
+        history.record(3, 0, input(1), false);
+
+        assert!(history.confirm(3, 0, input(2)));
+        assert!(!history.confirm(2, 0, input(1)));
+        assert!(history.confirm(1, 0, input(0)));
+        assert!(!history.confirm(1, 0, input(4)));
+        assert!(!history.confirm(9, 0, input(4)));
+
+        assert_eq!(history.take_mispredicted(), vec![1, 3]);
+        assert!(history.take_mispredicted().is_empty());
+        assert!(history.get(1, 0).unwrap().confirmed);
+    }
+}
@@ -0,0 +1,331 @@
+use crate::input_frame::InputFrame;
+use retro_ab::{erro_handle::ErroHandle, retro_sys::retro_log_level};
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread::{self, sleep, JoinHandle},
+    time::{Duration, Instant},
+};
+
+static ACCEPT_SLEEP_TIME: Duration = Duration::from_millis(5);
+static WRITE_TIMEOUT: Duration = Duration::from_millis(50);
+static HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(2);
+static MAX_REQUEST_SIZE: usize = 1024;
+static KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(10);
+static SSE_HEADERS: &str = "HTTP/1.1 200 OK\r\n\
+    Content-Type: text/event-stream\r\n\
+    Cache-Control: no-cache\r\n\
+    Connection: keep-alive\r\n\
+    Access-Control-Allow-Origin: *\r\n\r\n";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OverlayServerConfig {
+    #[doc = "precisa ser um endereço de loopback (ex: 127.0.0.1:55401), o input do jogador não deve sair da máquina"]
+    pub address: SocketAddr,
+    #[doc = "quantidade máxima de atualizações enviadas por segundo"]
+    pub max_rate: u32,
+}
+
+/// # Overlay server
+///
+/// Servidor HTTP de Server-Sent Events para overlays de stream (ex: uma browser source do OBS
+/// com `new EventSource("http://127.0.0.1:55401")`). Qualquer requisição recebe o stream, e cada
+/// evento é o frame que o Core está recebendo no input_state_callback:
+///
+/// ```text
+/// data: {"frame":1200,"ports":[{"bitmask":257,"analog":[0,0,0,0]},{"bitmask":0,"analog":[0,0,0,0]}]}
+/// ```
+///
+/// Um evento só é enviado quando o frame muda, respeitando o limite de max_rate.
+#[derive(Debug)]
+pub struct OverlayServer {
+    local_addr: SocketAddr,
+    running: Arc<Mutex<bool>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl OverlayServer {
+    pub fn start(
+        config: OverlayServerConfig,
+        current_frame: Arc<Mutex<(u64, InputFrame)>>,
+    ) -> Result<Self, ErroHandle> {
+        if !config.address.ip().is_loopback() {
+            return Err(ErroHandle {
+                level: retro_log_level::RETRO_LOG_ERROR,
+                message: format!(
+                    "não foi possível iniciar o servidor do overlay: {} não é um endereço de loopback",
+                    config.address
+                ),
+            });
+        }
+
+        let listener = TcpListener::bind(config.address).map_err(server_erro)?;
+        listener.set_nonblocking(true).map_err(server_erro)?;
+
+        let local_addr = listener.local_addr().map_err(server_erro)?;
+        let interval = Duration::from_secs(1) / config.max_rate.max(1);
+        let running = Arc::new(Mutex::new(true));
+        let thread_running = running.clone();
+
+        let thread = thread::spawn(move || {
+            let mut pending: Vec<PendingClient> = Vec::new();
+            let mut clients: Vec<TcpStream> = Vec::new();
+            let mut last_frame = None;
+            let mut last_send = Instant::now();
+            let mut last_keep_alive = Instant::now();
+
+            while *thread_running.lock().unwrap() {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        if stream.set_nonblocking(true).is_ok() {
+                            pending.push(PendingClient {
+                                stream,
+                                request: Vec::new(),
+                                accepted_at: Instant::now(),
+                            });
+                        }
+                        continue;
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                    Err(_) => break,
+                }
+
+                for mut client in std::mem::take(&mut pending) {
+                    match client.read_request() {
+                        Some(true) => {
+                            if let Some(stream) = open_stream(client.stream) {
+                                clients.push(stream);
+                            }
+                        }
+                        Some(false) if client.accepted_at.elapsed() < HANDSHAKE_TIMEOUT => {
+                            pending.push(client)
+                        }
+                        _ => {}
+                    }
+                }
+
+                if last_send.elapsed() >= interval {
+                    let (frame, input) = current_frame.lock().unwrap().clone();
+
+                    if last_frame != Some(frame) && !clients.is_empty() {
+                        let event = format!("data: {}\n\n", frame_to_json(frame, &input));
+
+                        broadcast(&mut clients, &event);
+                        last_frame = Some(frame);
+                        last_send = Instant::now();
+                    }
+                }
+
+                if last_keep_alive.elapsed() >= KEEP_ALIVE_INTERVAL {
+                    broadcast(&mut clients, ": keep-alive\n\n");
+                    last_keep_alive = Instant::now();
+                }
+
+                sleep(ACCEPT_SLEEP_TIME);
+            }
+        });
+
+        Ok(Self {
+            local_addr,
+            running,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    #[doc = "fecha todas as conexões e encerra a thread do servidor"]
+    pub fn stop(&mut self) {
+        *self.running.lock().unwrap() = false;
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for OverlayServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+//conexão que ainda não terminou de enviar a requisição, ela é lida sem bloquear para que um
+//cliente lento não atrase os eventos dos outros
+#[derive(Debug)]
+struct PendingClient {
+    stream: TcpStream,
+    request: Vec<u8>,
+    accepted_at: Instant,
+}
+
+impl PendingClient {
+    #[doc = "Some(true) quando a requisição terminou, Some(false) enquanto ela não chegou inteira e None se a conexão foi fechada"]
+    fn read_request(&mut self) -> Option<bool> {
+        let mut buf = [0u8; MAX_REQUEST_SIZE];
+
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => return None,
+                Ok(len) => self.request.extend_from_slice(&buf[..len]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(_) => return None,
+            }
+        }
+
+        //o conteúdo da requisição não importa, ela só precisa ser consumida antes da resposta
+        Some(
+            self.request.len() >= MAX_REQUEST_SIZE
+                || self.request.windows(4).any(|end| end == b"\r\n\r\n"),
+        )
+    }
+}
+
+fn open_stream(mut stream: TcpStream) -> Option<TcpStream> {
+    stream.set_nonblocking(false).ok()?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT)).ok()?;
+
+    stream.write_all(SSE_HEADERS.as_bytes()).ok()?;
+
+    Some(stream)
+}
+
+fn broadcast(clients: &mut Vec<TcpStream>, event: &str) {
+    clients.retain_mut(|client| client.write_all(event.as_bytes()).is_ok());
+}
+
+fn frame_to_json(frame_count: u64, frame: &InputFrame) -> String {
+    let ports: Vec<String> = frame
+        .ports
+        .iter()
+        .map(|input| {
+            let [lx, ly, rx, ry] = input.analog;
+            format!(
+                "{{\"bitmask\":{},\"analog\":[{lx},{ly},{rx},{ry}]}}",
+                input.bitmask as u16
+            )
+        })
+        .collect();
+
+    format!(
+        "{{\"frame\":{frame_count},\"ports\":[{}]}}",
+        ports.join(",")
+    )
+}
+
+fn server_erro(erro: std::io::Error) -> ErroHandle {
+    ErroHandle {
+        level: retro_log_level::RETRO_LOG_ERROR,
+        message: format!("não foi possível iniciar o servidor do overlay: {erro}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input_frame::PortInput;
+    use std::io::{BufRead, BufReader};
+
+    fn frame(bitmask: i16) -> InputFrame {
+        InputFrame {
+            ports: vec![PortInput {
+                bitmask,
+                analog: [1, -2, 3, i16::MIN],
+            }],
+            keyboard: Vec::new(),
+        }
+    }
+
+    fn connect(server: &OverlayServer) -> BufReader<TcpStream> {
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+
+        //os headers terminam na primeira linha vazia
+        while line != "\r\n" {
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+        }
+
+        reader
+    }
+
+    fn next_event(reader: &mut BufReader<TcpStream>) -> Option<serde_json::Value> {
+        let mut line = String::new();
+
+        loop {
+            line.clear();
+            reader.read_line(&mut line).ok()?;
+
+            if let Some(data) = line.strip_prefix("data: ") {
+                return serde_json::from_str(data).ok();
+            }
+        }
+    }
+
+    #[test]
+    fn events_follow_the_frame_at_most_max_rate_times_per_second() {
+        let current_frame = Arc::new(Mutex::new((1, frame(-1))));
+        let server = OverlayServer::start(
+            OverlayServerConfig {
+                address: "127.0.0.1:0".parse().unwrap(),
+                max_rate: 10,
+            },
+            current_frame.clone(),
+        )
+        .unwrap();
+
+        //um cliente que nunca envia a requisição não pode atrasar os outros
+        let _silent = TcpStream::connect(server.local_addr()).unwrap();
+        let mut reader = connect(&server);
+
+        let event = next_event(&mut reader).unwrap();
+        assert_eq!(
+            event,
+            serde_json::json!({
+                "frame": 1,
+                "ports": [{ "bitmask": 0xffff, "analog": [1, -2, 3, i16::MIN] }],
+            })
+        );
+
+        let start = Instant::now();
+        let updater = thread::spawn(move || {
+            for frame_count in 2..=500 {
+                *current_frame.lock().unwrap() = (frame_count, frame(0));
+                sleep(Duration::from_millis(1));
+            }
+        });
+
+        let mut frames = Vec::new();
+        while start.elapsed() < Duration::from_millis(400) {
+            frames.push(next_event(&mut reader).unwrap()["frame"].as_u64().unwrap());
+        }
+        updater.join().unwrap();
+
+        //em 400ms com 10 eventos por segundo cabem no máximo 5 eventos
+        assert!(!frames.is_empty() && frames.len() <= 5, "{frames:?}");
+        assert!(frames.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn refuses_addresses_outside_loopback() {
+        let result = OverlayServer::start(
+            OverlayServerConfig {
+                address: "0.0.0.0:0".parse().unwrap(),
+                max_rate: 10,
+            },
+            Arc::new(Mutex::new((0, InputFrame::default()))),
+        );
+
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,666 @@
+use crate::{
+    devices_manager::Device,
+    gamepad::{retro_gamepad::RetroGamePad, virtual_gamepad::VirtualGamePad},
+    middleware::swapped_port,
+    port_policy::{LowestFreePort, PortPolicy},
+};
+use gilrs::Button;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JoinMode {
+    #[doc = "um device sem porta recebe a menor porta livre ao pressionar esse botão"]
+    pub join_button: Button,
+    #[doc = "o device perde a porta depois de segurar esse botão durante 'leave_hold'"]
+    pub leave_button: Button,
+    pub leave_hold: Duration,
+}
+
+impl Default for JoinMode {
+    fn default() -> Self {
+        Self {
+            join_button: Button::Start,
+            leave_button: Button::Select,
+            leave_hold: Duration::from_millis(1500),
+        }
+    }
+}
+
+/// # Port assignment
+///
+/// Tudo o que decide em qual porta um device fica: a política de portas e os controles que
+/// foram desconectados mas ainda estão dentro do tempo de reconexão.
+///
+/// Enquanto esse tempo não acaba a porta do controle desconectado continua reservada, e se o
+/// mesmo controle (uuid do gilrs e nome) voltar ele recebe de novo o id, a porta e o key_map
+/// que tinha antes.
+///
+/// Os gamepads virtuais (ex: controles remotos) não fazem parte de connected_gamepads, as portas
+/// deles ficam guardadas aqui para que nenhum outro device receba a mesma porta. Eles nunca
+/// esperam pela reconexão, mas entram na mesma fila de espera quando ficam sem porta.
+///
+/// Devices que se conectam sem receber uma porta entram numa fila de espera, e quando uma porta
+/// fica livre o device que está esperando a mais tempo é o primeiro a receber uma porta da política.
+///
+/// No modo de entrada (JoinMode) nenhum device recebe porta ao se conectar, as portas são dadas
+/// na ordem em que os jogadores pressionam o botão de entrada e a fila de espera não é usada.
+///
+/// Com as portas travadas (ex: durante a partida) as portas atuais não mudam: devices novos vão
+/// para a fila de espera, ninguém é promovido, entra ou sai pelo JoinMode, e a porta de um device
+/// desconectado fica reservada até ele voltar, outro device recebê-la pelo assign_port ou as
+/// portas serem destravadas.
+///
+/// Com o auto pause ativado, a porta de um device que desconecta fica aguardando um controle até
+/// que o mesmo ou outro device receba essa porta, e enquanto houver uma porta aguardando o
+/// frontend deve manter o Core pausado.
+#[derive(Debug)]
+pub struct PortAssignment {
+    policy: Box<dyn PortPolicy>,
+    reconnect_grace: Duration,
+    pending: Vec<(RetroGamePad, Instant)>,
+    waiting: Vec<Uuid>,
+    join_mode: Option<JoinMode>,
+    lineup_locked: bool,
+    leaving: Vec<(Uuid, Instant)>,
+    ports_locked: bool,
+    auto_pause: bool,
+    awaiting_controller: Vec<i16>,
+    virtual_ports: Vec<(Uuid, i16)>,
+}
+
+impl PortAssignment {
+    pub fn new() -> Self {
+        Self {
+            policy: Box::new(LowestFreePort),
+            reconnect_grace: Duration::ZERO,
+            pending: Vec::new(),
+            waiting: Vec::new(),
+            join_mode: None,
+            lineup_locked: false,
+            leaving: Vec::new(),
+            ports_locked: false,
+            auto_pause: false,
+            awaiting_controller: Vec::new(),
+            virtual_ports: Vec::new(),
+        }
+    }
+
+    pub fn set_policy(&mut self, policy: Box<dyn PortPolicy>) {
+        self.policy = policy;
+    }
+
+    pub fn set_reconnect_grace(&mut self, grace: Duration) {
+        self.reconnect_grace = grace;
+    }
+
+    pub fn get_reconnect_grace(&self) -> Duration {
+        self.reconnect_grace
+    }
+
+    #[doc = "os devices da fila de espera também passam a precisar do botão de entrada"]
+    pub fn set_join_mode(&mut self, join_mode: Option<JoinMode>) {
+        if join_mode.is_some() {
+            self.waiting.clear();
+        }
+
+        self.join_mode = join_mode;
+        self.leaving.clear();
+    }
+
+    pub fn get_join_mode(&self) -> Option<JoinMode> {
+        self.join_mode
+    }
+
+    #[doc = "enquanto estiver travado ninguém entra ou sai pelos botões do JoinMode"]
+    pub fn set_lineup_locked(&mut self, locked: bool) {
+        self.lineup_locked = locked;
+        self.leaving.clear();
+    }
+
+    pub fn is_lineup_locked(&self) -> bool {
+        self.lineup_locked
+    }
+
+    #[doc = "ao destravar, as reservas passam a respeitar o tempo de reconexão e a fila de espera volta a andar"]
+    pub fn set_ports_locked(&mut self, locked: bool) {
+        self.ports_locked = locked;
+
+        //o tempo de reconexão dos controles que desconectaram com as portas travadas começa agora
+        if !locked {
+            for (_, since) in &mut self.pending {
+                *since = Instant::now();
+            }
+        }
+    }
+
+    pub fn is_ports_locked(&self) -> bool {
+        self.ports_locked
+    }
+
+    pub fn set_auto_pause(&mut self, enabled: bool) {
+        self.auto_pause = enabled;
+
+        if !enabled {
+            self.awaiting_controller.clear();
+        }
+    }
+
+    pub fn is_auto_pause_enabled(&self) -> bool {
+        self.auto_pause
+    }
+
+    #[doc = "portas que ficaram sem o seu controle com o auto pause ativado"]
+    pub fn get_awaiting_controller(&self) -> Vec<i16> {
+        self.awaiting_controller.clone()
+    }
+
+    #[doc = "retorna true se a porta passou a aguardar um controle"]
+    pub fn await_controller(&mut self, port: i16, gamepads: &[RetroGamePad]) -> bool {
+        if !self.auto_pause
+            || port < 0
+            || self.awaiting_controller.contains(&port)
+            || self.is_port_in_use(port, gamepads)
+        {
+            return false;
+        }
+
+        self.awaiting_controller.push(port);
+        true
+    }
+
+    fn is_port_in_use(&self, port: i16, gamepads: &[RetroGamePad]) -> bool {
+        gamepads.iter().any(|gamepad| gamepad.retro_port == port) || self.is_virtual_port(port)
+    }
+
+    #[doc = "a porta deixa de aguardar quando algum device está usando ela"]
+    pub fn update_awaiting_controller(&mut self, gamepads: &[RetroGamePad]) {
+        let virtual_ports = &self.virtual_ports;
+
+        self.awaiting_controller.retain(|port| {
+            !gamepads.iter().any(|gamepad| gamepad.retro_port == *port)
+                && !virtual_ports
+                    .iter()
+                    .any(|(_, virtual_port)| virtual_port == port)
+        });
+    }
+
+    #[doc = "as portas dos controles esperando a reconexão não são oferecidas para a política, e se ela escolher uma porta que não existe ou já está ocupada o gamepad recebe a menor porta livre"]
+    pub fn assign(
+        &mut self,
+        gamepad: &RetroGamePad,
+        gamepads: &[RetroGamePad],
+        max_ports: usize,
+    ) -> i16 {
+        if self.join_mode.is_some() || self.ports_locked {
+            return -1;
+        }
+
+        let reserved = self.with_pending(gamepads);
+        let port = self.policy.assign(gamepad, &reserved, max_ports);
+
+        self.checked_port(port, gamepads, max_ports)
+    }
+
+    //a política pode ser do usuário, então a porta escolhida é conferida antes de ser usada
+    fn checked_port(&self, port: i16, gamepads: &[RetroGamePad], max_ports: usize) -> i16 {
+        if port == -1 {
+            return -1;
+        }
+
+        let taken = port < 0
+            || port as usize >= max_ports
+            || self
+                .with_pending(gamepads)
+                .iter()
+                .any(|gamepad| gamepad.retro_port == port)
+            || self.is_virtual_port(port);
+
+        if taken {
+            self.lowest_free_port(gamepads, max_ports)
+        } else {
+            port
+        }
+    }
+
+    fn lowest_free_port(&self, gamepads: &[RetroGamePad], max_ports: usize) -> i16 {
+        let reserved = self.with_pending(gamepads);
+
+        (0..max_ports as i16)
+            .find(|port| {
+                !reserved.iter().any(|gamepad| gamepad.retro_port == *port)
+                    && !self.is_virtual_port(*port)
+            })
+            .unwrap_or(-1)
+    }
+
+    #[doc = "retorna a porta do gamepad virtual, -1 se não houver porta livre ou as portas estiverem travadas. Sem porta e fora do JoinMode ele entra na fila de espera"]
+    pub fn connect_virtual(
+        &mut self,
+        gamepad: &VirtualGamePad,
+        gamepads: &[RetroGamePad],
+        max_ports: usize,
+    ) -> i16 {
+        let port = self.assign_virtual(gamepad, gamepads, max_ports);
+
+        self.virtual_ports.push((gamepad.id, port));
+
+        if self.join_mode.is_none() && port < 0 {
+            self.waiting.push(gamepad.id);
+        }
+
+        port
+    }
+
+    fn assign_virtual(
+        &mut self,
+        gamepad: &VirtualGamePad,
+        gamepads: &[RetroGamePad],
+        max_ports: usize,
+    ) -> i16 {
+        if self.join_mode.is_some() || self.ports_locked {
+            return -1;
+        }
+
+        let reserved = self.with_pending(gamepads);
+        let port = self.policy.assign_virtual(gamepad, &reserved, max_ports);
+
+        self.checked_port(port, gamepads, max_ports)
+    }
+
+    #[doc = "'gamepad' deve estar com a porta que estava usando"]
+    pub fn disconnect_virtual(&mut self, gamepad: &VirtualGamePad) {
+        self.waiting.retain(|id| *id != gamepad.id);
+        self.policy.release_virtual(gamepad);
+        self.virtual_ports
+            .retain(|(virtual_id, _)| *virtual_id != gamepad.id);
+    }
+
+    #[doc = "deve ser chamado sempre que a porta de um gamepad virtual mudar"]
+    pub fn move_virtual(&mut self, id: Uuid, port: i16) {
+        for (virtual_id, virtual_port) in &mut self.virtual_ports {
+            if *virtual_id == id {
+                *virtual_port = port;
+            }
+        }
+    }
+
+    pub fn is_virtual_port(&self, port: i16) -> bool {
+        port >= 0 && self.virtual_ports.iter().any(|(_, p)| *p == port)
+    }
+
+    fn with_pending(&self, gamepads: &[RetroGamePad]) -> Vec<RetroGamePad> {
+        let mut reserved = gamepads.to_vec();
+        reserved.extend(self.pending.iter().map(|(pending, _)| pending.clone()));
+
+        reserved
+    }
+
+    #[doc = "devolve o controle que estava esperando a reconexão com o id, a porta e o key_map restaurados. Controles do mesmo modelo e nome não podem ser diferenciados, o que desconectou por último é o primeiro a ser restaurado"]
+    pub fn reconnect(
+        &mut self,
+        gamepad: &RetroGamePad,
+        gamepads: &[RetroGamePad],
+        max_ports: usize,
+    ) -> Option<RetroGamePad> {
+        let index = self.pending.iter().rposition(|(pending, _)| {
+            pending.native_uuid == gamepad.native_uuid && pending.name == gamepad.name
+        })?;
+
+        let (mut restored, _) = self.pending.remove(index);
+        restored.inner_id = gamepad.inner_id;
+        restored.analog = [0; 4];
+
+        for key_map in &mut restored.key_map {
+            key_map.pressed = false;
+        }
+
+        //o claim_port desfaz a reserva quando a porta é dada manualmente para outro device, então uma
+        //porta ainda reservada é devolvida mesmo com outros devices nela (ex: share_port)
+        if restored.retro_port < 0 || restored.retro_port as usize >= max_ports {
+            restored.retro_port = self.assign(&restored, gamepads, max_ports);
+        }
+
+        Some(restored)
+    }
+
+    #[doc = "as reservas e as portas aguardando um controle acompanham os jogadores trocados"]
+    pub fn swap_ports(&mut self, port: i16, other: i16) {
+        for (pending, _) in &mut self.pending {
+            pending.retro_port = swapped_port(pending.retro_port, port, other);
+        }
+
+        for awaiting in &mut self.awaiting_controller {
+            *awaiting = swapped_port(*awaiting, port, other);
+        }
+    }
+
+    #[doc = "a porta deixa de estar reservada para o controle desconectado, que volta sem porta se reconectar"]
+    pub fn claim_port(&mut self, port: i16) {
+        for (pending, _) in &mut self.pending {
+            if pending.retro_port == port {
+                pending.retro_port = -1;
+            }
+        }
+    }
+
+    #[doc = "coloca o device no fim da fila de espera se ele ficou sem porta ao se conectar"]
+    pub fn wait_for_port(&mut self, gamepad: &RetroGamePad) {
+        if self.join_mode.is_none() && gamepad.retro_port < 0 && !self.waiting.contains(&gamepad.id)
+        {
+            self.waiting.push(gamepad.id);
+        }
+    }
+
+    #[doc = "ids dos devices na fila de espera, o primeiro é o que está esperando a mais tempo"]
+    pub fn get_waiting(&self) -> Vec<Uuid> {
+        self.waiting.clone()
+    }
+
+    #[doc = "dá as portas livres para os devices da fila de espera, retorna os devices que receberam uma porta"]
+    pub fn promote_waiting(
+        &mut self,
+        gamepads: &mut [RetroGamePad],
+        virtual_gamepads: &mut [VirtualGamePad],
+        max_ports: usize,
+    ) -> Vec<Device> {
+        let mut promoted = Vec::new();
+
+        if self.ports_locked {
+            return promoted;
+        }
+
+        while let Some(&id) = self.waiting.first() {
+            let gamepad = gamepads.iter().position(|gamepad| gamepad.id == id);
+            let virtual_gamepad = virtual_gamepads.iter().position(|gamepad| gamepad.id == id);
+
+            //o device pode ter sido desconectado ou recebido uma porta pelo assign_port
+            let device = match (gamepad, virtual_gamepad) {
+                (Some(index), _) if gamepads[index].retro_port < 0 => {
+                    let port = self.assign(&gamepads[index], gamepads, max_ports);
+
+                    (port >= 0).then(|| {
+                        gamepads[index].retro_port = port;
+                        Device::from_gamepad(&gamepads[index])
+                    })
+                }
+                (_, Some(index)) if virtual_gamepads[index].retro_port < 0 => {
+                    let port = self.assign_virtual(&virtual_gamepads[index], gamepads, max_ports);
+
+                    (port >= 0).then(|| {
+                        virtual_gamepads[index].retro_port = port;
+                        self.move_virtual(id, port);
+                        Device::from_virtual(&virtual_gamepads[index])
+                    })
+                }
+                _ => {
+                    self.waiting.remove(0);
+                    continue;
+                }
+            };
+
+            //ninguém passa na frente de quem está esperando a mais tempo
+            let Some(device) = device else {
+                break;
+            };
+
+            self.waiting.remove(0);
+            promoted.push(device);
+        }
+
+        promoted
+    }
+
+    #[doc = "aplica os botões de entrada e saída do JoinMode, retorna a porta anterior de cada device que mudou de porta"]
+    pub fn update_lineup(
+        &mut self,
+        gamepads: &mut [RetroGamePad],
+        max_ports: usize,
+    ) -> Vec<(i16, RetroGamePad)> {
+        let Some(join_mode) = self.join_mode else {
+            return Vec::new();
+        };
+
+        if self.lineup_locked || self.ports_locked {
+            return Vec::new();
+        }
+
+        self.leaving
+            .retain(|(id, _)| gamepads.iter().any(|gamepad| gamepad.id == *id));
+
+        let mut changes = Vec::new();
+
+        for index in 0..gamepads.len() {
+            let gamepad = &gamepads[index];
+
+            if gamepad.retro_port < 0 {
+                if !gamepad.is_native_pressed(join_mode.join_button) {
+                    continue;
+                }
+
+                let port = self.lowest_free_port(gamepads, max_ports);
+
+                if port >= 0 {
+                    gamepads[index].retro_port = port;
+                    changes.push((-1, gamepads[index].clone()));
+                }
+            } else if gamepad.is_native_pressed(join_mode.leave_button) {
+                let id = gamepad.id;
+                let since = match self.leaving.iter().find(|(leaving, _)| *leaving == id) {
+                    Some((_, since)) => *since,
+                    None => {
+                        self.leaving.push((id, Instant::now()));
+                        continue;
+                    }
+                };
+
+                if since.elapsed() >= join_mode.leave_hold {
+                    self.leaving.retain(|(leaving, _)| *leaving != id);
+
+                    let from = gamepads[index].retro_port;
+                    gamepads[index].retro_port = -1;
+                    changes.push((from, gamepads[index].clone()));
+                }
+            } else {
+                let id = gamepad.id;
+                self.leaving.retain(|(leaving, _)| *leaving != id);
+            }
+        }
+
+        changes
+    }
+
+    #[doc = "deve ser chamado com o gamepad já removido de 'gamepads', retorna true se a porta ficou reservada pelas portas travadas. Se outros devices continuam na porta ela não fica reservada, mas o controle ainda volta para ela ao reconectar"]
+    pub fn disconnect(&mut self, gamepad: RetroGamePad, gamepads: &[RetroGamePad]) -> bool {
+        self.waiting.retain(|id| *id != gamepad.id);
+
+        if self.ports_locked && gamepad.retro_port >= 0 {
+            let reserved = !self.is_port_in_use(gamepad.retro_port, gamepads);
+            self.pending.push((gamepad, Instant::now()));
+            return reserved;
+        }
+
+        if self.reconnect_grace.is_zero() {
+            self.policy.release(&gamepad);
+        } else {
+            self.pending.push((gamepad, Instant::now()));
+        }
+
+        false
+    }
+
+    #[doc = "libera as portas dos controles que não voltaram dentro do tempo de reconexão"]
+    pub fn release_expired(&mut self) -> Vec<RetroGamePad> {
+        let grace = self.reconnect_grace;
+        let mut expired = Vec::new();
+
+        if self.ports_locked {
+            return expired;
+        }
+
+        self.pending.retain(|(gamepad, since)| {
+            let alive = since.elapsed() < grace;
+
+            if !alive {
+                expired.push(gamepad.clone());
+            }
+
+            alive
+        });
+
+        for gamepad in &expired {
+            self.policy.release(gamepad);
+        }
+
+        expired
+    }
+}
+
+impl Default for PortAssignment {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::port_policy::RememberLastPort;
+
+    //conecta um gamepad virtual da mesma forma que o RemoteServer
+    fn connect(assignment: &mut PortAssignment, name: &str, max_ports: usize) -> VirtualGamePad {
+        let mut gamepad = VirtualGamePad::new(name.to_string(), -1, 1);
+        gamepad.retro_port = assignment.connect_virtual(&gamepad, &[], max_ports);
+
+        gamepad
+    }
+
+    #[test]
+    fn virtual_pads_get_the_lowest_free_port() {
+        let mut assignment = PortAssignment::new();
+        let first = connect(&mut assignment, "a", 2);
+        let second = connect(&mut assignment, "b", 2);
+
+        assert_eq!(first.retro_port, 0);
+        assert_eq!(second.retro_port, 1);
+        assert_eq!(connect(&mut assignment, "c", 2).retro_port, -1);
+
+        assignment.disconnect_virtual(&first);
+        assert!(!assignment.is_virtual_port(0));
+        assert_eq!(connect(&mut assignment, "d", 2).retro_port, 0);
+
+        assignment.move_virtual(second.id, 5);
+        assert!(!assignment.is_virtual_port(1));
+        assert!(assignment.is_virtual_port(5));
+    }
+
+    #[test]
+    fn remembered_port_is_restored_only_while_it_is_free() {
+        let mut assignment = PortAssignment::new();
+        assignment.set_policy(Box::new(RememberLastPort::new()));
+
+        let first = connect(&mut assignment, "a", 4);
+        connect(&mut assignment, "b", 4);
+        assignment.disconnect_virtual(&first);
+
+        let first = connect(&mut assignment, "a", 4);
+        assert_eq!(first.retro_port, 0);
+        assignment.disconnect_virtual(&first);
+
+        //'c' fica com a porta lembrada de 'a', que recebe a menor porta livre
+        assert_eq!(connect(&mut assignment, "c", 4).retro_port, 0);
+        assert_eq!(connect(&mut assignment, "a", 4).retro_port, 2);
+    }
+
+    //sempre escolhe a mesma porta, mesmo que ela não exista ou já esteja ocupada
+    #[derive(Debug)]
+    struct FixedPort(i16);
+
+    impl PortPolicy for FixedPort {
+        fn assign(&mut self, _: &RetroGamePad, _: &[RetroGamePad], _: usize) -> i16 {
+            self.0
+        }
+
+        fn assign_virtual(&mut self, _: &VirtualGamePad, _: &[RetroGamePad], _: usize) -> i16 {
+            self.0
+        }
+    }
+
+    #[test]
+    fn port_chosen_by_the_policy_must_be_free() {
+        let mut assignment = PortAssignment::new();
+        assignment.set_reconnect_grace(Duration::from_secs(60));
+
+        let gamepad = RetroGamePad::test_gamepad(0, -1);
+        let gamepads = [RetroGamePad::test_gamepad(1, 0)];
+
+        for (port, expected) in [(7, 1), (-5, 1), (0, 1), (1, 1), (-1, -1)] {
+            assignment.set_policy(Box::new(FixedPort(port)));
+            assert_eq!(
+                assignment.assign(&gamepad, &gamepads, 2),
+                expected,
+                "{port}"
+            );
+        }
+
+        //a porta 1 fica reservada para o controle esperando a reconexão
+        assignment.disconnect(RetroGamePad::test_gamepad(2, 1), &gamepads);
+        assignment.set_policy(Box::new(FixedPort(1)));
+        assert_eq!(assignment.assign(&gamepad, &gamepads, 3), 2);
+
+        assignment.set_policy(Box::new(FixedPort(0)));
+        let remote = VirtualGamePad::new("a".to_string(), -1, 1);
+        assert_eq!(assignment.connect_virtual(&remote, &gamepads, 3), 2);
+        assert_eq!(assignment.assign(&gamepad, &gamepads, 3), -1);
+    }
+
+    #[test]
+    fn no_port_is_given_in_join_mode_or_with_ports_locked() {
+        let mut assignment = PortAssignment::new();
+
+        assignment.set_join_mode(Some(JoinMode::default()));
+        assert_eq!(connect(&mut assignment, "a", 4).retro_port, -1);
+
+        assignment.set_join_mode(None);
+        assignment.set_ports_locked(true);
+        assert_eq!(connect(&mut assignment, "b", 4).retro_port, -1);
+        assert!(!assignment.is_virtual_port(-1));
+
+        assignment.set_ports_locked(false);
+        assert_eq!(connect(&mut assignment, "c", 4).retro_port, 0);
+    }
+
+    #[test]
+    fn ports_await_a_controller_only_with_auto_pause() {
+        let mut assignment = PortAssignment::new();
+        assert!(!assignment.await_controller(0, &[]));
+
+        assignment.set_auto_pause(true);
+        assert!(assignment.await_controller(0, &[]));
+        assert!(!assignment.await_controller(0, &[]));
+        assert!(!assignment.await_controller(-1, &[]));
+
+        //outro device ainda está na porta
+        connect(&mut assignment, "a", 4);
+        assert!(!assignment.await_controller(0, &[]));
+
+        assignment.update_awaiting_controller(&[]);
+        assert!(assignment.get_awaiting_controller().is_empty());
+
+        assert!(assignment.await_controller(2, &[]));
+        assignment.set_auto_pause(false);
+        assert!(assignment.get_awaiting_controller().is_empty());
+    }
+
+    #[test]
+    fn awaiting_ports_follow_swapped_players() {
+        let mut assignment = PortAssignment::new();
+        assignment.set_auto_pause(true);
+        assignment.await_controller(0, &[]);
+        assignment.await_controller(3, &[]);
+
+        assignment.swap_ports(0, 1);
+
+        assert_eq!(assignment.get_awaiting_controller(), vec![1, 3]);
+    }
+}
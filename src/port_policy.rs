@@ -0,0 +1,152 @@
+use crate::gamepad::{retro_gamepad::RetroGamePad, virtual_gamepad::VirtualGamePad};
+use std::fmt::Debug;
+use uuid::Uuid;
+
+#[doc = "decide em qual porta um device recém conectado vai ficar"]
+pub trait PortPolicy: Debug + Send {
+    #[doc = "'gamepads' são os devices já conectados, deve retornar -1 para deixar o device sem porta"]
+    fn assign(
+        &mut self,
+        gamepad: &RetroGamePad,
+        gamepads: &[RetroGamePad],
+        max_ports: usize,
+    ) -> i16;
+
+    #[doc = "chamado quando o device é desconectado, ainda com a porta que ele estava usando"]
+    fn release(&mut self, _gamepad: &RetroGamePad) {}
+
+    #[doc = "o mesmo que assign para os gamepads virtuais, por padrão recebem a menor porta livre"]
+    fn assign_virtual(
+        &mut self,
+        _gamepad: &VirtualGamePad,
+        gamepads: &[RetroGamePad],
+        max_ports: usize,
+    ) -> i16 {
+        lowest_free_port(gamepads, max_ports)
+    }
+
+    #[doc = "o mesmo que release para os gamepads virtuais"]
+    fn release_virtual(&mut self, _gamepad: &VirtualGamePad) {}
+}
+
+#[doc = "a menor porta que nenhum device está usando, é a política padrão"]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LowestFreePort;
+
+impl PortPolicy for LowestFreePort {
+    fn assign(
+        &mut self,
+        _gamepad: &RetroGamePad,
+        gamepads: &[RetroGamePad],
+        max_ports: usize,
+    ) -> i16 {
+        lowest_free_port(gamepads, max_ports)
+    }
+}
+
+/// # Remember last port
+///
+/// Ao desconectar, a porta do controle é guardada junto com o modelo (uuid do gilrs) e o nome do
+/// controle. Quando um controle igual se conecta ele volta para essa porta se ela estiver livre,
+/// caso contrário recebe a menor porta livre.
+///
+/// Controles do mesmo modelo não podem ser diferenciados, cada um recebe uma das portas guardadas
+/// para aquele modelo. Os gamepads virtuais são lembrados apenas pelo nome.
+#[derive(Debug, Clone, Default)]
+pub struct RememberLastPort {
+    last_ports: Vec<(Uuid, String, i16)>,
+}
+
+impl RememberLastPort {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn take_port(
+        &mut self,
+        native_uuid: Uuid,
+        name: &str,
+        gamepads: &[RetroGamePad],
+        max_ports: usize,
+    ) -> i16 {
+        let remembered = self.last_ports.iter().position(|(uuid, last_name, port)| {
+            *uuid == native_uuid
+                && last_name == name
+                && (*port as usize) < max_ports
+                && !gamepads.iter().any(|g| g.retro_port == *port)
+        });
+
+        match remembered {
+            Some(index) => self.last_ports.remove(index).2,
+            None => lowest_free_port(gamepads, max_ports),
+        }
+    }
+}
+
+impl PortPolicy for RememberLastPort {
+    fn assign(
+        &mut self,
+        gamepad: &RetroGamePad,
+        gamepads: &[RetroGamePad],
+        max_ports: usize,
+    ) -> i16 {
+        self.take_port(gamepad.native_uuid, &gamepad.name, gamepads, max_ports)
+    }
+
+    fn release(&mut self, gamepad: &RetroGamePad) {
+        if gamepad.retro_port >= 0 {
+            self.last_ports.push((
+                gamepad.native_uuid,
+                gamepad.name.clone(),
+                gamepad.retro_port,
+            ));
+        }
+    }
+
+    fn assign_virtual(
+        &mut self,
+        gamepad: &VirtualGamePad,
+        gamepads: &[RetroGamePad],
+        max_ports: usize,
+    ) -> i16 {
+        self.take_port(Uuid::nil(), &gamepad.name, gamepads, max_ports)
+    }
+
+    fn release_virtual(&mut self, gamepad: &VirtualGamePad) {
+        if gamepad.retro_port >= 0 {
+            self.last_ports
+                .push((Uuid::nil(), gamepad.name.clone(), gamepad.retro_port));
+        }
+    }
+}
+
+#[doc = "nenhum device recebe porta ao se conectar, o frontend escolhe com RetroAbController::assign_port"]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ManualOnly;
+
+impl PortPolicy for ManualOnly {
+    fn assign(
+        &mut self,
+        _gamepad: &RetroGamePad,
+        _gamepads: &[RetroGamePad],
+        _max_ports: usize,
+    ) -> i16 {
+        -1
+    }
+
+    fn assign_virtual(
+        &mut self,
+        _gamepad: &VirtualGamePad,
+        _gamepads: &[RetroGamePad],
+        _max_ports: usize,
+    ) -> i16 {
+        -1
+    }
+}
+
+//se o valor retornado for -1 significa que todas as portas suportas pelo Core ja estão sendo usadas
+pub fn lowest_free_port(gamepads: &[RetroGamePad], max_ports: usize) -> i16 {
+    (0..max_ports as i16)
+        .find(|port| !gamepads.iter().any(|gamepad| gamepad.retro_port == *port))
+        .unwrap_or(-1)
+}
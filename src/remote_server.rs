@@ -0,0 +1,393 @@
+use crate::{
+    devices_manager::DeviceStateListener,
+    gamepad::{
+        retro_gamepad::RetroGamePad,
+        update_gamepad_state_handle::{connect_virtual_handle, disconnect_virtual_handle},
+        virtual_gamepad::VirtualGamePad,
+    },
+    port_assignment::PortAssignment,
+};
+use retro_ab::{erro_handle::ErroHandle, retro_sys::retro_log_level};
+use std::{
+    net::{SocketAddr, UdpSocket},
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+pub const PACKET_HELLO: u8 = 0x01;
+pub const PACKET_BUTTONS: u8 = 0x02;
+pub const PACKET_ANALOG: u8 = 0x03;
+pub const PACKET_BYE: u8 = 0x04;
+pub const PACKET_WELCOME: u8 = 0x81;
+
+#[doc = "hellos de novos clientes são ignorados enquanto o servidor já tiver esse número de clientes"]
+pub const MAX_CLIENTS: usize = 16;
+
+const MAX_PACKET_SIZE: usize = 512;
+const RECV_TIMEOUT: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RemoteServerConfig {
+    #[doc = "endereço onde o servidor vai escutar, ex: 0.0.0.0:55400 para aceitar celulares da rede local"]
+    pub address: SocketAddr,
+    #[doc = "o controle é desconectado se não enviar nenhum pacote durante esse tempo"]
+    pub timeout: Duration,
+}
+
+//estado do DevicesManager que o servidor precisa para conectar e desconectar os gamepads virtuais
+struct ServerDevices {
+    connected_gamepads: Arc<Mutex<Vec<RetroGamePad>>>,
+    virtual_gamepads: Arc<Mutex<Vec<VirtualGamePad>>>,
+    max_ports: Arc<Mutex<usize>>,
+    listener: Option<Arc<Mutex<DeviceStateListener>>>,
+    ports: Arc<Mutex<PortAssignment>>,
+}
+
+impl ServerDevices {
+    fn connect(&self, name: String) -> Uuid {
+        connect_virtual_handle(
+            name,
+            &self.connected_gamepads,
+            &self.virtual_gamepads,
+            &self.max_ports,
+            &self.listener,
+            &self.ports,
+        )
+        .id
+    }
+
+    fn disconnect(&self, gamepad: Uuid) {
+        disconnect_virtual_handle(
+            gamepad,
+            &self.connected_gamepads,
+            &self.virtual_gamepads,
+            &self.listener,
+            &self.ports,
+        );
+    }
+}
+
+#[derive(Debug, Clone)]
+struct RemoteClient {
+    address: SocketAddr,
+    gamepad: Uuid,
+    last_seen: Instant,
+}
+
+/// # Remote controller server
+///
+/// Recebe pacotes UDP de controles remotos (ex: um app de celular) e cria um [VirtualGamePad]
+/// para cada cliente, a porta é escolhida da mesma forma que nos gamepads físicos.
+/// Cada cliente é identificado pelo endereço de origem dos pacotes.
+///
+/// Os gamepads virtuais não têm um device do gilrs, então não disparam hotkeys, macros nem o
+/// JoinMode, o estado deles entra direto no frame da porta.
+///
+/// Todos os números são little endian e o primeiro byte de cada pacote é o seu tipo:
+///
+/// | tipo                | conteúdo                                                              |
+/// |---------------------|-----------------------------------------------------------------------|
+/// | 0x01 hello          | u8 tamanho do nome e o nome em utf8                                   |
+/// | 0x02 buttons        | u16 bitmask no formato RETRO_DEVICE_ID_JOYPAD_MASK                    |
+/// | 0x03 analog         | 4 × i16 [esquerdo x, esquerdo y, direito x, direito y]                |
+/// | 0x04 bye            | vazio                                                                 |
+/// | 0x81 welcome        | resposta do servidor ao hello, i16 com a porta recebida (-1 sem porta) |
+///
+/// Um controle que recebe -1 entra na fila de espera junto com os gamepads físicos, e quando ele
+/// recebe uma porta o DevicesManager envia o evento Promoted.
+///
+/// Pacotes de clientes que não enviaram o hello são ignorados, assim como o hello de novos
+/// clientes quando o servidor já tem [MAX_CLIENTS] clientes. O cliente deve enviar o estado
+/// periodicamente (ex: a cada 100ms) mesmo sem mudanças para não ser desconectado pelo timeout.
+#[derive(Debug)]
+pub struct RemoteServer {
+    local_addr: SocketAddr,
+    running: Arc<Mutex<bool>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl RemoteServer {
+    pub fn start(
+        config: RemoteServerConfig,
+        connected_gamepads: Arc<Mutex<Vec<RetroGamePad>>>,
+        virtual_gamepads: Arc<Mutex<Vec<VirtualGamePad>>>,
+        max_ports: Arc<Mutex<usize>>,
+        listener: Option<Arc<Mutex<DeviceStateListener>>>,
+        ports: Arc<Mutex<PortAssignment>>,
+    ) -> Result<Self, ErroHandle> {
+        let socket = UdpSocket::bind(config.address).map_err(server_erro)?;
+        socket
+            .set_read_timeout(Some(RECV_TIMEOUT))
+            .map_err(server_erro)?;
+
+        let local_addr = socket.local_addr().map_err(server_erro)?;
+        let running = Arc::new(Mutex::new(true));
+        let thread_running = running.clone();
+        let devices = ServerDevices {
+            connected_gamepads,
+            virtual_gamepads,
+            max_ports,
+            listener,
+            ports,
+        };
+
+        let thread = thread::spawn(move || {
+            let mut clients: Vec<RemoteClient> = Vec::new();
+            let mut buf = [0u8; MAX_PACKET_SIZE];
+
+            while *thread_running.lock().unwrap() {
+                if let Ok((len, address)) = socket.recv_from(&mut buf) {
+                    handle_packet(&buf[..len], address, &socket, &mut clients, &devices);
+                }
+
+                clients.retain(|client| {
+                    let alive = client.last_seen.elapsed() < config.timeout;
+
+                    if !alive {
+                        devices.disconnect(client.gamepad);
+                    }
+
+                    alive
+                });
+            }
+
+            for client in clients {
+                devices.disconnect(client.gamepad);
+            }
+        });
+
+        Ok(Self {
+            local_addr,
+            running,
+            thread: Some(thread),
+        })
+    }
+
+    #[doc = "útil quando a porta informada na configuração é 0 e o sistema escolhe uma porta livre"]
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    #[doc = "desconecta todos os controles remotos e encerra a thread do servidor"]
+    pub fn stop(&mut self) {
+        *self.running.lock().unwrap() = false;
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for RemoteServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn handle_packet(
+    packet: &[u8],
+    address: SocketAddr,
+    socket: &UdpSocket,
+    clients: &mut Vec<RemoteClient>,
+    devices: &ServerDevices,
+) {
+    let Some((&packet_type, payload)) = packet.split_first() else {
+        return;
+    };
+
+    let client = clients.iter().position(|client| client.address == address);
+
+    if packet_type == PACKET_HELLO {
+        let gamepad = match client {
+            Some(index) => clients[index].gamepad,
+            None if clients.len() >= MAX_CLIENTS => return,
+            None => {
+                let name = payload
+                    .split_first()
+                    .and_then(|(len, name)| name.get(..*len as usize))
+                    .and_then(|name| String::from_utf8(name.to_vec()).ok())
+                    .unwrap_or_else(|| address.to_string());
+
+                let gamepad = devices.connect(name);
+
+                clients.push(RemoteClient {
+                    address,
+                    gamepad,
+                    last_seen: Instant::now(),
+                });
+
+                gamepad
+            }
+        };
+
+        let port = devices
+            .virtual_gamepads
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|g| g.id == gamepad)
+            .map_or(-1, |g| g.retro_port);
+
+        let mut welcome = vec![PACKET_WELCOME];
+        welcome.extend_from_slice(&port.to_le_bytes());
+        let _ = socket.send_to(&welcome, address);
+    }
+
+    let Some(index) = client else {
+        return;
+    };
+
+    let client = &mut clients[index];
+    client.last_seen = Instant::now();
+
+    if packet_type == PACKET_BYE {
+        devices.disconnect(client.gamepad);
+        clients.remove(index);
+        return;
+    }
+
+    let mut gamepads = devices.virtual_gamepads.lock().unwrap();
+
+    let Some(gamepad) = gamepads.iter_mut().find(|g| g.id == client.gamepad) else {
+        return;
+    };
+
+    if packet_type == PACKET_BUTTONS && payload.len() >= 2 {
+        let bitmask = i16::from_le_bytes([payload[0], payload[1]]);
+        gamepad.set_state(bitmask, gamepad.analog);
+    } else if packet_type == PACKET_ANALOG && payload.len() >= 8 {
+        let mut analog = [0i16; 4];
+
+        for (value, bytes) in analog.iter_mut().zip(payload.chunks_exact(2)) {
+            *value = i16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+
+        gamepad.set_state(gamepad.bitmask, analog);
+    }
+}
+
+fn server_erro(erro: std::io::Error) -> ErroHandle {
+    ErroHandle {
+        level: retro_log_level::RETRO_LOG_ERROR,
+        message: format!("não foi possível iniciar o servidor de controles remotos: {erro}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Devices {
+        virtual_gamepads: Arc<Mutex<Vec<VirtualGamePad>>>,
+        server: RemoteServer,
+    }
+
+    fn start(timeout: Duration) -> Devices {
+        let virtual_gamepads = Arc::new(Mutex::new(Vec::new()));
+        let server = RemoteServer::start(
+            RemoteServerConfig {
+                address: "127.0.0.1:0".parse().unwrap(),
+                timeout,
+            },
+            Arc::new(Mutex::new(Vec::new())),
+            virtual_gamepads.clone(),
+            Arc::new(Mutex::new(2)),
+            None,
+            Arc::new(Mutex::new(PortAssignment::new())),
+        )
+        .unwrap();
+
+        Devices {
+            virtual_gamepads,
+            server,
+        }
+    }
+
+    fn client(devices: &Devices) -> UdpSocket {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+        socket.connect(devices.server.local_addr()).unwrap();
+        socket
+    }
+
+    fn hello(socket: &UdpSocket, name: &str) -> Option<i16> {
+        let mut packet = vec![PACKET_HELLO, name.len() as u8];
+        packet.extend_from_slice(name.as_bytes());
+        socket.send(&packet).unwrap();
+
+        let mut buf = [0u8; 8];
+        let len = socket.recv(&mut buf).ok()?;
+        assert_eq!(&buf[..1], &[PACKET_WELCOME]);
+        assert_eq!(len, 3);
+
+        Some(i16::from_le_bytes([buf[1], buf[2]]))
+    }
+
+    //a thread do servidor trata os pacotes sem avisar, então o estado é verificado até mudar
+    fn wait_until(devices: &Devices, condition: impl Fn(&[VirtualGamePad]) -> bool) -> bool {
+        let start = Instant::now();
+
+        while start.elapsed() < Duration::from_secs(2) {
+            if condition(&devices.virtual_gamepads.lock().unwrap()) {
+                return true;
+            }
+
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        false
+    }
+
+    #[test]
+    fn client_drives_a_virtual_gamepad() {
+        let devices = start(Duration::from_secs(10));
+        let socket = client(&devices);
+
+        assert_eq!(hello(&socket, "celular"), Some(0));
+        assert!(wait_until(&devices, |g| g.len() == 1 && g[0].name == "celular"));
+
+        socket.send(&[PACKET_BUTTONS, 0x09, 0x00]).unwrap();
+        assert!(wait_until(&devices, |g| g[0].bitmask == 0x09));
+
+        let mut analog = vec![PACKET_ANALOG];
+        for value in [100i16, -100, 0, i16::MIN] {
+            analog.extend_from_slice(&value.to_le_bytes());
+        }
+        socket.send(&analog).unwrap();
+        assert!(wait_until(&devices, |g| g[0].analog == [100, -100, 0, i16::MIN]));
+        assert_eq!(devices.virtual_gamepads.lock().unwrap()[0].bitmask, 0x09);
+
+        socket.send(&[PACKET_BYE]).unwrap();
+        assert!(wait_until(&devices, |g| g.is_empty()));
+    }
+
+    #[test]
+    fn silent_client_is_disconnected() {
+        let devices = start(Duration::from_millis(200));
+        let socket = client(&devices);
+
+        assert_eq!(hello(&socket, "celular"), Some(0));
+        assert!(wait_until(&devices, |g| g.len() == 1));
+        assert!(wait_until(&devices, |g| g.is_empty()));
+    }
+
+    #[test]
+    fn hello_beyond_the_client_limit_is_ignored() {
+        let devices = start(Duration::from_secs(10));
+        let sockets: Vec<UdpSocket> = (0..MAX_CLIENTS).map(|_| client(&devices)).collect();
+
+        for socket in &sockets {
+            assert!(hello(socket, "celular").is_some());
+        }
+
+        assert_eq!(hello(&client(&devices), "celular"), None);
+        assert_eq!(devices.virtual_gamepads.lock().unwrap().len(), MAX_CLIENTS);
+
+        //o hello de um cliente conhecido continua respondido
+        assert_eq!(hello(&sockets[1], "celular"), Some(1));
+    }
+}
@@ -1,9 +1,27 @@
 use crate::devices_manager::{DeviceRubble, DeviceStateListener, DevicesManager};
-use crate::gamepad::retro_gamepad::RetroGamePad;
+use crate::gamepad::{
+    button_gestures::GestureConfig, retro_gamepad::RetroGamePad, virtual_gamepad::VirtualGamePad,
+};
+use crate::hotkeys::{Hotkey, HotkeyAction};
+use crate::input_frame::{AnalogMerge, InputFrame, PortInput};
+use crate::input_macro::{InputMacro, MacroBinding};
+use crate::input_provider::{InputProvider, ProviderMode};
+use crate::middleware::InputMiddleware;
+use crate::movie::{player::MoviePlayer, MovieHeader};
+use crate::overlay_server::OverlayServerConfig;
+use crate::port_assignment::JoinMode;
+use crate::port_policy::PortPolicy;
+use crate::remote_server::RemoteServerConfig;
 use crate::state_thread::EventThread;
+use crate::turbo::{TurboButton, TurboMode};
+use gilrs::Button;
 use retro_ab::erro_handle::ErroHandle;
-use retro_ab::retro_sys::retro_rumble_effect;
+use retro_ab::retro_sys::{retro_rumble_effect, RETRO_DEVICE_ANALOG, RETRO_DEVICE_KEYBOARD};
+use std::net::SocketAddr;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use uuid::Uuid;
 
 lazy_static! {
     static ref DEVICES_MANAGER: Arc<Mutex<DevicesManager>> =
@@ -41,10 +59,84 @@ impl RetroAbController {
         DEVICES_MANAGER.lock().unwrap().get_gamepads()
     }
 
+    pub fn get_virtual_list(&self) -> Vec<VirtualGamePad> {
+        DEVICES_MANAGER.lock().unwrap().get_virtual_gamepads()
+    }
+
     pub fn set_max_port(max: usize) {
         DEVICES_MANAGER.lock().unwrap().set_max_port(max);
     }
 
+    #[doc = "define como os devices recebem uma porta ao se conectar, o padrão é LowestFreePort"]
+    pub fn set_port_policy(&self, port_policy: Box<dyn PortPolicy>) {
+        DEVICES_MANAGER.lock().unwrap().set_port_policy(port_policy);
+    }
+
+    #[doc = "se o mesmo controle voltar dentro desse tempo ele recebe de novo a porta e o key_map e o evento é Reconnected"]
+    pub fn set_reconnect_grace_period(&self, grace: Duration) {
+        DEVICES_MANAGER
+            .lock()
+            .unwrap()
+            .set_reconnect_grace_period(grace);
+    }
+
+    pub fn get_reconnect_grace_period(&self) -> Duration {
+        DEVICES_MANAGER.lock().unwrap().get_reconnect_grace_period()
+    }
+
+    #[doc = "o primeiro id é o próximo a receber uma porta quando alguma ficar livre"]
+    pub fn get_waiting_gamepads(&self) -> Vec<Uuid> {
+        DEVICES_MANAGER.lock().unwrap().get_waiting_gamepads()
+    }
+
+    #[doc = "modo \"pressione Start para entrar\", as portas são dadas na ordem em que os jogadores pressionam o botão"]
+    pub fn set_join_mode(&self, join_mode: Option<JoinMode>) {
+        DEVICES_MANAGER.lock().unwrap().set_join_mode(join_mode);
+    }
+
+    pub fn get_join_mode(&self) -> Option<JoinMode> {
+        DEVICES_MANAGER.lock().unwrap().get_join_mode()
+    }
+
+    #[doc = "trava os jogadores atuais, útil quando a partida começa"]
+    pub fn set_lineup_locked(&self, locked: bool) {
+        DEVICES_MANAGER.lock().unwrap().set_lineup_locked(locked);
+    }
+
+    pub fn is_lineup_locked(&self) -> bool {
+        DEVICES_MANAGER.lock().unwrap().is_lineup_locked()
+    }
+
+    #[doc = "deve ser ativado quando o conteúdo começa, as portas atuais ficam congeladas e quem desconectar tem a porta reservada"]
+    pub fn set_ports_locked(&self, locked: bool) {
+        DEVICES_MANAGER.lock().unwrap().set_ports_locked(locked);
+    }
+
+    pub fn is_ports_locked(&self) -> bool {
+        DEVICES_MANAGER.lock().unwrap().is_ports_locked()
+    }
+
+    #[doc = "quando um device com porta desconectar o evento AutoPause é enviado e is_awaiting_controller passa a retornar true"]
+    pub fn set_auto_pause(&self, enabled: bool) {
+        DEVICES_MANAGER.lock().unwrap().set_auto_pause(enabled);
+    }
+
+    pub fn is_auto_pause_enabled(&self) -> bool {
+        DEVICES_MANAGER.lock().unwrap().is_auto_pause_enabled()
+    }
+
+    #[doc = "o Core deve continuar pausado enquanto retornar true"]
+    pub fn is_awaiting_controller(&self) -> bool {
+        DEVICES_MANAGER.lock().unwrap().is_awaiting_controller()
+    }
+
+    pub fn get_ports_awaiting_controller(&self) -> Vec<i16> {
+        DEVICES_MANAGER
+            .lock()
+            .unwrap()
+            .get_ports_awaiting_controller()
+    }
+
     #[doc = "Para que o CORE possa 'tomar posse' com existo dos eventos do gamepad é necessário interromper o a thread de eventos"]
     pub fn stop_thread_events(&mut self) {
         self.event_thread.stop();
@@ -58,15 +150,370 @@ impl RetroAbController {
     pub fn apply_rumble(&self, rubble: DeviceRubble) {
         DEVICES_MANAGER.lock().unwrap().apply_rumble(rubble);
     }
+
+    #[doc = "ativa o turbo em um botão de uma porta, os pulsos são contados a cada input_poll_callback"]
+    pub fn set_turbo_button(&self, button: TurboButton) {
+        DEVICES_MANAGER.lock().unwrap().set_turbo_button(button);
+    }
+
+    pub fn remove_turbo_button(&self, port: i16, retro: u32) {
+        DEVICES_MANAGER
+            .lock()
+            .unwrap()
+            .remove_turbo_button(port, retro);
+    }
+
+    pub fn set_turbo_mode(&self, mode: TurboMode) {
+        DEVICES_MANAGER.lock().unwrap().set_turbo_mode(mode);
+    }
+
+    #[doc = "associa um combo de botões a uma ação, quando o combo é pressionado o listener recebe DeviceState::HotkeyPressed"]
+    pub fn add_hotkey(&self, hotkey: Hotkey) {
+        DEVICES_MANAGER.lock().unwrap().add_hotkey(hotkey);
+    }
+
+    pub fn remove_hotkey(&self, action: &HotkeyAction) {
+        DEVICES_MANAGER.lock().unwrap().remove_hotkey(action);
+    }
+
+    pub fn set_keyboard_key(&self, key: u32, pressed: bool) {
+        DEVICES_MANAGER
+            .lock()
+            .unwrap()
+            .set_keyboard_key(key, pressed);
+    }
+
+    #[doc = "grava o input de todas as portas a cada input_poll_callback em um movie"]
+    pub fn start_recording(
+        &self,
+        path: &Path,
+        core_name: &str,
+        content_hash: &[u8],
+    ) -> Result<(), ErroHandle> {
+        DEVICES_MANAGER
+            .lock()
+            .unwrap()
+            .start_recording(path, core_name, content_hash)
+    }
+
+    pub fn stop_recording(&self) -> Result<u64, ErroHandle> {
+        DEVICES_MANAGER.lock().unwrap().stop_recording()
+    }
+
+    #[doc = "enquanto o movie estiver em execução o input_state_callback responde com os frames gravados"]
+    pub fn play_movie(&self, path: &Path) -> Result<MovieHeader, ErroHandle> {
+        DEVICES_MANAGER.lock().unwrap().play_movie(path)
+    }
+
+    #[doc = "executa um movie já carregado, como os importados com movie::fm2::import_fm2 ou movie::bk2::import_bk2_input_log"]
+    pub fn load_movie(&self, player: MoviePlayer) {
+        DEVICES_MANAGER.lock().unwrap().load_movie(player);
+    }
+
+    #[doc = "interrompe o movie no frame atual e devolve o controle aos devices"]
+    pub fn take_over_movie(&self) -> Option<u64> {
+        DEVICES_MANAGER.lock().unwrap().take_over_movie()
+    }
+
+    #[doc = "agenda o take over para quando o movie entregar 'frame' frames ao Core"]
+    pub fn take_over_movie_at(&self, frame: u64) -> Result<(), ErroHandle> {
+        DEVICES_MANAGER.lock().unwrap().take_over_movie_at(frame)
+    }
+
+    pub fn is_playing_movie(&self) -> bool {
+        DEVICES_MANAGER.lock().unwrap().is_playing_movie()
+    }
+
+    #[doc = "a porta passa a receber o input do provider, substituindo ou combinando com os devices conectados nela"]
+    pub fn attach_input_provider(
+        &self,
+        port: i16,
+        provider: Box<dyn InputProvider>,
+        mode: ProviderMode,
+    ) {
+        DEVICES_MANAGER
+            .lock()
+            .unwrap()
+            .attach_input_provider(port, provider, mode);
+    }
+
+    pub fn detach_input_provider(&self, port: i16) -> Option<Box<dyn InputProvider>> {
+        DEVICES_MANAGER.lock().unwrap().detach_input_provider(port)
+    }
+
+    #[doc = "grava o input da porta a cada frame até stop_macro_recording ser chamado"]
+    pub fn start_macro_recording(&self, port: i16) {
+        DEVICES_MANAGER.lock().unwrap().start_macro_recording(port);
+    }
+
+    pub fn stop_macro_recording(&self, name: &str) -> Option<InputMacro> {
+        DEVICES_MANAGER.lock().unwrap().stop_macro_recording(name)
+    }
+
+    #[doc = "adiciona uma macro salva anteriormente, por exemplo no perfil do usuário com InputMacro::to_bytes"]
+    pub fn add_macro(&self, input_macro: InputMacro) {
+        DEVICES_MANAGER.lock().unwrap().add_macro(input_macro);
+    }
+
+    pub fn remove_macro(&self, name: &str) {
+        DEVICES_MANAGER.lock().unwrap().remove_macro(name);
+    }
+
+    pub fn get_macros(&self) -> Vec<InputMacro> {
+        DEVICES_MANAGER.lock().unwrap().get_macros()
+    }
+
+    #[doc = "quando o botão 'trigger' for pressionado em um controle da porta a macro é executada frame a frame"]
+    pub fn bind_macro(&self, binding: MacroBinding) {
+        DEVICES_MANAGER.lock().unwrap().bind_macro(binding);
+    }
+
+    pub fn unbind_macro(&self, port: i16, trigger: Button) {
+        DEVICES_MANAGER.lock().unwrap().unbind_macro(port, trigger);
+    }
+
+    #[cfg(feature = "scripting")]
+    #[doc = "carrega um script Rhai que pode alterar o input de todas as portas a cada frame, 'budget' é o tempo máximo do script por frame"]
+    pub fn load_script(&self, source: &str, budget: Duration) -> Result<(), ErroHandle> {
+        DEVICES_MANAGER.lock().unwrap().load_script(source, budget)
+    }
+
+    #[cfg(feature = "scripting")]
+    pub fn unload_script(&self) {
+        DEVICES_MANAGER.lock().unwrap().unload_script();
+    }
+
+    #[cfg(feature = "scripting")]
+    pub fn take_script_erro(&self) -> Option<ErroHandle> {
+        DEVICES_MANAGER.lock().unwrap().take_script_erro()
+    }
+
+    #[doc = "atrasa o input da porta em 'frames' frames, útil no netplay ou para imitar o atraso do hardware original"]
+    pub fn set_input_delay(&self, port: i16, frames: usize) {
+        DEVICES_MANAGER
+            .lock()
+            .unwrap()
+            .set_input_delay(port, frames);
+    }
+
+    pub fn get_input_delay(&self, port: i16) -> usize {
+        DEVICES_MANAGER.lock().unwrap().get_input_delay(port)
+    }
+
+    #[doc = "adiciona um estágio no fim do pipeline, o estágio é executado dentro do input_poll_callback e não deve chamar o RetroAbController"]
+    pub fn add_middleware(
+        &self,
+        middleware: Arc<Mutex<dyn InputMiddleware>>,
+    ) -> Result<(), ErroHandle> {
+        DEVICES_MANAGER.lock().unwrap().add_middleware(middleware)
+    }
+
+    pub fn insert_middleware_before(
+        &self,
+        stage_name: &str,
+        middleware: Arc<Mutex<dyn InputMiddleware>>,
+    ) -> Result<(), ErroHandle> {
+        DEVICES_MANAGER
+            .lock()
+            .unwrap()
+            .insert_middleware_before(stage_name, middleware)
+    }
+
+    pub fn insert_middleware_after(
+        &self,
+        stage_name: &str,
+        middleware: Arc<Mutex<dyn InputMiddleware>>,
+    ) -> Result<(), ErroHandle> {
+        DEVICES_MANAGER
+            .lock()
+            .unwrap()
+            .insert_middleware_after(stage_name, middleware)
+    }
+
+    pub fn remove_middleware(&self, stage_name: &str) -> Option<Arc<Mutex<dyn InputMiddleware>>> {
+        DEVICES_MANAGER
+            .lock()
+            .unwrap()
+            .remove_middleware(stage_name)
+    }
+
+    #[doc = "desabilitar um estágio em uma porta faz o input dessa porta passar pelo estágio sem alterações"]
+    pub fn set_middleware_port_enabled(
+        &self,
+        stage_name: &str,
+        port: i16,
+        enabled: bool,
+    ) -> Result<(), ErroHandle> {
+        DEVICES_MANAGER
+            .lock()
+            .unwrap()
+            .set_middleware_port_enabled(stage_name, port, enabled)
+    }
+
+    #[doc = "retorna os nomes dos estágios na ordem em que são executados"]
+    pub fn get_middleware_names(&self) -> Vec<String> {
+        DEVICES_MANAGER.lock().unwrap().get_middleware_names()
+    }
+
+    pub fn enable_rollback(&self, capacity: usize) {
+        DEVICES_MANAGER.lock().unwrap().enable_rollback(capacity);
+    }
+
+    pub fn disable_rollback(&self) {
+        DEVICES_MANAGER.lock().unwrap().disable_rollback();
+    }
+
+    #[doc = "chame depois de restaurar o estado do Core, os frames refeitos usam o input guardado no histórico e não passam pelos providers nem pelos middlewares, retorna erro durante a gravação ou reprodução de um movie"]
+    pub fn rollback_to(&self, frame_number: u64) -> Result<(), ErroHandle> {
+        DEVICES_MANAGER.lock().unwrap().rollback_to(frame_number)
+    }
+
+    #[doc = "verifique depois de receber o input remoto, se houver frames o Core deve voltar ao primeiro deles"]
+    pub fn take_mispredicted_frames(&self) -> Vec<u64> {
+        DEVICES_MANAGER.lock().unwrap().take_mispredicted_frames()
+    }
+
+    pub fn get_current_frame(&self) -> InputFrame {
+        DEVICES_MANAGER.lock().unwrap().get_current_frame()
+    }
+
+    #[doc = "chame depois do input_poll_callback para enviar o input do frame aos outros jogadores"]
+    pub fn encode_current_frame(&self) -> Result<Vec<u8>, ErroHandle> {
+        DEVICES_MANAGER.lock().unwrap().encode_current_frame()
+    }
+
+    pub fn set_remote_port(&self, port: i16, remote: bool) {
+        DEVICES_MANAGER
+            .lock()
+            .unwrap()
+            .set_remote_port(port, remote);
+    }
+
+    pub fn get_remote_ports(&self) -> Vec<i16> {
+        DEVICES_MANAGER.lock().unwrap().get_remote_ports()
+    }
+
+    pub fn submit_remote_input(&self, frame_number: u64, port: i16, input: PortInput) {
+        DEVICES_MANAGER
+            .lock()
+            .unwrap()
+            .submit_remote_input(frame_number, port, input);
+    }
+
+    #[doc = "recebe os bytes enviados por outro jogador com encode_current_frame, retorna o número do frame"]
+    pub fn submit_remote_frame(&self, bytes: &[u8]) -> Result<u64, ErroHandle> {
+        DEVICES_MANAGER.lock().unwrap().submit_remote_frame(bytes)
+    }
+
+    #[doc = "permite que celulares e tablets se conectem como gamepads pela rede, veja RemoteServer para o formato dos pacotes"]
+    pub fn start_remote_server(
+        &self,
+        config: RemoteServerConfig,
+    ) -> Result<SocketAddr, ErroHandle> {
+        self.stop_remote_server();
+        DEVICES_MANAGER.lock().unwrap().start_remote_server(config)
+    }
+
+    #[doc = "desconecta todos os controles remotos"]
+    pub fn stop_remote_server(&self) {
+        //o listener pode chamar o RetroAbController ao receber o Disconnected, então a thread do
+        //servidor é encerrada sem o lock do DEVICES_MANAGER
+        let server = DEVICES_MANAGER.lock().unwrap().take_remote_server();
+
+        if let Some(mut server) = server {
+            server.stop();
+        }
+    }
+
+    #[doc = "envia o input de cada frame como Server-Sent Events para overlays de stream, veja OverlayServer"]
+    pub fn start_overlay_server(
+        &self,
+        config: OverlayServerConfig,
+    ) -> Result<SocketAddr, ErroHandle> {
+        DEVICES_MANAGER.lock().unwrap().start_overlay_server(config)
+    }
+
+    pub fn stop_overlay_server(&self) {
+        DEVICES_MANAGER.lock().unwrap().stop_overlay_server();
+    }
+
+    #[doc = "troca o botão RETRO_DEVICE_ID_JOYPAD_* que o botão nativo do gamepad aciona, retorna erro se 'retro' não for um RETRO_DEVICE_ID_JOYPAD_*"]
+    pub fn set_gamepad_key_map(
+        &self,
+        gamepad_id: Uuid,
+        native: Button,
+        retro: u32,
+    ) -> Result<(), ErroHandle> {
+        DEVICES_MANAGER
+            .lock()
+            .unwrap()
+            .set_gamepad_key_map(gamepad_id, native, retro)
+    }
+
+    #[doc = "coloca o device na porta informada, ex: mover um controle para o jogador 2. A porta precisa estar livre"]
+    pub fn assign_port(&self, gamepad_id: Uuid, port: i16) -> Result<(), ErroHandle> {
+        DEVICES_MANAGER
+            .lock()
+            .unwrap()
+            .assign_port(gamepad_id, port)
+    }
+
+    #[doc = "coloca mais um gamepad na porta, ex: dois controles movendo o jogador 1 ao mesmo tempo. O teclado não é um device com porta, para usar o teclado junto com um gamepad anexe um InputProvider com ProviderMode::Combine que traduz as teclas para a porta"]
+    pub fn share_port(&self, gamepad_id: Uuid, port: i16) -> Result<(), ErroHandle> {
+        DEVICES_MANAGER.lock().unwrap().share_port(gamepad_id, port)
+    }
+
+    #[doc = "como os eixos analógicos dos devices de uma mesma porta são combinados, o padrão é AnalogMerge::LargestMagnitude"]
+    pub fn set_analog_merge(&self, analog_merge: AnalogMerge) {
+        DEVICES_MANAGER
+            .lock()
+            .unwrap()
+            .set_analog_merge(analog_merge);
+    }
+
+    pub fn get_analog_merge(&self) -> AnalogMerge {
+        DEVICES_MANAGER.lock().unwrap().get_analog_merge()
+    }
+
+    #[doc = "troca os jogadores das duas portas, um DeviceState::PortChanged é enviado para cada device movido. O turbo, as macros e o atraso de input das portas acompanham os jogadores, as portas remotas do netplay não"]
+    pub fn swap_ports(&self, port: i16, other: i16) -> Result<(), ErroHandle> {
+        DEVICES_MANAGER.lock().unwrap().swap_ports(port, other)
+    }
+
+    pub fn unassign_port(&self, gamepad_id: Uuid) -> Result<(), ErroHandle> {
+        DEVICES_MANAGER.lock().unwrap().unassign_port(gamepad_id)
+    }
+
+    #[cfg(all(unix, feature = "control-socket"))]
+    #[doc = "permite que outros programas consultem e alterem o input pelo Unix socket em 'path', chame depois de criar o RetroAbController"]
+    pub fn start_control_server(&self, path: &Path) -> Result<(), ErroHandle> {
+        DEVICES_MANAGER.lock().unwrap().start_control_server(path)
+    }
+
+    #[cfg(all(unix, feature = "control-socket"))]
+    pub fn stop_control_server(&self) {
+        DEVICES_MANAGER.lock().unwrap().stop_control_server();
+    }
+
+    #[doc = "altera os tempos usados para gerar ButtonHeld, ButtonDoubleTapped e ButtonLongReleased"]
+    pub fn set_gesture_config(&self, config: GestureConfig) {
+        DEVICES_MANAGER.lock().unwrap().set_gesture_config(config);
+    }
 }
 
 //***********ENVIE ESSAS CALLBACKS PARA CORE****************/
 pub fn input_poll_callback() {
-    DEVICES_MANAGER.lock().unwrap().update_state();
+    DEVICES_MANAGER.lock().unwrap().update_frame_state();
 }
 
-pub fn input_state_callback(port: i16, _device: i16, _index: i16, id: i16) -> i16 {
-    DEVICES_MANAGER.lock().unwrap().get_input_state(port, id)
+pub fn input_state_callback(port: i16, device: i16, index: i16, id: i16) -> i16 {
+    let manager = DEVICES_MANAGER.lock().unwrap();
+
+    match device as u32 {
+        RETRO_DEVICE_ANALOG => manager.get_analog_state(port, index, id),
+        RETRO_DEVICE_KEYBOARD => manager.get_keyboard_state(id),
+        _ => manager.get_input_state(port, id),
+    }
 }
 
 pub fn rumble_callback(
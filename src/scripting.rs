@@ -0,0 +1,297 @@
+use crate::{
+    input_frame::{InputFrame, PortInput},
+    middleware::{InputMiddleware, MiddlewareContext, SCRIPT_STAGE},
+};
+use retro_ab::{erro_handle::ErroHandle, retro_sys};
+use rhai::{
+    module_resolvers::DummyModuleResolver, Array, CallFnOptions, Dynamic, Engine, Map, Module,
+    Scope, AST,
+};
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+static SCRIPT_FUNCTION: &str = "on_frame";
+static MAX_OPERATIONS: u64 = 1_000_000;
+static PROGRESS_CHECK_INTERVAL: u64 = 256;
+
+/// # Frame script
+///
+/// Script Rhai executado a cada input_poll_callback depois que o input dos devices foi lido, o
+/// retorno do script é o que o Core recebe no input_state_callback.
+///
+/// O script deve declarar a função `on_frame(frame, ports)`, `ports` é um array com um mapa
+/// `#{ bitmask, analog }` por porta e deve ser retornado com as alterações desejadas. Os botões estão
+/// disponíveis no módulo `joypad` (ex: `joypad::A`) e `this` pode ser usado para guardar estado
+/// entre os frames:
+///
+/// ```rhai
+/// fn on_frame(frame, ports) {
+///     if this == () { this = #{ run: false }; }
+///     if ports[0].bitmask & (1 << joypad::SELECT) != 0 { this.run = true; }
+///     if this.run { ports[0].bitmask = ports[0].bitmask | (1 << joypad::B); }
+///     ports
+/// }
+/// ```
+///
+/// O script não tem acesso a arquivos nem a módulos externos, e cada frame tem um limite de tempo
+/// e de operações, se o limite for ultrapassado o frame chega ao Core sem alterações.
+#[derive(Debug)]
+pub struct FrameScript {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    state: Dynamic,
+    deadline: Arc<Mutex<Instant>>,
+    budget: Duration,
+    last_erro: Option<String>,
+}
+
+impl FrameScript {
+    pub fn new(source: &str, budget: Duration) -> Result<Self, ErroHandle> {
+        let deadline = Arc::new(Mutex::new(Instant::now() + budget));
+        let mut engine = Engine::new();
+
+        //o Engine::new vem com print, debug e eval habilitados, o script não deve escrever no stdout nem avaliar código
+        engine.on_print(|_| {});
+        engine.on_debug(|_, _, _| {});
+        engine.disable_symbol("eval");
+        engine.set_module_resolver(DummyModuleResolver::new());
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.set_max_call_levels(32);
+        engine.set_max_expr_depths(64, 32);
+        engine.set_max_string_size(4096);
+        engine.set_max_array_size(1024);
+        engine.set_max_map_size(256);
+        engine.register_static_module("joypad", joypad_module().into());
+
+        let progress_deadline = deadline.clone();
+        engine.on_progress(move |operations| {
+            if operations % PROGRESS_CHECK_INTERVAL != 0 {
+                return None;
+            }
+
+            if Instant::now() > *progress_deadline.lock().unwrap() {
+                Some("o script ultrapassou o tempo limite do frame".into())
+            } else {
+                None
+            }
+        });
+
+        let ast = engine
+            .compile(source)
+            .map_err(|e| script_erro(e.to_string()))?;
+
+        if !ast.iter_functions().any(|f| f.name == SCRIPT_FUNCTION) {
+            return Err(script_erro(format!(
+                "o script precisa declarar a função {SCRIPT_FUNCTION}(frame, ports)"
+            )));
+        }
+
+        let mut scope = Scope::new();
+        engine
+            .run_ast_with_scope(&mut scope, &ast)
+            .map_err(|e| script_erro(e.to_string()))?;
+
+        Ok(Self {
+            engine,
+            ast,
+            scope,
+            state: Dynamic::UNIT,
+            deadline,
+            budget,
+            last_erro: None,
+        })
+    }
+
+    #[doc = "se o script falhar o frame não é alterado e o erro fica disponível em take_erro"]
+    pub fn run(&mut self, frame_count: u64, frame: &mut InputFrame) {
+        *self.deadline.lock().unwrap() = Instant::now() + self.budget;
+
+        let ports: Array = frame.ports.iter().map(port_to_dynamic).collect();
+        let options = CallFnOptions::new()
+            .eval_ast(false)
+            .rewind_scope(false)
+            .bind_this_ptr(&mut self.state);
+
+        let result = self.engine.call_fn_with_options::<Array>(
+            options,
+            &mut self.scope,
+            &self.ast,
+            SCRIPT_FUNCTION,
+            (frame_count as i64, ports),
+        );
+
+        match result.map_err(|e| e.to_string()).and_then(ports_from_array) {
+            Ok(ports) => frame.ports = ports,
+            Err(e) => self.last_erro = Some(e),
+        }
+    }
+
+    pub fn take_erro(&mut self) -> Option<ErroHandle> {
+        self.last_erro.take().map(script_erro)
+    }
+}
+
+fn joypad_module() -> Module {
+    let mut module = Module::new();
+    let buttons = [
+        ("B", retro_sys::RETRO_DEVICE_ID_JOYPAD_B),
+        ("Y", retro_sys::RETRO_DEVICE_ID_JOYPAD_Y),
+        ("SELECT", retro_sys::RETRO_DEVICE_ID_JOYPAD_SELECT),
+        ("START", retro_sys::RETRO_DEVICE_ID_JOYPAD_START),
+        ("UP", retro_sys::RETRO_DEVICE_ID_JOYPAD_UP),
+        ("DOWN", retro_sys::RETRO_DEVICE_ID_JOYPAD_DOWN),
+        ("LEFT", retro_sys::RETRO_DEVICE_ID_JOYPAD_LEFT),
+        ("RIGHT", retro_sys::RETRO_DEVICE_ID_JOYPAD_RIGHT),
+        ("A", retro_sys::RETRO_DEVICE_ID_JOYPAD_A),
+        ("X", retro_sys::RETRO_DEVICE_ID_JOYPAD_X),
+        ("L", retro_sys::RETRO_DEVICE_ID_JOYPAD_L),
+        ("R", retro_sys::RETRO_DEVICE_ID_JOYPAD_R),
+        ("L2", retro_sys::RETRO_DEVICE_ID_JOYPAD_L2),
+        ("R2", retro_sys::RETRO_DEVICE_ID_JOYPAD_R2),
+        ("L3", retro_sys::RETRO_DEVICE_ID_JOYPAD_L3),
+        ("R3", retro_sys::RETRO_DEVICE_ID_JOYPAD_R3),
+    ];
+
+    for (name, retro) in buttons {
+        module.set_var(name, retro as i64);
+    }
+
+    module
+}
+
+fn port_to_dynamic(input: &PortInput) -> Dynamic {
+    let mut map = Map::new();
+    let analog: Array = input
+        .analog
+        .iter()
+        .map(|value| Dynamic::from(*value as i64))
+        .collect();
+
+    map.insert("bitmask".into(), Dynamic::from(input.bitmask as u16 as i64));
+    map.insert("analog".into(), Dynamic::from(analog));
+
+    Dynamic::from(map)
+}
+
+fn ports_from_array(ports: Array) -> Result<Vec<PortInput>, String> {
+    ports
+        .into_iter()
+        .map(|port| {
+            let map = port
+                .try_cast::<Map>()
+                .ok_or("cada porta retornada deve ser um mapa #{ bitmask, analog }")?;
+
+            let bitmask = map
+                .get("bitmask")
+                .and_then(|value| value.as_int().ok())
+                .ok_or("bitmask deve ser um número")?;
+
+            let mut input = PortInput {
+                bitmask: bitmask as u16 as i16,
+                ..Default::default()
+            };
+
+            if let Some(analog) = map
+                .get("analog")
+                .and_then(|a| a.clone().try_cast::<Array>())
+            {
+                for (value, new_value) in input.analog.iter_mut().zip(analog) {
+                    let new_value = new_value
+                        .as_int()
+                        .map_err(|_| "analog deve ser um número")?;
+                    *value = new_value.clamp(i16::MIN as i64, i16::MAX as i64) as i16;
+                }
+            }
+
+            Ok(input)
+        })
+        .collect()
+}
+
+fn script_erro(message: String) -> ErroHandle {
+    ErroHandle {
+        level: retro_sys::retro_log_level::RETRO_LOG_ERROR,
+        message,
+    }
+}
+
+impl InputMiddleware for Option<FrameScript> {
+    fn name(&self) -> &str {
+        SCRIPT_STAGE
+    }
+
+    fn process(&mut self, context: &MiddlewareContext, frame: &mut InputFrame) {
+        if let Some(script) = self {
+            script.run(context.frame_count, frame);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame() -> InputFrame {
+        InputFrame {
+            ports: vec![PortInput::default(); 2],
+            keyboard: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn changes_the_frame_and_keeps_state() {
+        let source = "
+            fn on_frame(frame, ports) {
+                if this == () { this = 0; }
+                this += 1;
+                ports[0].bitmask = 1 << joypad::R3;
+                ports[1].analog = [this, -100000, 0, 0];
+                ports
+            }
+        ";
+        let mut script = FrameScript::new(source, Duration::from_secs(1)).unwrap();
+        let mut input = frame();
+
+        script.run(0, &mut input);
+        script.run(1, &mut input);
+
+        assert!(script.take_erro().is_none());
+        assert_eq!(input.ports[0].bitmask, i16::MIN);
+        assert_eq!(input.ports[1].analog, [2, i16::MIN, 0, 0]);
+    }
+
+    #[test]
+    fn rejects_invalid_scripts() {
+        let budget = Duration::from_secs(1);
+
+        assert!(FrameScript::new("fn on_frame(frame, ports) {", budget).is_err());
+        assert!(FrameScript::new("fn other(frame, ports) { ports }", budget).is_err());
+        assert!(FrameScript::new("import \"file\" as f; fn on_frame(a, b) { b }", budget).is_err());
+        assert!(FrameScript::new("eval(\"1\"); fn on_frame(a, b) { b }", budget).is_err());
+    }
+
+    #[test]
+    fn keeps_the_frame_when_the_script_fails() {
+        let budget = Duration::from_secs(1);
+        let scripts = [
+            "fn on_frame(frame, ports) { 1 }",
+            "fn on_frame(frame, ports) { [1] }",
+            "fn on_frame(frame, ports) { [#{ bitmask: \"a\" }] }",
+            "fn on_frame(frame, ports) { loop {} }",
+        ];
+
+        for source in scripts {
+            let mut script = FrameScript::new(source, budget).unwrap();
+            let mut input = frame();
+
+            script.run(0, &mut input);
+
+            assert_eq!(input, frame(), "{source}");
+            assert!(script.take_erro().is_some(), "{source}");
+            assert!(script.take_erro().is_none());
+        }
+    }
+}
@@ -0,0 +1,386 @@
+use crate::{
+    devices_manager::DevicesManager,
+    input_frame::PortInput,
+    input_provider::{InputProvider, ProviderMode},
+    RetroAbController,
+};
+use retro_ab::{
+    erro_handle::ErroHandle,
+    retro_sys::{
+        retro_log_level, RETRO_DEVICE_ID_JOYPAD_A, RETRO_DEVICE_ID_JOYPAD_B,
+        RETRO_DEVICE_ID_JOYPAD_DOWN, RETRO_DEVICE_ID_JOYPAD_L, RETRO_DEVICE_ID_JOYPAD_L2,
+        RETRO_DEVICE_ID_JOYPAD_L3, RETRO_DEVICE_ID_JOYPAD_LEFT, RETRO_DEVICE_ID_JOYPAD_R,
+        RETRO_DEVICE_ID_JOYPAD_R2, RETRO_DEVICE_ID_JOYPAD_R3, RETRO_DEVICE_ID_JOYPAD_RIGHT,
+        RETRO_DEVICE_ID_JOYPAD_SELECT, RETRO_DEVICE_ID_JOYPAD_START, RETRO_DEVICE_ID_JOYPAD_UP,
+        RETRO_DEVICE_ID_JOYPAD_X, RETRO_DEVICE_ID_JOYPAD_Y,
+    },
+};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScriptEvent {
+    #[doc = "frame relativo ao início do script, o primeiro input_poll_callback depois do attach é o frame 0"]
+    pub frame: u64,
+    pub port: i16,
+    pub retro: u32,
+    pub pressed: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InputScript {
+    events: Vec<ScriptEvent>,
+}
+
+impl InputScript {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[doc = "'retro' deve ser um RETRO_DEVICE_ID_JOYPAD_*, valores a partir de 16 retornam erro"]
+    pub fn press(mut self, frame: u64, port: i16, retro: u32) -> Result<Self, ErroHandle> {
+        self.push(frame, port, retro, true)?;
+        Ok(self)
+    }
+
+    pub fn release(mut self, frame: u64, port: i16, retro: u32) -> Result<Self, ErroHandle> {
+        self.push(frame, port, retro, false)?;
+        Ok(self)
+    }
+
+    #[doc = "pressiona o botão no frame informado e solta depois de 'frames' frames"]
+    pub fn tap(self, frame: u64, port: i16, retro: u32, frames: u64) -> Result<Self, ErroHandle> {
+        self.press(frame, port, retro)?
+            .release(frame + frames.max(1), port, retro)
+    }
+
+    #[doc = "lê um script em texto com uma ação por linha no formato 'frame ação botão porta' (ex: '120 press start 0'), a porta pode ser omitida e '#' inicia um comentário. Os botões aceitos são os nomes do RETRO_DEVICE_ID_JOYPAD_* em minúsculo (a, b, start, l2...)"]
+    pub fn parse(text: &str) -> Result<Self, ErroHandle> {
+        let mut script = Self::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+
+            let (frame, action, button, port) = match fields[..] {
+                [frame, action, button, port] => (frame, action, button, port),
+                [frame, action, button] => (frame, action, button, "0"),
+                _ => return Err(script_erro(number, "esperado 'frame ação botão porta'")),
+            };
+
+            let frame = frame
+                .parse()
+                .map_err(|_| script_erro(number, "frame inválido"))?;
+            let port = port
+                .parse()
+                .map_err(|_| script_erro(number, "porta inválida"))?;
+            let retro = retro_from_name(button)
+                .ok_or_else(|| script_erro(number, &format!("botão '{button}' desconhecido")))?;
+
+            script = match action {
+                "press" => script.press(frame, port, retro)?,
+                "release" => script.release(frame, port, retro)?,
+                _ => return Err(script_erro(number, "a ação deve ser 'press' ou 'release'")),
+            };
+        }
+
+        Ok(script)
+    }
+
+    #[doc = "último frame em que o script altera algum botão"]
+    pub fn last_frame(&self) -> u64 {
+        self.events
+            .iter()
+            .map(|event| event.frame)
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn ports(&self) -> Vec<i16> {
+        let mut ports: Vec<i16> = self.events.iter().map(|event| event.port).collect();
+        ports.sort();
+        ports.dedup();
+        ports
+    }
+
+    pub fn get_port_input(&self, port: i16, frame: u64) -> PortInput {
+        let mut input = PortInput::default();
+
+        for event in self
+            .events
+            .iter()
+            .filter(|event| event.port == port && event.frame <= frame)
+        {
+            if event.pressed {
+                input.bitmask |= 1 << event.retro;
+            } else {
+                input.bitmask &= !(1 << event.retro);
+            }
+        }
+
+        input
+    }
+
+    fn push(&mut self, frame: u64, port: i16, retro: u32, pressed: bool) -> Result<(), ErroHandle> {
+        if retro >= 16 {
+            return Err(harness_erro(format!(
+                "o botão {retro} não é um RETRO_DEVICE_ID_JOYPAD_*"
+            )));
+        }
+
+        let event = ScriptEvent {
+            frame,
+            port,
+            retro,
+            pressed,
+        };
+
+        //eventos do mesmo frame mantêm a ordem em que foram adicionados
+        let index = self.events.partition_point(|e| e.frame <= frame);
+        self.events.insert(index, event);
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct ScriptedPort {
+    script: Arc<InputScript>,
+    port: i16,
+    start: Option<u64>,
+    frames: Arc<Mutex<u64>>,
+}
+
+impl InputProvider for ScriptedPort {
+    fn poll(&mut self, frame: u64) -> PortInput {
+        let start = *self.start.get_or_insert(frame);
+        let frame = frame - start;
+
+        //todas as portas do script avançam juntas e compartilham o mesmo contador
+        let mut frames = self.frames.lock().unwrap();
+        *frames = (*frames).max(frame + 1);
+
+        self.script.get_port_input(self.port, frame)
+    }
+}
+
+#[doc = "entrega um InputScript ao Core através do input_poll_callback e input_state_callback sem precisar de nenhum controle conectado, útil para testar Cores no CI (ex: apertar Start no frame 120 para passar pelo menu da rom). Aviso: pare a thread de eventos com RetroAbController::stop_thread_events antes de iniciar o Core"]
+#[derive(Debug)]
+pub struct TestHarness {
+    script: Arc<InputScript>,
+    frames: Arc<Mutex<u64>>,
+}
+
+impl TestHarness {
+    #[doc = "as portas usadas pelo script passam a ignorar os gamepads conectados"]
+    pub fn attach(controller: &RetroAbController, script: InputScript) -> Self {
+        Self::new(script, |port, provider| {
+            controller.attach_input_provider(port, provider, ProviderMode::Replace)
+        })
+    }
+
+    #[doc = "igual ao attach, mas usando um DevicesManager próprio em vez do RetroAbController, os frames são montados com DevicesManager::update_frame_state e não precisam do gilrs nem da thread de eventos"]
+    pub fn attach_to_manager(manager: &DevicesManager, script: InputScript) -> Self {
+        Self::new(script, |port, provider| {
+            manager.attach_input_provider(port, provider, ProviderMode::Replace)
+        })
+    }
+
+    pub fn detach(self, controller: &RetroAbController) {
+        for port in self.script.ports() {
+            controller.detach_input_provider(port);
+        }
+    }
+
+    pub fn detach_from_manager(self, manager: &DevicesManager) {
+        for port in self.script.ports() {
+            manager.detach_input_provider(port);
+        }
+    }
+
+    fn new(script: InputScript, mut attach: impl FnMut(i16, Box<dyn InputProvider>)) -> Self {
+        let script = Arc::new(script);
+        let frames = Arc::new(Mutex::new(0));
+
+        for port in script.ports() {
+            attach(
+                port,
+                Box::new(ScriptedPort {
+                    script: script.clone(),
+                    port,
+                    start: None,
+                    frames: frames.clone(),
+                }),
+            );
+        }
+
+        Self { script, frames }
+    }
+
+    #[doc = "quantidade de frames entregues ao Core desde o attach"]
+    pub fn frame_count(&self) -> u64 {
+        *self.frames.lock().unwrap()
+    }
+
+    #[doc = "verdadeiro depois que o último evento do script já foi entregue ao Core"]
+    pub fn is_finished(&self) -> bool {
+        self.frame_count() > self.script.last_frame()
+    }
+
+    pub fn check_frame_count(&self, expected: u64) -> Result<(), ErroHandle> {
+        if self.frame_count() == expected {
+            return Ok(());
+        }
+
+        Err(harness_erro(format!(
+            "o Core recebeu {} frames, mas eram esperados {}",
+            self.frame_count(),
+            expected
+        )))
+    }
+
+    pub fn check_finished(&self) -> Result<(), ErroHandle> {
+        if self.is_finished() {
+            return Ok(());
+        }
+
+        Err(harness_erro(format!(
+            "o script termina no frame {}, mas o Core recebeu somente {} frames",
+            self.script.last_frame(),
+            self.frame_count()
+        )))
+    }
+}
+
+fn retro_from_name(name: &str) -> Option<u32> {
+    let retro = match name.to_lowercase().as_str() {
+        "b" => RETRO_DEVICE_ID_JOYPAD_B,
+        "y" => RETRO_DEVICE_ID_JOYPAD_Y,
+        "select" => RETRO_DEVICE_ID_JOYPAD_SELECT,
+        "start" => RETRO_DEVICE_ID_JOYPAD_START,
+        "up" => RETRO_DEVICE_ID_JOYPAD_UP,
+        "down" => RETRO_DEVICE_ID_JOYPAD_DOWN,
+        "left" => RETRO_DEVICE_ID_JOYPAD_LEFT,
+        "right" => RETRO_DEVICE_ID_JOYPAD_RIGHT,
+        "a" => RETRO_DEVICE_ID_JOYPAD_A,
+        "x" => RETRO_DEVICE_ID_JOYPAD_X,
+        "l" => RETRO_DEVICE_ID_JOYPAD_L,
+        "r" => RETRO_DEVICE_ID_JOYPAD_R,
+        "l2" => RETRO_DEVICE_ID_JOYPAD_L2,
+        "r2" => RETRO_DEVICE_ID_JOYPAD_R2,
+        "l3" => RETRO_DEVICE_ID_JOYPAD_L3,
+        "r3" => RETRO_DEVICE_ID_JOYPAD_R3,
+        _ => return None,
+    };
+
+    Some(retro)
+}
+
+fn script_erro(line: usize, message: &str) -> ErroHandle {
+    harness_erro(format!("linha {}: {}", line + 1, message))
+}
+
+fn harness_erro(message: String) -> ErroHandle {
+    ErroHandle {
+        level: retro_log_level::RETRO_LOG_ERROR,
+        message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use retro_ab::retro_sys::RETRO_DEVICE_ID_JOYPAD_MASK;
+
+    #[test]
+    fn parses_text_scripts() {
+        let text = "
+            # passa pelo menu
+            120 press START
+            125 release start 0   # solta
+            10 press a 1
+        ";
+        let parsed = InputScript::parse(text).unwrap();
+        let built = InputScript::new()
+            .tap(120, 0, RETRO_DEVICE_ID_JOYPAD_START, 5)
+            .unwrap()
+            .press(10, 1, RETRO_DEVICE_ID_JOYPAD_A)
+            .unwrap();
+
+        assert_eq!(parsed, built);
+        assert_eq!(parsed.last_frame(), 125);
+        assert_eq!(parsed.ports(), vec![0, 1]);
+        assert_eq!(InputScript::parse("").unwrap(), InputScript::new());
+    }
+
+    #[test]
+    fn rejects_invalid_lines() {
+        let lines = [
+            "120 press",
+            "120 press start 0 1",
+            "x press start",
+            "-1 press start",
+            "120 hold start",
+            "120 press turbo",
+            "120 press start p1",
+        ];
+
+        for line in lines {
+            assert!(InputScript::parse(line).is_err(), "{line}");
+        }
+
+        let erro = InputScript::parse("1 press a\n\n2 hold a").unwrap_err();
+        assert!(erro.message.starts_with("linha 3:"));
+    }
+
+    #[test]
+    fn input_follows_the_events_up_to_the_frame() {
+        let script = InputScript::new()
+            .release(30, 0, RETRO_DEVICE_ID_JOYPAD_B)
+            .unwrap()
+            .press(10, 0, RETRO_DEVICE_ID_JOYPAD_B)
+            .unwrap()
+            .press(20, 0, RETRO_DEVICE_ID_JOYPAD_R3)
+            .unwrap();
+        let b = 1 << RETRO_DEVICE_ID_JOYPAD_B;
+        let r3 = 1 << RETRO_DEVICE_ID_JOYPAD_R3;
+
+        assert_eq!(script.get_port_input(0, 9).bitmask, 0);
+        assert_eq!(script.get_port_input(0, 10).bitmask, b);
+        assert_eq!(script.get_port_input(0, 20).bitmask, b | r3);
+        assert_eq!(script.get_port_input(0, 30).bitmask, r3);
+        assert_eq!(script.get_port_input(1, 30).bitmask, 0);
+        assert!(script.press(0, 0, 16).is_err());
+    }
+
+    #[test]
+    fn drives_a_manager_without_gilrs() {
+        let mut manager = DevicesManager::new(None);
+        let start = 1 << RETRO_DEVICE_ID_JOYPAD_START;
+        let script = InputScript::parse("1 press start 1\n2 release start 1").unwrap();
+        let harness = TestHarness::attach_to_manager(&manager, script);
+
+        assert!(harness.check_finished().is_err());
+
+        let mut received = Vec::new();
+
+        for _ in 0..3 {
+            manager.update_frame_state();
+            received.push(manager.get_input_state(1, RETRO_DEVICE_ID_JOYPAD_MASK as i16));
+        }
+
+        assert_eq!(received, vec![0, start, 0]);
+        assert!(harness.check_frame_count(3).is_ok());
+        assert!(harness.check_finished().is_ok());
+
+        let erro = harness.check_frame_count(4).unwrap_err();
+        assert!(erro.message.contains("3 frames"));
+
+        harness.detach_from_manager(&manager);
+        assert!(manager.detach_input_provider(1).is_none());
+    }
+}
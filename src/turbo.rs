@@ -0,0 +1,337 @@
+use crate::{
+    input_frame::InputFrame,
+    middleware::{swapped_port, InputMiddleware, MiddlewareContext, TURBO_STAGE},
+};
+use gilrs::Button;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TurboButton {
+    pub port: i16,
+    #[doc = "botão RETRO_DEVICE_ID_JOYPAD_* que terá o turbo"]
+    pub retro: u32,
+    #[doc = "quantidade de frames em que o botão fica pressionado a cada pulso"]
+    pub frames_on: u32,
+    #[doc = "quantidade de frames em que o botão fica solto a cada pulso"]
+    pub frames_off: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TurboMode {
+    #[doc = "o turbo esta sempre ativo nos botões configurados"]
+    Always,
+    #[doc = "o turbo começa desligado e o botão informado liga ou desliga o turbo da porta do controle que o pressionou"]
+    Toggle(Button),
+}
+
+#[derive(Debug, Clone)]
+pub struct Turbo {
+    mode: TurboMode,
+    buttons: Vec<TurboButton>,
+    held_frames: HashMap<(i16, u32), u32>,
+    enabled_ports: Vec<i16>,
+    hotkey_pressed: Vec<i16>,
+}
+
+impl Turbo {
+    pub fn new() -> Self {
+        Self {
+            mode: TurboMode::Always,
+            buttons: Vec::new(),
+            held_frames: HashMap::new(),
+            enabled_ports: Vec::new(),
+            hotkey_pressed: Vec::new(),
+        }
+    }
+
+    pub fn set_mode(&mut self, mode: TurboMode) {
+        self.mode = mode;
+        self.enabled_ports.clear();
+        self.hotkey_pressed.clear();
+    }
+
+    pub fn set_button(&mut self, button: TurboButton) {
+        self.remove_button(button.port, button.retro);
+        self.buttons.push(button);
+    }
+
+    pub fn remove_button(&mut self, port: i16, retro: u32) {
+        self.buttons
+            .retain(|button| button.port != port || button.retro != retro);
+        self.held_frames.remove(&(port, retro));
+    }
+
+    #[doc = "os botões e o estado do turbo das duas portas são trocados, para acompanhar os jogadores"]
+    pub fn swap_ports(&mut self, port: i16, other: i16) {
+        for button in &mut self.buttons {
+            button.port = swapped_port(button.port, port, other);
+        }
+
+        self.held_frames = self
+            .held_frames
+            .drain()
+            .map(|((p, retro), held)| ((swapped_port(p, port, other), retro), held))
+            .collect();
+
+        for p in self
+            .enabled_ports
+            .iter_mut()
+            .chain(&mut self.hotkey_pressed)
+        {
+            *p = swapped_port(*p, port, other);
+        }
+    }
+
+    pub fn is_enabled(&self, port: i16) -> bool {
+        match self.mode {
+            TurboMode::Always => true,
+            TurboMode::Toggle(_) => self.enabled_ports.contains(&port),
+        }
+    }
+
+    fn update_toggle(&mut self, context: &MiddlewareContext) {
+        let TurboMode::Toggle(hotkey) = self.mode else {
+            return;
+        };
+
+        for gamepad in context
+            .gamepads
+            .iter()
+            .filter(|gamepad| gamepad.retro_port >= 0)
+        {
+            let port = gamepad.retro_port;
+            let pressed = gamepad.is_native_pressed(hotkey);
+            let was_pressed = self.hotkey_pressed.contains(&port);
+
+            if pressed && !was_pressed {
+                if self.enabled_ports.contains(&port) {
+                    self.enabled_ports.retain(|p| *p != port);
+                } else {
+                    self.enabled_ports.push(port);
+                }
+                self.hotkey_pressed.push(port);
+            } else if !pressed && was_pressed {
+                self.hotkey_pressed.retain(|p| *p != port);
+            }
+        }
+    }
+
+    //botões RETRO_DEVICE_ID_JOYPAD_* mapeados para o hotkey do toggle em cada gamepad que está
+    //pressionando ele, o hotkey não deve chegar ao Core mas os outros devices da porta continuam
+    //podendo usar esses botões
+    fn toggle_masks(&self, context: &MiddlewareContext) -> Vec<(i16, i16)> {
+        let TurboMode::Toggle(hotkey) = self.mode else {
+            return Vec::new();
+        };
+
+        context
+            .gamepads
+            .iter()
+            .filter(|gamepad| gamepad.retro_port >= 0 && gamepad.is_native_pressed(hotkey))
+            .map(|gamepad| {
+                let mask = gamepad
+                    .key_map
+                    .iter()
+                    .filter(|key_map| key_map.native == hotkey)
+                    .filter_map(|key_map| 1i16.checked_shl(key_map.retro))
+                    .fold(0, |mask, bit| mask | bit);
+
+                (gamepad.retro_port, mask & !context.bitmask_without(gamepad))
+            })
+            .collect()
+    }
+
+    #[doc = "remove do bitmask os botões com turbo que estão na fase 'solto' do pulso"]
+    pub fn apply(&self, port: i16, bitmask: i16) -> i16 {
+        if !self.is_enabled(port) {
+            return bitmask;
+        }
+
+        let mut bitmask = bitmask;
+
+        for button in self.buttons.iter().filter(|button| button.port == port) {
+            let held = *self.held_frames.get(&(port, button.retro)).unwrap_or(&0);
+            let period = button.frames_on + button.frames_off;
+
+            if held == 0 || period == 0 {
+                continue;
+            }
+
+            if (held - 1) % period >= button.frames_on {
+                if let Some(mask) = 1i16.checked_shl(button.retro) {
+                    bitmask &= !mask;
+                }
+            }
+        }
+
+        bitmask
+    }
+}
+
+impl InputMiddleware for Turbo {
+    fn name(&self) -> &str {
+        TURBO_STAGE
+    }
+
+    #[doc = "é aqui que os frames de cada pulso são contados, usando o input que chegou a esse estágio"]
+    fn process(&mut self, context: &MiddlewareContext, frame: &mut InputFrame) {
+        self.update_toggle(context);
+
+        for (port, mask) in self.toggle_masks(context) {
+            if let Some(input) = frame.ports.get_mut(port as usize) {
+                input.bitmask &= !mask;
+            }
+        }
+
+        for button in &self.buttons {
+            if !context.is_port_enabled(button.port) {
+                continue;
+            }
+
+            let held = frame
+                .port(button.port)
+                .is_some_and(|input| input.get_key_pressed(button.retro as i16) == 1);
+
+            let frames = self
+                .held_frames
+                .entry((button.port, button.retro))
+                .or_insert(0);
+
+            *frames = if held { *frames + 1 } else { 0 };
+        }
+
+        for (port, input) in frame.ports.iter_mut().enumerate() {
+            if context.is_port_enabled(port as i16) {
+                input.bitmask = self.apply(port as i16, input.bitmask);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        devices_manager::DevicesRequireFunctions, gamepad::retro_gamepad::RetroGamePad,
+        input_frame::PortInput,
+    };
+    use retro_ab::retro_sys::{RETRO_DEVICE_ID_JOYPAD_B, RETRO_DEVICE_ID_JOYPAD_R3};
+
+    //'bitmask' é o que os gamepads virtuais e os InputProvider colocaram na porta 0
+    fn run(turbo: &mut Turbo, gamepads: &[RetroGamePad], bitmask: i16) -> i16 {
+        let mut frame = InputFrame {
+            ports: vec![PortInput::default(); 2],
+            keyboard: Vec::new(),
+        };
+        frame.ports[0].bitmask = bitmask;
+
+        for gamepad in gamepads.iter().filter(|gamepad| gamepad.retro_port >= 0) {
+            frame.ports[gamepad.retro_port as usize].bitmask |= gamepad.get_key_bitmask();
+        }
+
+        let context = MiddlewareContext {
+            frame_count: 0,
+            gamepads,
+            gilrs: None,
+            disabled_ports: &[],
+            shared_bitmasks: &[bitmask],
+        };
+        turbo.process(&context, &mut frame);
+
+        frame.ports[0].bitmask
+    }
+
+    #[test]
+    fn pulses_while_the_button_is_held() {
+        let b = 1 << RETRO_DEVICE_ID_JOYPAD_B;
+        let mut turbo = Turbo::new();
+        turbo.set_button(TurboButton {
+            port: 0,
+            retro: RETRO_DEVICE_ID_JOYPAD_B,
+            frames_on: 2,
+            frames_off: 1,
+        });
+
+        let pulses: Vec<i16> = (0..6).map(|_| run(&mut turbo, &[], b)).collect();
+        assert_eq!(pulses, vec![b, b, 0, b, b, 0]);
+
+        //soltar o botão reinicia o pulso
+        assert_eq!(run(&mut turbo, &[], 0), 0);
+        assert_eq!(run(&mut turbo, &[], b), b);
+
+        turbo.remove_button(0, RETRO_DEVICE_ID_JOYPAD_B);
+        let held: Vec<i16> = (0..3).map(|_| run(&mut turbo, &[], b)).collect();
+        assert_eq!(held, vec![b; 3]);
+    }
+
+    #[test]
+    fn hotkey_toggles_the_turbo_of_its_port() {
+        let b = 1 << RETRO_DEVICE_ID_JOYPAD_B;
+        let mut turbo = Turbo::new();
+        turbo.set_mode(TurboMode::Toggle(Button::RightThumb));
+        turbo.set_button(TurboButton {
+            port: 0,
+            retro: RETRO_DEVICE_ID_JOYPAD_B,
+            frames_on: 1,
+            frames_off: 1,
+        });
+
+        let mut gamepads = vec![
+            RetroGamePad::test_gamepad(0, 0),
+            RetroGamePad::test_gamepad(1, -1),
+        ];
+        assert!(!turbo.is_enabled(0));
+
+        //um gamepad sem porta não liga o turbo de nenhuma porta
+        gamepads[1].test_press(Button::RightThumb, true);
+        run(&mut turbo, &gamepads, 0);
+        assert!(!turbo.is_enabled(-1));
+        assert!(!turbo.is_enabled(0));
+
+        //o hotkey está mapeado para o R3, mas não chega ao Core
+        gamepads[0].test_press(Button::RightThumb, true);
+        assert_eq!(run(&mut turbo, &gamepads, b), b);
+        assert!(turbo.is_enabled(0));
+        assert_eq!(run(&mut turbo, &gamepads, b), 0);
+        assert_eq!(
+            gamepads[0].get_key_bitmask(),
+            1 << RETRO_DEVICE_ID_JOYPAD_R3
+        );
+
+        //soltar o hotkey não desliga o turbo
+        gamepads[0].test_press(Button::RightThumb, false);
+        assert_eq!(run(&mut turbo, &gamepads, b), b);
+        assert!(turbo.is_enabled(0));
+
+        gamepads[0].test_press(Button::RightThumb, true);
+        run(&mut turbo, &gamepads, 0);
+        assert!(!turbo.is_enabled(0));
+        assert_eq!(run(&mut turbo, &gamepads, b), b);
+        assert_eq!(run(&mut turbo, &gamepads, b), b);
+    }
+
+    #[test]
+    fn hotkey_bit_held_by_another_device_of_the_port_reaches_the_core() {
+        let r3 = 1 << RETRO_DEVICE_ID_JOYPAD_R3;
+        let mut turbo = Turbo::new();
+        turbo.set_mode(TurboMode::Toggle(Button::RightThumb));
+
+        let mut gamepads = vec![
+            RetroGamePad::test_gamepad(0, 0),
+            RetroGamePad::test_gamepad(1, 0),
+        ];
+        gamepads[0].test_press(Button::RightThumb, true);
+        assert_eq!(run(&mut turbo, &gamepads, 0), 0);
+        assert_eq!(run(&mut turbo, &gamepads, r3), r3);
+
+        //o R3 do segundo gamepad vem de outro botão, então ele não liga nem desliga o turbo
+        for key_map in &mut gamepads[1].key_map {
+            if key_map.native == Button::South {
+                key_map.retro = RETRO_DEVICE_ID_JOYPAD_R3;
+            }
+        }
+        gamepads[1].test_press(Button::South, true);
+        assert_eq!(run(&mut turbo, &gamepads, 0), r3);
+        assert!(turbo.is_enabled(0));
+    }
+}